@@ -0,0 +1,21 @@
+//! The parser must never panic, whatever the input.
+//!
+//! ```sh
+//! cargo +nightly fuzz run parse
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modelfile::modelfile::Modelfile;
+
+fuzz_target!(|input: &str| {
+    if let Err(error) = input.parse::<Modelfile>() {
+        // rendering the diagnostic slices the source line
+        let _ = error.to_string();
+    }
+
+    for error in Modelfile::parse_recovering(input).errors {
+        let _ = error.to_string();
+    }
+});
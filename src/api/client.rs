@@ -0,0 +1,561 @@
+//! An async client for the [Ollama API],
+//! enabled with the `client` feature.
+//!
+//! [Ollama API]: https://github.com/ollama/ollama/blob/main/docs/api.md
+
+use futures_util::{stream, Stream, StreamExt as _};
+use reqwest::{
+    header::CONTENT_LENGTH, Body, IntoUrl, Method, RequestBuilder, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use thiserror::Error;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    blob::{BlobError, BlobTransport, LocalBlob},
+    digest::Digest,
+    modelfile::{error::ModelfileError, Modelfile},
+};
+
+use super::{CreateRequest, ListResponse, ListedModel, ProgressResponse, ShowResponse};
+
+/// Where Ollama listens by default.
+pub const DEFAULT_URL: &str = "http://127.0.0.1:11434";
+
+/// Errors from talking to Ollama.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("request to Ollama failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// Ollama answered with an error status.
+    #[error("Ollama responded with {status}: {message}")]
+    Status { status: u16, message: String },
+    /// Ollama reported an error in the middle of a stream.
+    #[error("Ollama reported an error: {0}")]
+    Stream(String),
+    #[error("unexpected response from Ollama: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Modelfile(#[from] ModelfileError),
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+}
+
+/// An async client for a running Ollama server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    /// Without a trailing `/`.
+    base_url: String,
+}
+
+impl Default for Client {
+    /// A client for Ollama at [`DEFAULT_URL`].
+    fn default() -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: DEFAULT_URL.to_string(),
+        }
+    }
+}
+
+impl Client {
+    /// A client for Ollama at `base_url`, like `http://localhost:11434`.
+    pub fn new(base_url: impl IntoUrl) -> Result<Self, ClientError> {
+        Ok(Client {
+            http: reqwest::Client::new(),
+            base_url: base_url
+                .into_url()?
+                .as_str()
+                .trim_end_matches('/')
+                .to_string(),
+        })
+    }
+
+    /// The parsed [`Modelfile`] of an installed model.
+    pub async fn show(&self, model: &str) -> Result<Modelfile, ClientError> {
+        Ok(self.show_response(model).await?.modelfile)
+    }
+
+    /// Everything Ollama knows about an installed model.
+    pub async fn show_response(&self, model: &str) -> Result<ShowResponse, ClientError> {
+        let response = self
+            .send(
+                self.request(Method::POST, "api/show")
+                    .json(&json!({ "model": model })),
+            )
+            .await?;
+
+        Ok(response.json().await?)
+    }
+
+    /// Create a model called `name` from `modelfile`,
+    /// streaming Ollama's progress.
+    ///
+    /// Local files in the Modelfile have to be uploaded as blobs first,
+    /// see [`Modelfile::to_create_request`].
+    pub async fn create(
+        &self,
+        name: &str,
+        modelfile: &Modelfile,
+    ) -> Result<impl Stream<Item = Result<ProgressResponse, ClientError>>, ClientError> {
+        self.create_from(modelfile.to_create_request(name)?).await
+    }
+
+    /// Send a create request,
+    /// like one from [`BlobUpload::to_create_request`](crate::blob::BlobUpload::to_create_request),
+    /// streaming Ollama's progress.
+    pub async fn create_from(
+        &self,
+        request: CreateRequest,
+    ) -> Result<impl Stream<Item = Result<ProgressResponse, ClientError>>, ClientError> {
+        let request = CreateRequest {
+            stream: Some(true),
+            ..request
+        };
+
+        let response = self
+            .send(self.request(Method::POST, "api/create").json(&request))
+            .await?;
+
+        Ok(json_lines(response))
+    }
+
+    /// Pull a model from its registry,
+    /// streaming Ollama's progress.
+    pub async fn pull(
+        &self,
+        model: &str,
+    ) -> Result<impl Stream<Item = Result<ProgressResponse, ClientError>>, ClientError> {
+        let response = self
+            .send(
+                self.request(Method::POST, "api/pull")
+                    .json(&json!({ "model": model, "stream": true })),
+            )
+            .await?;
+
+        Ok(json_lines(response))
+    }
+
+    /// Copy the model `source` to a new name, `destination`.
+    pub async fn copy(&self, source: &str, destination: &str) -> Result<(), ClientError> {
+        self.send(
+            self.request(Method::POST, "api/copy")
+                .json(&json!({ "source": source, "destination": destination })),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete an installed model.
+    pub async fn delete(&self, model: &str) -> Result<(), ClientError> {
+        self.send(
+            self.request(Method::DELETE, "api/delete")
+                .json(&json!({ "model": model })),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// The installed models.
+    pub async fn list(&self) -> Result<Vec<ListedModel>, ClientError> {
+        let response = self.send(self.request(Method::GET, "api/tags")).await?;
+        let list: ListResponse = response.json().await?;
+
+        Ok(list.models)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/{path}", self.base_url))
+    }
+
+    /// Send the request and turn an error status into [`ClientError::Status`].
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await?;
+        let message = match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(ErrorResponse { error }) => error,
+            Err(_) => body,
+        };
+
+        Err(ClientError::Status {
+            status: status.as_u16(),
+            message,
+        })
+    }
+}
+
+/// Blobs are checked and uploaded through `/api/blobs/:digest`.
+impl BlobTransport for Client {
+    type Error = ClientError;
+
+    async fn exists(&self, digest: &Digest) -> Result<bool, ClientError> {
+        let response = self
+            .request(Method::HEAD, &format!("api/blobs/{digest}"))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(ClientError::Status {
+                status: status.as_u16(),
+                message: format!("couldn't check blob {digest}"),
+            }),
+        }
+    }
+
+    async fn upload(&self, blob: &LocalBlob) -> Result<(), ClientError> {
+        let file = tokio::fs::File::open(&blob.path)
+            .await
+            .map_err(|source| BlobError::Io {
+                path: blob.path.clone(),
+                source,
+            })?;
+
+        self.send(
+            self.request(Method::POST, &format!("api/blobs/{}", blob.digest))
+                .header(CONTENT_LENGTH, blob.size)
+                .body(Body::wrap_stream(ReaderStream::new(file))),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// The body Ollama sends with an error.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// A line of a streamed response,
+/// either an event or an error.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StreamLine<T> {
+    Error(ErrorResponse),
+    Event(T),
+}
+
+/// Parse a response of newline delimited JSON.
+fn json_lines<T: DeserializeOwned>(
+    response: Response,
+) -> impl Stream<Item = Result<T, ClientError>> {
+    let chunks = response.bytes_stream().boxed();
+
+    stream::unfold(
+        (chunks, Vec::new()),
+        |(mut chunks, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    if line.trim_ascii().is_empty() {
+                        continue;
+                    }
+                    return Some((parse_line(&line), (chunks, buffer)));
+                }
+
+                match chunks.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(error)) => return Some((Err(error.into()), (chunks, buffer))),
+                    // the last line may not end with a newline
+                    None if !buffer.trim_ascii().is_empty() => {
+                        let line = std::mem::take(&mut buffer);
+                        return Some((parse_line(&line), (chunks, buffer)));
+                    }
+                    None => return None,
+                }
+            }
+        },
+    )
+}
+
+fn parse_line<T: DeserializeOwned>(line: &[u8]) -> Result<T, ClientError> {
+    match serde_json::from_slice(line)? {
+        StreamLine::Event(event) => Ok(event),
+        StreamLine::Error(ErrorResponse { error }) => Err(ClientError::Stream(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt as _;
+    use insta::assert_debug_snapshot;
+    use wiremock::{
+        matchers::{body_json, body_string, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::modelfile::test_data::{load_show_responses, TestData, TEST_GOOD_DATA_DIR};
+
+    use super::*;
+
+    async fn mock_server() -> (MockServer, Client) {
+        let server = MockServer::start().await;
+        let client = Client::new(server.uri()).expect("mock server URI should be valid");
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn show_parses_fixtures() {
+        let (server, client) = mock_server().await;
+
+        let responses = load_show_responses(TEST_GOOD_DATA_DIR);
+        assert!(!responses.is_empty());
+
+        for TestData {
+            path: file,
+            contents,
+        } in responses
+        {
+            let model = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .expect("fixture should have a file name");
+            Mock::given(method("POST"))
+                .and(path("/api/show"))
+                .and(body_json(json!({ "model": model })))
+                .respond_with(ResponseTemplate::new(200).set_body_string(contents.clone()))
+                .mount(&server)
+                .await;
+
+            let expected: ShowResponse =
+                serde_json::from_str(&contents).expect("should parse show response");
+            let modelfile = client.show(model).await.expect("should show model");
+
+            assert_eq!(modelfile, expected.modelfile);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_streams_progress() {
+        let (server, client) = mock_server().await;
+        let modelfile: Modelfile = "FROM llama3.2\nSYSTEM You are a pirate."
+            .parse()
+            .expect("should parse Modelfile");
+
+        let request = CreateRequest {
+            stream: Some(true),
+            ..modelfile
+                .to_create_request("pirate")
+                .expect("should create request")
+        };
+        Mock::given(method("POST"))
+            .and(path("/api/create"))
+            .and(body_json(&request))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "{\"status\":\"using existing layer sha256:966de95ca8a62200913e3f8bfbf84c8494536f1b94b49166851e76644e966396\"}\n\
+                {\"status\":\"writing manifest\"}\n\
+                {\"status\":\"success\"}\n",
+            ))
+            .mount(&server)
+            .await;
+
+        let events: Vec<ProgressResponse> = client
+            .create("pirate", &modelfile)
+            .await
+            .expect("should start creating model")
+            .try_collect()
+            .await
+            .expect("should stream progress");
+
+        assert_debug_snapshot!(events, @r#"
+        [
+            ProgressResponse {
+                status: "using existing layer sha256:966de95ca8a62200913e3f8bfbf84c8494536f1b94b49166851e76644e966396",
+                digest: None,
+                total: None,
+                completed: None,
+            },
+            ProgressResponse {
+                status: "writing manifest",
+                digest: None,
+                total: None,
+                completed: None,
+            },
+            ProgressResponse {
+                status: "success",
+                digest: None,
+                total: None,
+                completed: None,
+            },
+        ]
+        "#);
+    }
+
+    #[tokio::test]
+    async fn errors_are_reported() {
+        let (server, client) = mock_server().await;
+        Mock::given(method("POST"))
+            .and(path("/api/pull"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "{\"status\":\"pulling manifest\"}\n{\"error\":\"pull model manifest: file does not exist\"}",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/delete"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .set_body_string("{\"error\":\"model 'nope' not found\"}"),
+            )
+            .mount(&server)
+            .await;
+
+        let events: Vec<Result<ProgressResponse, ClientError>> = client
+            .pull("nope")
+            .await
+            .expect("should start pulling model")
+            .collect()
+            .await;
+        let delete = client.delete("nope").await;
+
+        assert_debug_snapshot!((events, delete), @r#"
+        (
+            [
+                Ok(
+                    ProgressResponse {
+                        status: "pulling manifest",
+                        digest: None,
+                        total: None,
+                        completed: None,
+                    },
+                ),
+                Err(
+                    Stream(
+                        "pull model manifest: file does not exist",
+                    ),
+                ),
+            ],
+            Err(
+                Status {
+                    status: 404,
+                    message: "model 'nope' not found",
+                },
+            ),
+        )
+        "#);
+    }
+
+    #[tokio::test]
+    async fn blobs_are_uploaded() {
+        let (server, client) = mock_server().await;
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        std::fs::write(dir.path().join("model.gguf"), "model").expect("should write model");
+        std::fs::write(dir.path().join("lora.gguf"), "lora").expect("should write adapter");
+
+        let modelfile: Modelfile = "FROM ./model.gguf\nADAPTER ./lora.gguf"
+            .parse()
+            .expect("should parse Modelfile");
+        let upload = modelfile
+            .prepare_blobs(dir.path())
+            .expect("should hash local files");
+        let [model, adapter] = [&upload.blobs[0].digest, &upload.blobs[1].digest];
+
+        Mock::given(method("HEAD"))
+            .and(path(format!("/api/blobs/{model}")))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path(format!("/api/blobs/{adapter}")))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/api/blobs/{adapter}")))
+            .and(body_string("lora"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uploaded = upload.upload(&client).await.expect("should upload blobs");
+
+        assert_eq!(uploaded, vec![adapter.clone()]);
+    }
+
+    #[tokio::test]
+    async fn copy_delete_and_list() {
+        let (server, client) = mock_server().await;
+        Mock::given(method("POST"))
+            .and(path("/api/copy"))
+            .and(body_json(
+                json!({ "source": "llama3.2", "destination": "pirate" }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/delete"))
+            .and(body_json(json!({ "model": "pirate" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [{
+                    "name": "llama3.2:latest",
+                    "model": "llama3.2:latest",
+                    "modified_at": "2024-11-02T12:00:00.000000000-04:00",
+                    "size": 2019393189,
+                    "digest": "a80c4f17acd55265feec403c7aef86be0c25983ab279d83f3bcd3abbcb5b8b72",
+                    "details": {
+                        "parent_model": "",
+                        "format": "gguf",
+                        "family": "llama",
+                        "families": ["llama"],
+                        "parameter_size": "3.2B",
+                        "quantization_level": "Q4_K_M"
+                    }
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        client
+            .copy("llama3.2", "pirate")
+            .await
+            .expect("should copy model");
+        client.delete("pirate").await.expect("should delete model");
+        let models = client.list().await.expect("should list models");
+
+        assert_debug_snapshot!(models, @r#"
+        [
+            ListedModel {
+                name: "llama3.2:latest",
+                model: "llama3.2:latest",
+                modified_at: Some(
+                    "2024-11-02T12:00:00.000000000-04:00",
+                ),
+                size: 2019393189,
+                digest: "a80c4f17acd55265feec403c7aef86be0c25983ab279d83f3bcd3abbcb5b8b72",
+                details: ModelDetails {
+                    parent_model: "",
+                    format: "gguf",
+                    family: "llama",
+                    families: [
+                        "llama",
+                    ],
+                    parameter_size: "3.2B",
+                    quantization_level: "Q4_K_M",
+                },
+            },
+        ]
+        "#);
+    }
+}
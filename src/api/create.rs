@@ -0,0 +1,532 @@
+//! The request body of [`/api/create`],
+//! which creates a model from structured Modelfile fields.
+//!
+//! [`/api/create`]: https://github.com/ollama/ollama/blob/main/docs/api.md#create-a-model
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value;
+
+use crate::{
+    digest::Digest,
+    message::{ChatMessage, Message},
+    modelfile::{
+        base_model::{BaseModel, ModelSource},
+        builder::ModelfileBuilder,
+        error::ModelfileError,
+        instruction::Parameters,
+        Modelfile, Multiline, Parameter, TensorFile,
+    },
+};
+
+/// The body of a `/api/create` request.
+///
+/// Local files can't be sent in a request,
+/// so `FROM` and `ADAPTER` either name a model
+/// or refer to blobs already uploaded to Ollama by digest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CreateRequest {
+    /// The name of the model to create.
+    pub model: String,
+    /// The model to build on, like `llama3.2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Model files to build from, by file name.
+    /// The first file is the base model,
+    /// the others are more layers, like a projector.
+    #[serde(default, skip_serializing_if = "Files::is_empty")]
+    pub files: Files,
+    /// Adapter files, by file name, in the order they are applied.
+    #[serde(default, skip_serializing_if = "Files::is_empty")]
+    pub adapters: Files,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Ollama accepts a single license or a list.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub license: Vec<String>,
+    /// Parameter values by name.
+    /// Parameters that can be repeated, like `stop`, are lists.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parameters: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<ChatMessage>,
+    /// Quantize the model while creating it, like `q4_K_M`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantize: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+}
+
+impl CreateRequest {
+    /// Send assistant `messages` that are written like tool calls in a Modelfile,
+    /// a `{"tool_calls": [...]}` object, as tool calls.
+    ///
+    /// `MESSAGE` content is kept as text otherwise,
+    /// see [`ChatMessage::read_tool_calls`].
+    pub fn read_tool_calls(mut self) -> Self {
+        self.messages = self
+            .messages
+            .into_iter()
+            .map(ChatMessage::read_tool_calls)
+            .collect();
+        self
+    }
+}
+
+/// Files by name, in the order they were added.
+///
+/// Written as a JSON object like Ollama expects,
+/// but unlike a map the order of the files is kept,
+/// since it decides which file is the base model
+/// and the order stacked adapters are applied in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Files(Vec<(String, Digest)>);
+
+impl Files {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Add a file at the end,
+    /// or replace the digest of the file with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, digest: Digest) {
+        let name = name.into();
+        match self.0.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = digest,
+            None => self.0.push((name, digest)),
+        }
+    }
+
+    /// The digest of the file called `name`.
+    pub fn get(&self, name: &str) -> Option<&Digest> {
+        self.iter()
+            .find_map(|(existing, digest)| (existing == name).then_some(digest))
+    }
+
+    /// The files in order, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Digest)> {
+        self.0.iter().map(|(name, digest)| (name.as_str(), digest))
+    }
+
+    /// The digests of the files in order.
+    pub fn into_digests(self) -> impl Iterator<Item = Digest> {
+        self.0.into_iter().map(|(_, digest)| digest)
+    }
+}
+
+impl<N: Into<String>> FromIterator<(N, Digest)> for Files {
+    fn from_iter<T: IntoIterator<Item = (N, Digest)>>(iter: T) -> Self {
+        let mut files = Files::default();
+        for (name, digest) in iter {
+            files.insert(name, digest);
+        }
+        files
+    }
+}
+
+impl Serialize for Files {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Files {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FilesVisitor;
+
+        impl<'de> Visitor<'de> for FilesVisitor {
+            type Value = Files;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of file names to digests")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Files, A::Error> {
+                let mut files = Files::default();
+                while let Some((name, digest)) = map.next_entry::<String, Digest>()? {
+                    files.insert(name, digest);
+                }
+                Ok(files)
+            }
+        }
+
+        deserializer.deserialize_map(FilesVisitor)
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+impl Modelfile {
+    /// The body of a `/api/create` request
+    /// that creates a model called `name` from this Modelfile.
+    ///
+    /// Fails with [`ModelfileError::LocalFile`]
+    /// if `FROM` or `ADAPTER` is a local path,
+    /// since those have to be uploaded as blobs first.
+    ///
+    /// Blobs are named after their digest, like `sha256-<hex>`.
+    /// Use [`BlobUpload::to_create_request`](crate::blob::BlobUpload::to_create_request)
+    /// to keep the names of uploaded local files.
+    ///
+    /// A create request has no `REQUIRES`,
+    /// check it against the server with [`Modelfile::check_version`] first.
+    pub fn to_create_request(
+        &self,
+        name: impl Into<String>,
+    ) -> Result<CreateRequest, ModelfileError> {
+        self.create_request(name.into(), Digest::blob_file_name)
+    }
+
+    /// See [`Modelfile::to_create_request`],
+    /// with the `files` and `adapters` named by `file_name`.
+    pub(crate) fn create_request(
+        &self,
+        name: String,
+        file_name: impl Fn(&Digest) -> String,
+    ) -> Result<CreateRequest, ModelfileError> {
+        let mut request = CreateRequest {
+            model: name,
+            template: self.template.as_deref().map(text),
+            system: self.system.as_deref().map(text),
+            license: self.license.as_deref().map(text).into_iter().collect(),
+            parameters: parameters_to_json(&self.parameters),
+            messages: self.messages.iter().map(ChatMessage::from).collect(),
+            ..Default::default()
+        };
+
+        match self.from.source() {
+            ModelSource::Reference(_) => request.from = Some(self.from.to_string()),
+            ModelSource::Blob(digest) => {
+                request.files.insert(file_name(digest), digest.clone());
+            }
+            ModelSource::Gguf(path)
+            | ModelSource::Safetensor(path)
+            | ModelSource::Directory(path) => {
+                return Err(ModelfileError::LocalFile(path.clone()));
+            }
+        }
+
+        for layer in &self.layers {
+            match layer.source() {
+                ModelSource::Blob(digest) => {
+                    request.files.insert(file_name(digest), digest.clone());
+                }
+                ModelSource::Reference(reference) => {
+                    return Err(ModelfileError::Builder(format!(
+                        "a create request can only build FROM one model, not also {reference}"
+                    )));
+                }
+                ModelSource::Gguf(path)
+                | ModelSource::Safetensor(path)
+                | ModelSource::Directory(path) => {
+                    return Err(ModelfileError::LocalFile(path.clone()));
+                }
+            }
+        }
+
+        for adapter in &self.adapters {
+            match &**adapter {
+                TensorFile::Blob(digest) => {
+                    request.adapters.insert(file_name(digest), digest.clone());
+                }
+                TensorFile::Gguf(path)
+                | TensorFile::Safetensor(path)
+                | TensorFile::Directory(path) => {
+                    return Err(ModelfileError::LocalFile(path.clone()));
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+impl TryFrom<CreateRequest> for Modelfile {
+    type Error = ModelfileError;
+
+    /// Build the [`Modelfile`] a `/api/create` request describes.
+    ///
+    /// Files are written as `@sha256:<hex>` blob references.
+    /// Without `from`, the first file is the base model,
+    /// and the other files are more `FROM` layers, like a projector.
+    /// Adapters are applied in the order they are listed.
+    fn try_from(request: CreateRequest) -> Result<Self, Self::Error> {
+        let mut files = request.files.into_digests().map(BaseModel::from);
+        let from = match request.from {
+            Some(from) => from.parse()?,
+            None => files.next().ok_or_else(|| {
+                ModelfileError::Builder("create request needs `from` or `files`".into())
+            })?,
+        };
+
+        ModelfileBuilder {
+            from: Some(from),
+            layers: files.collect(),
+            requires: None,
+            parameters: parameters_from_json(&request.parameters)?,
+            template: request.template.map(Into::into),
+            system: request.system.map(Into::into),
+            adapters: request
+                .adapters
+                .into_digests()
+                .map(|digest| TensorFile::Blob(digest).into())
+                .collect(),
+            license: (!request.license.is_empty()).then(|| request.license.join("\n").into()),
+            messages: messages_from_chat(request.messages)?.into(),
+        }
+        .build()
+    }
+}
+
+/// The text of a multiline value, without the `"""` quotes.
+fn text(multiline: &Multiline) -> String {
+    AsRef::<str>::as_ref(multiline).to_string()
+}
+
+fn parameters_to_json(parameters: &Parameters) -> BTreeMap<String, Value> {
+    let mut json = BTreeMap::new();
+
+    for parameter in parameters.iter() {
+        match parameter {
+            Parameter::Stop(stop) => {
+                let stops = json
+                    .entry(parameter.name().to_string())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(stops) = stops {
+                    stops.push(Value::String(stop.clone()));
+                }
+            }
+            Parameter::Other { name, value } => {
+                let value = serde_json::from_str(value).unwrap_or(Value::String(value.clone()));
+                json.insert(name.clone(), value);
+            }
+            _ => {
+                // numbers and booleans are written the same way in JSON
+                let value = parameter.value();
+                let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+                json.insert(parameter.name().to_string(), value);
+            }
+        }
+    }
+
+    json
+}
+
+pub(crate) fn parameters_from_json(
+    json: &BTreeMap<String, Value>,
+) -> Result<Parameters, ModelfileError> {
+    let line = |name: &str, value: &Value| match value {
+        Value::String(text) if text.is_empty() || text.trim() != text => {
+            format!("{name} \"{text}\"")
+        }
+        Value::String(text) => format!("{name} {text}"),
+        value => format!("{name} {value}"),
+    };
+
+    let lines: Vec<String> = json
+        .iter()
+        .flat_map(|(name, value)| match value {
+            Value::Array(values) => values.iter().map(|value| line(name, value)).collect(),
+            value => vec![line(name, value)],
+        })
+        .collect();
+
+    Parameters::from_lines(&lines.join("\n"))
+}
+
+pub(crate) fn messages_from_chat(
+    messages: Vec<ChatMessage>,
+) -> Result<Vec<Message>, ModelfileError> {
+    messages
+        .into_iter()
+        .map(|message| {
+            let role = message.role.clone();
+            Message::try_from(message)
+                .map_err(|_| ModelfileError::Builder(format!("unknown message role: {role}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+
+    use crate::modelfile::test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR};
+
+    use super::*;
+
+    #[test]
+    fn create_request_snapshot() {
+        let modelfile: Modelfile = "FROM llama3.2\n\
+            ADAPTER @sha256:6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa\n\
+            SYSTEM You are a pirate.\n\
+            PARAMETER temperature 0.7\n\
+            PARAMETER num_ctx 8192\n\
+            PARAMETER stop <|start_header_id|>\n\
+            PARAMETER stop <|eot_id|>\n\
+            PARAMETER penalize_newline false\n\
+            MESSAGE user Ahoy?\n\
+            MESSAGE assistant Arr!\n"
+            .parse()
+            .expect("should parse Modelfile");
+
+        let request = modelfile
+            .to_create_request("pirate")
+            .expect("should create request");
+
+        assert_snapshot!(
+            serde_json::to_string_pretty(&request).expect("should serialize request"),
+            @r#"
+        {
+          "model": "pirate",
+          "from": "llama3.2",
+          "adapters": {
+            "sha256-6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa": "sha256:6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa"
+          },
+          "system": "You are a pirate.",
+          "parameters": {
+            "num_ctx": 8192,
+            "penalize_newline": false,
+            "stop": [
+              "<|start_header_id|>",
+              "<|eot_id|>"
+            ],
+            "temperature": 0.7
+          },
+          "messages": [
+            {
+              "role": "user",
+              "content": "Ahoy?"
+            },
+            {
+              "role": "assistant",
+              "content": "Arr!"
+            }
+          ]
+        }
+        "#
+        );
+
+        let round_trip = Modelfile::try_from(request.clone())
+            .expect("should build Modelfile from request")
+            .to_create_request("pirate")
+            .expect("should create request");
+        assert_eq!(round_trip, request);
+    }
+
+    /// Write blobs referred to by their store path as `@sha256:<hex>`,
+    /// like a Modelfile built from a create request.
+    fn blob_references(mut modelfile: Modelfile) -> Modelfile {
+        for model in std::iter::once(&mut modelfile.from).chain(&mut modelfile.layers) {
+            if let ModelSource::Blob(digest) = model.source() {
+                *model = digest.clone().into();
+            }
+        }
+        modelfile
+    }
+
+    #[test]
+    fn fixtures_round_trip_through_create_requests() {
+        for TestData { path, contents } in load_modelfiles(TEST_GOOD_DATA_DIR) {
+            dbg!(&path);
+            let modelfile: Modelfile = contents.parse().expect("should parse Modelfile");
+            let request = modelfile
+                .to_create_request("fixture")
+                .expect("should create request");
+
+            let json = serde_json::to_string(&request).expect("should serialize request");
+            let request: CreateRequest =
+                serde_json::from_str(&json).expect("should deserialize request");
+            let round_trip =
+                Modelfile::try_from(request).expect("should build Modelfile from request");
+
+            assert_eq!(round_trip, blob_references(modelfile));
+        }
+    }
+
+    #[test]
+    fn projector_layers_round_trip_in_order() {
+        let projector = "@sha256:622429e8d31810962dd984bc98559e706db2fb1d40e99cb073beb7148d909d73";
+        let weights = "@sha256:652e85aa1e14c9087a4ccc3ab516fb794cbcf152f8b4b8d3c0b828da4ada62d9";
+        // the weights sort after the projector
+        let modelfile: Modelfile = format!("FROM {weights}\nFROM {projector}\n")
+            .parse()
+            .expect("should parse Modelfile");
+
+        let round_trip = Modelfile::try_from(
+            modelfile
+                .to_create_request("vision")
+                .expect("should create request"),
+        )
+        .expect("should build Modelfile from request");
+
+        assert_eq!(round_trip.from.as_str(), weights);
+        assert_eq!(round_trip.layers, modelfile.layers);
+        assert_eq!(round_trip, modelfile);
+    }
+
+    #[test]
+    fn stacked_adapters_round_trip_in_order() {
+        let domain = "@sha256:f000000000000000000000000000000000000000000000000000000000000000";
+        let style = "@sha256:0000000000000000000000000000000000000000000000000000000000000001";
+        // the domain adapter sorts after the style adapter
+        let modelfile: Modelfile = format!("FROM llama3.2\nADAPTER {domain}\nADAPTER {style}\n")
+            .parse()
+            .expect("should parse Modelfile");
+
+        let request = modelfile
+            .to_create_request("stacked")
+            .expect("should create request");
+        let json = serde_json::to_string(&request).expect("should serialize request");
+        let request: CreateRequest =
+            serde_json::from_str(&json).expect("should deserialize request");
+        let round_trip = Modelfile::try_from(request).expect("should build Modelfile from request");
+
+        assert_eq!(
+            round_trip
+                .adapters
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [domain, style]
+        );
+        assert_eq!(round_trip, modelfile);
+    }
+
+    #[test]
+    fn local_files_must_be_uploaded() {
+        let modelfile: Modelfile = "FROM ./model.gguf".parse().expect("should parse Modelfile");
+
+        let error = modelfile
+            .to_create_request("local")
+            .expect_err("should not create a request from a local file");
+
+        assert_snapshot!(error, @"./model.gguf must be uploaded as a blob first");
+    }
+}
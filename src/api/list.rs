@@ -0,0 +1,34 @@
+//! The response of [`/api/tags`],
+//! the models installed in Ollama.
+//!
+//! [`/api/tags`]: https://github.com/ollama/ollama/blob/main/docs/api.md#list-local-models
+
+use serde::{Deserialize, Serialize};
+
+use super::ModelDetails;
+
+/// The response of `/api/tags`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListResponse {
+    #[serde(default)]
+    pub models: Vec<ListedModel>,
+}
+
+/// A model installed in Ollama.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedModel {
+    /// The name of the model, like `llama3.2:latest`.
+    pub name: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<String>,
+    /// The size of the model in bytes.
+    #[serde(default)]
+    pub size: u64,
+    /// The hex digest of the model manifest.
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
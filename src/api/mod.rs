@@ -0,0 +1,18 @@
+//! Types for the [Ollama API] that carry Modelfiles,
+//! and, with the `client` feature, an async `Client` for it.
+//!
+//! [Ollama API]: https://github.com/ollama/ollama/blob/main/docs/api.md
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod create;
+pub mod list;
+pub mod progress;
+pub mod show;
+
+#[cfg(feature = "client")]
+pub use client::{Client, ClientError};
+pub use create::CreateRequest;
+pub use list::{ListResponse, ListedModel};
+pub use progress::ProgressResponse;
+pub use show::{ModelDetails, ShowResponse, TensorInfo};
@@ -0,0 +1,32 @@
+//! The progress events streamed by
+//! [`/api/create`](super::create) and `/api/pull`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::Digest;
+
+/// A single progress event,
+/// like `{"status": "pulling manifest"}`
+/// or `{"status": "pulling 6a0746a1ec1a", "digest": "sha256:…", "total": 2019377376, "completed": 241970}`.
+///
+/// The last event of a successful stream has the status `success`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgressResponse {
+    pub status: String,
+    /// The blob being transferred, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+    /// The size of the blob in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    /// How many bytes of the blob have been transferred.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+impl ProgressResponse {
+    /// `true` for the last event of a successful stream.
+    pub fn is_success(&self) -> bool {
+        self.status == "success"
+    }
+}
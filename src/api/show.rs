@@ -0,0 +1,246 @@
+//! The response of [`/api/show`],
+//! Ollama's description of an installed model.
+//!
+//! [`/api/show`]: https://github.com/ollama/ollama/blob/main/docs/api.md#show-model-information
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::modelfile::{
+    instruction::{License, Parameters, SystemMessage, Template},
+    Modelfile,
+};
+
+/// The response of `/api/show`.
+///
+/// The embedded `modelfile` is parsed into a [`Modelfile`]
+/// and the column-aligned `parameters` block into [`Parameters`].
+/// Serializing writes the [rendered](Modelfile::render) Modelfile,
+/// not the original text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShowResponse {
+    #[serde(with = "modelfile_text")]
+    pub modelfile: Modelfile,
+    #[serde(default, with = "parameter_lines")]
+    pub parameters: Parameters,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<Template>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<License>,
+    #[serde(default)]
+    pub details: ModelDetails,
+    /// Metadata read from the model file, like `general.architecture`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_info: Option<BTreeMap<String, serde_json::Value>>,
+    /// Metadata read from the projector file of a multimodal model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projector_info: Option<BTreeMap<String, serde_json::Value>>,
+    /// Only included when requested with `verbose`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tensors: Option<Vec<TensorInfo>>,
+    /// What the model can do, like `completion`, `tools` or `vision`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<String>,
+}
+
+/// A summary of the model weights.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub parent_model: String,
+    /// The file format of the weights, like `gguf`.
+    #[serde(default)]
+    pub format: String,
+    /// The model architecture, like `llama`.
+    #[serde(default)]
+    pub family: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub families: Vec<String>,
+    /// The number of parameters, like `8.0B` or `334M`.
+    #[serde(default)]
+    pub parameter_size: String,
+    /// The quantization of the weights, like `Q4_0` or `F16`.
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+/// A single tensor in the model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub name: String,
+    /// The data type, like `F32` or `Q4_K`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub shape: Vec<u64>,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// (De)serialize a [`Modelfile`] as its text.
+mod modelfile_text {
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    use crate::modelfile::Modelfile;
+
+    pub fn serialize<S: Serializer>(
+        modelfile: &Modelfile,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&modelfile.render())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Modelfile, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// (De)serialize [`Parameters`] as column-aligned `name value` lines.
+mod parameter_lines {
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    use crate::modelfile::instruction::Parameters;
+
+    pub fn serialize<S: Serializer>(
+        parameters: &Parameters,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&parameters.to_lines())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Parameters, D::Error> {
+        let lines: Option<String> = Option::deserialize(deserializer)?;
+        Parameters::from_lines(lines.as_deref().unwrap_or_default()).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use crate::modelfile::test_data::{
+        load_show_responses, TestData, TEST_BAD_DATA_DIR, TEST_GOOD_DATA_DIR,
+    };
+
+    use super::*;
+
+    #[test]
+    fn show_responses_are_parsed() {
+        let responses = load_show_responses(TEST_GOOD_DATA_DIR);
+        assert!(!responses.is_empty());
+
+        for TestData { path, contents } in responses {
+            dbg!(&path);
+            let response: ShowResponse =
+                serde_json::from_str(&contents).expect("should parse show response");
+
+            // `parameters` repeats the Modelfile's PARAMETER lines
+            assert_eq!(response.parameters, response.modelfile.parameters);
+
+            let json = serde_json::to_string(&response).expect("should serialize show response");
+            let round_trip: ShowResponse =
+                serde_json::from_str(&json).expect("should parse serialized show response");
+            assert_eq!(round_trip, response);
+        }
+
+        for TestData { path, contents } in load_show_responses(TEST_BAD_DATA_DIR) {
+            dbg!(&path);
+            serde_json::from_str::<ShowResponse>(&contents)
+                .expect_err("should not parse a show response with a bad Modelfile");
+        }
+    }
+
+    #[test]
+    fn show_response_details_snapshot() {
+        let contents = std::fs::read_to_string(format!(
+            "{TEST_GOOD_DATA_DIR}/mxbai-embed-large.latest.model.json"
+        ))
+        .expect("should read test data");
+
+        let response: ShowResponse =
+            serde_json::from_str(&contents).expect("should parse show response");
+
+        assert_debug_snapshot!(
+            (
+                response.details,
+                response.parameters.to_lines(),
+                response.modelfile.from.to_string()
+            ),
+            @r#"
+        (
+            ModelDetails {
+                parent_model: "",
+                format: "gguf",
+                family: "bert",
+                families: [
+                    "bert",
+                ],
+                parameter_size: "334M",
+                quantization_level: "F16",
+            },
+            "num_ctx                        512",
+            "/mnt/space/ollama/models/blobs/sha256-819c2adf5ce6df2b6bd2ae4ca90d2a69f060afeb438d0c171db57daa02e39c3d",
+        )
+        "#
+        );
+    }
+
+    #[test]
+    fn projectors_are_more_from_layers() {
+        let contents = std::fs::read_to_string(format!(
+            "{TEST_GOOD_DATA_DIR}/x.llama3.2-vision.latest.model.json"
+        ))
+        .expect("should read test data");
+
+        let response: ShowResponse =
+            serde_json::from_str(&contents).expect("should parse show response");
+        let projector_info = response
+            .projector_info
+            .expect("fixture should have projector_info");
+
+        // `families` has the model's architecture, then the projector's
+        assert_eq!(
+            response.details.families.len(),
+            response.modelfile.layers.len() + 1
+        );
+        assert_eq!(
+            projector_info.get("general.architecture"),
+            response
+                .details
+                .families
+                .get(1)
+                .map(|family| family.as_str().into())
+                .as_ref()
+        );
+        assert_debug_snapshot!(
+            (
+                response.modelfile.from.to_string(),
+                response.modelfile.layers.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                projector_info.get("general.type"),
+            ),
+            @r#"
+        (
+            "/mnt/space/ollama/models/blobs/sha256-652e85aa1e14c9087a4ccc3ab516fb794cbcf152f8b4b8d3c0b828da4ada62d9",
+            [
+                "/mnt/space/ollama/models/blobs/sha256-622429e8d31810962dd984bc98559e706db2fb1d40e99cb073beb7148d909d73",
+            ],
+            Some(
+                String("projector"),
+            ),
+        )
+        "#
+        );
+    }
+}
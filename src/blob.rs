@@ -0,0 +1,370 @@
+//! Uploading the local files a [`Modelfile`] refers to
+//! as blobs, so the Modelfile can be sent to [Ollama].
+//!
+//! Ollama can only create a model from files it already has,
+//! so `FROM ./model.gguf` or `ADAPTER ./lora.gguf`
+//! have to be hashed, uploaded and rewritten to `@sha256:<hex>` first:
+//!
+//! 1. [`Modelfile::prepare_blobs`] hashes the local files
+//!    and rewrites the Modelfile to refer to them by digest.
+//! 2. [`BlobUpload::upload`] sends the blobs Ollama doesn't have yet
+//!    through a [`BlobTransport`].
+//!
+//! [Ollama]: https://ollama.com/
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    api::CreateRequest,
+    digest::Digest,
+    modelfile::{
+        base_model::{BaseModel, ModelSource},
+        error::ModelfileError,
+        resolve_path, Modelfile, TensorFile,
+    },
+};
+
+/// A local file to upload as a blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalBlob {
+    pub path: PathBuf,
+    /// The name of the file in a create request,
+    /// which Ollama uses to tell a `.gguf` from a `.safetensors` file.
+    pub name: String,
+    pub digest: Digest,
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+impl LocalBlob {
+    /// Hash the file at `path`, named after its file name.
+    pub fn from_path(path: impl Into<PathBuf>) -> Result<Self, BlobError> {
+        let path = path.into();
+        let io_error = |source| BlobError::Io {
+            path: path.clone(),
+            source,
+        };
+
+        let size = std::fs::metadata(&path).map_err(io_error)?.len();
+        let digest = Digest::from_file(&path).map_err(io_error)?;
+
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(LocalBlob {
+            path,
+            name,
+            digest,
+            size,
+        })
+    }
+}
+
+/// Errors finding and hashing local files.
+#[derive(Debug, Error)]
+pub enum BlobError {
+    #[error("couldn't read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    /// Directories of safetensors files can't be referred to by a single digest.
+    #[error("{0} is a directory, only single files can be uploaded as blobs")]
+    Directory(PathBuf),
+}
+
+/// A [`Modelfile`] that refers to its local files by digest,
+/// and the files to upload before it can be used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobUpload {
+    pub modelfile: Modelfile,
+    pub blobs: Vec<LocalBlob>,
+}
+
+impl Modelfile {
+    /// Hash the local files in every `FROM` and `ADAPTER`
+    /// and rewrite them to `@sha256:<hex>` blob references.
+    ///
+    /// Relative paths are resolved against `base`,
+    /// usually the directory containing the Modelfile,
+    /// and `~` is expanded like in [`TensorFile::resolve`].
+    /// Each blob is named by its path relative to `base`.
+    ///
+    /// Directories of safetensors files fail with [`BlobError::Directory`],
+    /// since a Modelfile can only refer to a single blob by its digest.
+    pub fn prepare_blobs(&self, base: impl AsRef<Path>) -> Result<BlobUpload, BlobError> {
+        let base = base.as_ref();
+        let mut modelfile = self.clone();
+        let mut blobs: Vec<LocalBlob> = Vec::new();
+        let mut add = |path: &Path| -> Result<Digest, BlobError> {
+            let path = resolve_path(path.to_path_buf(), base);
+            let mut blob = LocalBlob::from_path(&path)?;
+            if let Ok(relative) = path.strip_prefix(base) {
+                blob.name = relative.to_string_lossy().into_owned();
+            }
+            let digest = blob.digest.clone();
+            if !blobs.iter().any(|existing| existing.digest == digest) {
+                blobs.push(blob);
+            }
+            Ok(digest)
+        };
+
+        let mut upload = |model: &mut BaseModel| -> Result<(), BlobError> {
+            let digest = match model.source() {
+                ModelSource::Gguf(path) | ModelSource::Safetensor(path) => add(path)?,
+                ModelSource::Directory(path) => return Err(BlobError::Directory(path.clone())),
+                ModelSource::Reference(_) | ModelSource::Blob(_) => return Ok(()),
+            };
+            *model = digest.into();
+            Ok(())
+        };
+        upload(&mut modelfile.from)?;
+        for layer in &mut modelfile.layers {
+            upload(layer)?;
+        }
+
+        for adapter in &mut modelfile.adapters {
+            match &**adapter {
+                TensorFile::Gguf(path) | TensorFile::Safetensor(path) => {
+                    *adapter = TensorFile::Blob(add(path)?).into();
+                }
+                TensorFile::Directory(path) => return Err(BlobError::Directory(path.clone())),
+                TensorFile::Blob(_) => {}
+            }
+        }
+
+        Ok(BlobUpload { modelfile, blobs })
+    }
+}
+
+/// How blobs get to Ollama,
+/// like the `Client` over HTTP with the `client` feature.
+pub trait BlobTransport {
+    type Error;
+
+    /// `true` if Ollama already has the blob.
+    fn exists(&self, digest: &Digest) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// Send the blob's file to Ollama.
+    fn upload(&self, blob: &LocalBlob) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+impl BlobUpload {
+    /// The body of a `/api/create` request
+    /// that creates a model called `name` from the rewritten Modelfile,
+    /// see [`Modelfile::to_create_request`].
+    ///
+    /// Uploaded files keep their local names,
+    /// so Ollama can tell their format from the extension.
+    pub fn to_create_request(
+        &self,
+        name: impl Into<String>,
+    ) -> Result<CreateRequest, ModelfileError> {
+        self.modelfile.create_request(name.into(), |digest| {
+            self.blobs
+                .iter()
+                .find(|blob| blob.digest == *digest)
+                .map_or_else(|| digest.blob_file_name(), |blob| blob.name.clone())
+        })
+    }
+
+    /// Upload the blobs Ollama doesn't have yet,
+    /// returning the digests that were uploaded.
+    pub async fn upload<T: BlobTransport>(&self, transport: &T) -> Result<Vec<Digest>, T::Error> {
+        let mut uploaded = Vec::new();
+
+        for blob in &self.blobs {
+            if transport.exists(&blob.digest).await? {
+                tracing::debug!("skipping blob {}, it already exists", blob.digest);
+                continue;
+            }
+
+            transport.upload(blob).await?;
+            uploaded.push(blob.digest.clone());
+        }
+
+        Ok(uploaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Mutex};
+
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+
+    /// Remembers uploads, and has the blobs it was created with.
+    #[derive(Default)]
+    struct MockTransport {
+        blobs: Mutex<BTreeSet<Digest>>,
+    }
+
+    impl BlobTransport for MockTransport {
+        type Error = std::convert::Infallible;
+
+        async fn exists(&self, digest: &Digest) -> Result<bool, Self::Error> {
+            Ok(self
+                .blobs
+                .lock()
+                .expect("lock should not be poisoned")
+                .contains(digest))
+        }
+
+        async fn upload(&self, blob: &LocalBlob) -> Result<(), Self::Error> {
+            self.blobs
+                .lock()
+                .expect("lock should not be poisoned")
+                .insert(blob.digest.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn local_files_are_rewritten_and_uploaded() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        std::fs::write(dir.path().join("model.gguf"), "model").expect("should write model");
+        std::fs::write(dir.path().join("lora.gguf"), "lora").expect("should write adapter");
+
+        let modelfile: Modelfile = "FROM ./model.gguf\nADAPTER lora.gguf\n"
+            .parse()
+            .expect("should parse Modelfile");
+
+        let upload = modelfile
+            .prepare_blobs(dir.path())
+            .expect("should hash local files");
+
+        assert_debug_snapshot!(
+            (
+                upload.modelfile.from.to_string(),
+                upload.modelfile.adapters.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                upload
+                    .blobs
+                    .iter()
+                    .map(|blob| (blob.path.strip_prefix(dir.path()).ok(), blob.size))
+                    .collect::<Vec<_>>(),
+            ),
+            @r#"
+        (
+            "@sha256:9372c470eeadd5ecd9c3c74c2b3cb633f8e2f2fad799250a0f70d652b6b825e4",
+            [
+                "@sha256:d339f720de1fd92a672df9ef19a8cdbda6171cbf33fcd35ad95c46f8aebaf628",
+            ],
+            [
+                (
+                    Some(
+                        "model.gguf",
+                    ),
+                    5,
+                ),
+                (
+                    Some(
+                        "lora.gguf",
+                    ),
+                    4,
+                ),
+            ],
+        )
+        "#
+        );
+
+        let model = upload.blobs[0].digest.clone();
+        let transport = MockTransport {
+            blobs: Mutex::new([model].into()),
+        };
+        let uploaded = upload
+            .upload(&transport)
+            .await
+            .expect("mock upload should not fail");
+
+        assert_eq!(uploaded, vec![upload.blobs[1].digest.clone()]);
+        assert!(upload
+            .upload(&transport)
+            .await
+            .expect("mock upload should not fail")
+            .is_empty());
+    }
+
+    #[test]
+    fn create_requests_keep_local_file_names() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        std::fs::create_dir(dir.path().join("models")).expect("should create models dir");
+        std::fs::write(dir.path().join("models/model.gguf"), "model").expect("should write model");
+        std::fs::write(dir.path().join("lora.safetensors"), "lora").expect("should write adapter");
+
+        let modelfile: Modelfile = "FROM ./models/model.gguf\nADAPTER lora.safetensors\n"
+            .parse()
+            .expect("should parse Modelfile");
+
+        let request = modelfile
+            .prepare_blobs(dir.path())
+            .expect("should hash local files")
+            .to_create_request("local")
+            .expect("should create request");
+
+        assert_eq!(
+            request
+                .files
+                .iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            ["models/model.gguf"]
+        );
+        assert_eq!(
+            request
+                .adapters
+                .iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            ["lora.safetensors"]
+        );
+    }
+
+    #[test]
+    fn missing_files_and_directories_are_errors() {
+        let missing: Modelfile = "FROM ./missing.gguf"
+            .parse()
+            .expect("should parse Modelfile");
+        let directory: Modelfile = "FROM ./safetensors/"
+            .parse()
+            .expect("should parse Modelfile");
+        let adapter_directory: Modelfile = "FROM llama3.2\nADAPTER ./lora/"
+            .parse()
+            .expect("should parse Modelfile");
+
+        assert!(matches!(
+            missing.prepare_blobs("/nonexistent"),
+            Err(BlobError::Io { .. })
+        ));
+        assert!(matches!(
+            directory.prepare_blobs("."),
+            Err(BlobError::Directory(_))
+        ));
+        assert!(matches!(
+            adapter_directory.prepare_blobs("."),
+            Err(BlobError::Directory(_))
+        ));
+    }
+
+    #[test]
+    fn home_paths_are_expanded() {
+        let Some(home) = crate::modelfile::home_dir() else {
+            return;
+        };
+        let modelfile: Modelfile = "FROM ~/missing-model.gguf"
+            .parse()
+            .expect("should parse Modelfile");
+
+        let Err(BlobError::Io { path, .. }) = modelfile.prepare_blobs("/nonexistent") else {
+            panic!("missing file should be an error");
+        };
+        assert_eq!(path, home.join("missing-model.gguf"));
+    }
+}
@@ -0,0 +1,157 @@
+//! Content digests that [Ollama] uses to name blobs.
+//!
+//! [Ollama]: https://ollama.com/
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use thiserror::Error;
+
+const ALGORITHM: &str = "sha256";
+const HEX_LEN: usize = 64;
+
+/// A `sha256` digest of a blob, like `sha256:6a0746a1...`.
+///
+/// Both the `sha256:<hex>` form used in Modelfiles and the API
+/// and the `sha256-<hex>` form used for blob file names are parsed.
+/// The digest is always displayed in the `sha256:<hex>` form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Digest {
+    hex: String,
+}
+
+impl Digest {
+    /// The lowercase hex encoded hash, without the algorithm prefix.
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    /// The name Ollama gives the blob file, `sha256-<hex>`.
+    pub fn blob_file_name(&self) -> String {
+        format!("{ALGORITHM}-{}", self.hex)
+    }
+
+    /// Hash everything read from `reader`,
+    /// a chunk at a time so large model files aren't loaded into memory.
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => hasher.update(&buffer[..read]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(Digest {
+            hex: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    /// Hash the contents of the file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Digest::from_reader(File::open(path)?)
+    }
+}
+
+/// Why a string is not a valid [`Digest`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DigestError {
+    #[error("digest should start with `sha256:` or `sha256-`: {0}")]
+    Algorithm(String),
+    #[error("digest should have {HEX_LEN} hex characters: {0}")]
+    Hex(String),
+}
+
+impl FromStr for Digest {
+    type Err = DigestError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let hex = input
+            .strip_prefix(ALGORITHM)
+            .and_then(|rest| rest.strip_prefix([':', '-']))
+            .ok_or_else(|| DigestError::Algorithm(input.to_string()))?;
+
+        if hex.len() != HEX_LEN || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DigestError::Hex(input.to_string()));
+        }
+
+        Ok(Digest {
+            hex: hex.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl TryFrom<String> for Digest {
+    type Error = DigestError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Digest> for String {
+    fn from(value: Digest) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{ALGORITHM}:{}", self.hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa";
+
+    #[test]
+    fn digests_are_parsed() {
+        let colon: Digest = format!("sha256:{HEX}")
+            .parse()
+            .expect("should parse sha256:");
+        let dash: Digest = format!("sha256-{HEX}")
+            .parse()
+            .expect("should parse sha256-");
+        let upper: Digest = format!("sha256:{}", HEX.to_uppercase())
+            .parse()
+            .expect("should parse uppercase hex");
+
+        assert_eq!(colon, dash);
+        assert_eq!(colon, upper);
+        assert_eq!(colon.to_string(), format!("sha256:{HEX}"));
+        assert_eq!(colon.hex(), HEX);
+
+        assert!(matches!(
+            format!("md5:{HEX}").parse::<Digest>(),
+            Err(DigestError::Algorithm(_))
+        ));
+        assert!(matches!(
+            "sha256:abc".parse::<Digest>(),
+            Err(DigestError::Hex(_))
+        ));
+    }
+
+    #[test]
+    fn contents_are_hashed() {
+        let digest = Digest::from_reader("hello".as_bytes()).expect("should hash bytes");
+
+        assert_eq!(
+            digest.to_string(),
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+}
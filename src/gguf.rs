@@ -0,0 +1,574 @@
+//! Reading the header of a [GGUF] model file:
+//! the key/value metadata and the tensor descriptions,
+//! never the tensor data.
+//!
+//! The metadata has the same keys Ollama shows in `model_info`,
+//! like `general.architecture` or `llama.context_length`.
+//!
+//! [GGUF]: https://github.com/ggerganov/ggml/blob/master/docs/gguf.md
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::modelfile::{
+    base_model::BaseModel, builder::ModelfileBuilder, error::ModelfileError, Modelfile, Parameter,
+};
+
+const MAGIC: [u8; 4] = *b"GGUF";
+/// How deep arrays can be nested, so a bad file can't overflow the stack.
+const MAX_DEPTH: usize = 8;
+
+/// Errors reading a GGUF header.
+#[derive(Debug, Error)]
+pub enum GgufError {
+    #[error("couldn't read GGUF header: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a GGUF file")]
+    Magic,
+    #[error("unsupported GGUF version {0}")]
+    Version(u32),
+    #[error("unknown GGUF value type {0}")]
+    ValueType(u32),
+    #[error("GGUF arrays are nested more than {MAX_DEPTH} deep")]
+    Nesting,
+    #[error("GGUF string is not UTF-8")]
+    Utf8,
+    #[error("num_ctx {num_ctx} is more than the model's context length {context_length}")]
+    ContextLength { num_ctx: usize, context_length: u64 },
+    #[error(transparent)]
+    Modelfile(#[from] ModelfileError),
+}
+
+/// A metadata value.
+///
+/// Serializes to plain JSON values, like Ollama's `model_info`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum GgufValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<GgufValue>),
+}
+
+impl GgufValue {
+    /// The value as an unsigned integer,
+    /// if it is a non-negative integer of any width.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            GgufValue::U8(value) => Some(value.into()),
+            GgufValue::U16(value) => Some(value.into()),
+            GgufValue::U32(value) => Some(value.into()),
+            GgufValue::U64(value) => Some(value),
+            GgufValue::I8(value) => value.try_into().ok(),
+            GgufValue::I16(value) => value.try_into().ok(),
+            GgufValue::I32(value) => value.try_into().ok(),
+            GgufValue::I64(value) => value.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GgufValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[GgufValue]> {
+        match self {
+            GgufValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// The header of a GGUF file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GgufHeader {
+    pub version: u32,
+    pub tensor_count: u64,
+    pub metadata: BTreeMap<String, GgufValue>,
+    /// The sum of the elements of every tensor.
+    tensor_elements: u64,
+}
+
+impl GgufHeader {
+    /// Read the header of the GGUF file at `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, GgufError> {
+        GgufHeader::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a GGUF header from the start of `reader`.
+    ///
+    /// Reading stops at the end of the tensor descriptions,
+    /// before the tensor data.
+    pub fn read(reader: impl Read) -> Result<Self, GgufError> {
+        let mut reader = Reader(reader);
+
+        if reader.bytes::<4>()? != MAGIC {
+            return Err(GgufError::Magic);
+        }
+        // version 1 used 32 bit counts
+        let version = reader.u32()?;
+        if !(2..=3).contains(&version) {
+            return Err(GgufError::Version(version));
+        }
+
+        let tensor_count = reader.u64()?;
+        let metadata_count = reader.u64()?;
+
+        let mut metadata = BTreeMap::new();
+        for _ in 0..metadata_count {
+            let key = reader.string()?;
+            let value_type = reader.u32()?;
+            metadata.insert(key, reader.value(value_type, 0)?);
+        }
+
+        let mut tensor_elements: u64 = 0;
+        for _ in 0..tensor_count {
+            let _name = reader.string()?;
+            let dimensions = reader.u32()?;
+            let mut elements: u64 = 1;
+            for _ in 0..dimensions {
+                elements = elements.saturating_mul(reader.u64()?);
+            }
+            let _tensor_type = reader.u32()?;
+            let _offset = reader.u64()?;
+            tensor_elements = tensor_elements.saturating_add(elements);
+        }
+
+        Ok(GgufHeader {
+            version,
+            tensor_count,
+            metadata,
+            tensor_elements,
+        })
+    }
+
+    /// A metadata value by key, like `general.architecture`.
+    pub fn get(&self, key: &str) -> Option<&GgufValue> {
+        self.metadata.get(key)
+    }
+
+    /// The model architecture, like `llama`.
+    pub fn architecture(&self) -> Option<&str> {
+        self.get("general.architecture")?.as_str()
+    }
+
+    /// The context length the model was trained with,
+    /// from `<architecture>.context_length`.
+    pub fn context_length(&self) -> Option<u64> {
+        let architecture = self.architecture()?;
+        self.get(&format!("{architecture}.context_length"))?
+            .as_u64()
+    }
+
+    /// The number of parameters,
+    /// from `general.parameter_count` or counted from the tensors.
+    pub fn parameter_count(&self) -> u64 {
+        self.get("general.parameter_count")
+            .and_then(GgufValue::as_u64)
+            .unwrap_or(self.tensor_elements)
+    }
+
+    /// The quantization of the weights, like `Q4_K_M`,
+    /// from `general.file_type`.
+    pub fn quantization(&self) -> Option<&'static str> {
+        file_type_name(self.get("general.file_type")?.as_u64()?)
+    }
+
+    /// The Jinja chat template from `tokenizer.chat_template`.
+    pub fn chat_template(&self) -> Option<&str> {
+        self.get("tokenizer.chat_template")?.as_str()
+    }
+
+    /// The text of the end of sequence token.
+    pub fn eos_token(&self) -> Option<&str> {
+        let id = self.get("tokenizer.ggml.eos_token_id")?.as_u64()?;
+        self.get("tokenizer.ggml.tokens")?
+            .as_array()?
+            .get(usize::try_from(id).ok()?)?
+            .as_str()
+    }
+
+    /// Check that `modelfile` fits the model,
+    /// like `num_ctx` not being more than the [context length](Self::context_length).
+    pub fn validate(&self, modelfile: &Modelfile) -> Result<(), GgufError> {
+        let Some(context_length) = self.context_length() else {
+            return Ok(());
+        };
+
+        for parameter in modelfile.parameters.iter() {
+            if let Parameter::NumCtx(num_ctx) = *parameter {
+                if num_ctx as u64 > context_length {
+                    return Err(GgufError::ContextLength {
+                        num_ctx,
+                        context_length,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Modelfile {
+    /// A starting [`Modelfile`] for the GGUF file at `path`,
+    /// built `FROM` the file with a `stop` parameter
+    /// for the end of sequence token, if the file has one.
+    ///
+    /// The chat template in the file is Jinja, not a Go template,
+    /// so no `TEMPLATE` is proposed, see [`GgufHeader::chat_template`]
+    /// and [`Template::from_jinja`](crate::modelfile::instruction::Template::from_jinja).
+    pub fn from_gguf(path: impl AsRef<Path>) -> Result<Self, GgufError> {
+        let path = path.as_ref();
+        let header = GgufHeader::from_path(path)?;
+
+        let mut builder = ModelfileBuilder {
+            from: Some(BaseModel::from(path.to_path_buf())),
+            ..Default::default()
+        };
+        if let Some(eos) = header.eos_token() {
+            builder = builder.parameter(Parameter::Stop(eos.to_string()));
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// The name of a `general.file_type`,
+/// see `llama_ftype` in llama.cpp.
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+/// Little-endian GGUF primitives.
+struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn string(&mut self) -> Result<String, GgufError> {
+        let len = self.u64()?;
+        // don't trust the length to allocate up front
+        let mut bytes = Vec::new();
+        (&mut self.0).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        String::from_utf8(bytes).map_err(|_| GgufError::Utf8)
+    }
+
+    fn value(&mut self, value_type: u32, depth: usize) -> Result<GgufValue, GgufError> {
+        Ok(match value_type {
+            0 => GgufValue::U8(u8::from_le_bytes(self.bytes()?)),
+            1 => GgufValue::I8(i8::from_le_bytes(self.bytes()?)),
+            2 => GgufValue::U16(u16::from_le_bytes(self.bytes()?)),
+            3 => GgufValue::I16(i16::from_le_bytes(self.bytes()?)),
+            4 => GgufValue::U32(self.u32()?),
+            5 => GgufValue::I32(i32::from_le_bytes(self.bytes()?)),
+            6 => GgufValue::F32(f32::from_le_bytes(self.bytes()?)),
+            7 => GgufValue::Bool(self.bytes::<1>()? != [0]),
+            8 => GgufValue::String(self.string()?),
+            9 if depth >= MAX_DEPTH => return Err(GgufError::Nesting),
+            9 => {
+                let item_type = self.u32()?;
+                let len = self.u64()?;
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.value(item_type, depth + 1)?);
+                }
+                GgufValue::Array(items)
+            }
+            10 => GgufValue::U64(self.u64()?),
+            11 => GgufValue::I64(i64::from_le_bytes(self.bytes()?)),
+            12 => GgufValue::F64(f64::from_le_bytes(self.bytes()?)),
+            unknown => return Err(GgufError::ValueType(unknown)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+    use serde_json::Value;
+
+    use crate::{
+        api::ShowResponse,
+        modelfile::{base_model::ModelSource, test_data::TEST_GOOD_DATA_DIR},
+    };
+
+    use super::*;
+
+    /// Write a GGUF header with `metadata`
+    /// and a single tensor of `elements` elements.
+    fn write_gguf(metadata: &BTreeMap<String, Value>, elements: u64) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, text: &str) {
+            out.extend((text.len() as u64).to_le_bytes());
+            out.extend(text.as_bytes());
+        }
+
+        fn value(out: &mut Vec<u8>, value: &Value) {
+            match value {
+                Value::Bool(value) => {
+                    out.extend(7u32.to_le_bytes());
+                    out.push(u8::from(*value));
+                }
+                Value::Number(number) => match (number.as_u64(), number.as_f64()) {
+                    (Some(int), _) if int <= u32::MAX.into() => {
+                        out.extend(4u32.to_le_bytes());
+                        out.extend((int as u32).to_le_bytes());
+                    }
+                    (Some(int), _) => {
+                        out.extend(10u32.to_le_bytes());
+                        out.extend(int.to_le_bytes());
+                    }
+                    (None, Some(float)) => {
+                        out.extend(6u32.to_le_bytes());
+                        out.extend((float as f32).to_le_bytes());
+                    }
+                    (None, None) => unreachable!("JSON numbers are u64 or f64 here"),
+                },
+                Value::String(text) => {
+                    out.extend(8u32.to_le_bytes());
+                    string(out, text);
+                }
+                Value::Array(items) => {
+                    out.extend(9u32.to_le_bytes());
+                    out.extend(8u32.to_le_bytes());
+                    out.extend((items.len() as u64).to_le_bytes());
+                    for item in items {
+                        string(out, item.as_str().expect("arrays are of strings here"));
+                    }
+                }
+                Value::Null | Value::Object(_) => unreachable!("skipped"),
+            }
+        }
+
+        let metadata: Vec<_> = metadata.iter().filter(|(_, v)| !v.is_null()).collect();
+
+        let mut out = Vec::new();
+        out.extend(MAGIC);
+        out.extend(3u32.to_le_bytes());
+        out.extend(1u64.to_le_bytes());
+        out.extend((metadata.len() as u64).to_le_bytes());
+        for (key, item) in metadata {
+            string(&mut out, key);
+            value(&mut out, item);
+        }
+
+        string(&mut out, "token_embd.weight");
+        out.extend(2u32.to_le_bytes());
+        out.extend(elements.to_le_bytes());
+        out.extend(1u64.to_le_bytes());
+        out.extend(2u32.to_le_bytes());
+        out.extend(0u64.to_le_bytes());
+        out
+    }
+
+    fn llama_model_info() -> BTreeMap<String, Value> {
+        let contents = std::fs::read_to_string(format!("{TEST_GOOD_DATA_DIR}/llama3.1.model.json"))
+            .expect("should read test data");
+        let response: ShowResponse =
+            serde_json::from_str(&contents).expect("should parse show response");
+
+        response.model_info.expect("fixture should have model_info")
+    }
+
+    #[test]
+    fn fixture_model_info_is_read_back() {
+        let model_info = llama_model_info();
+        let header =
+            GgufHeader::read(write_gguf(&model_info, 64).as_slice()).expect("should read header");
+
+        assert_debug_snapshot!(
+            (
+                header.architecture(),
+                header.context_length(),
+                header.parameter_count(),
+                header.quantization(),
+                header.chat_template(),
+            ),
+            @r#"
+        (
+            Some(
+                "llama",
+            ),
+            Some(
+                131072,
+            ),
+            8030261312,
+            Some(
+                "Q4_0",
+            ),
+            None,
+        )
+        "#
+        );
+
+        // everything Ollama shows, other than the omitted arrays
+        for (key, value) in model_info.iter().filter(|(_, value)| !value.is_null()) {
+            let read = serde_json::to_value(header.get(key)).expect("should serialize value");
+            match (read.as_f64(), value.as_f64()) {
+                // floats are written as f32
+                (Some(read), Some(expected)) if value.is_f64() => {
+                    assert!((read - expected).abs() <= expected.abs() * 1e-6, "{key}");
+                }
+                _ => assert_eq!(&read, value, "{key}"),
+            }
+        }
+    }
+
+    #[test]
+    fn modelfiles_are_proposed_and_validated() {
+        let mut metadata = BTreeMap::new();
+        metadata.insert("general.architecture".to_string(), "llama".into());
+        metadata.insert("llama.context_length".to_string(), 8192.into());
+        metadata.insert("tokenizer.ggml.eos_token_id".to_string(), 2.into());
+        metadata.insert(
+            "tokenizer.ggml.tokens".to_string(),
+            vec!["<unk>", "<s>", "</s>"].into(),
+        );
+        metadata.insert(
+            "tokenizer.chat_template".to_string(),
+            "{{ bos_token }}{% for message in messages %}{{ message['content'] }}{% endfor %}"
+                .into(),
+        );
+
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("model.gguf");
+        std::fs::write(&path, write_gguf(&metadata, 1024)).expect("should write model");
+
+        let modelfile = Modelfile::from_gguf(&path).expect("should propose Modelfile");
+        let header = GgufHeader::from_path(&path).expect("should read header");
+
+        assert_debug_snapshot!(
+            (
+                header.parameter_count(),
+                header.chat_template(),
+                &modelfile.parameters,
+            ),
+            @r#"
+        (
+            1024,
+            Some(
+                "{{ bos_token }}{% for message in messages %}{{ message['content'] }}{% endfor %}",
+            ),
+            Parameters(
+                [
+                    Stop(
+                        "</s>",
+                    ),
+                ],
+            ),
+        )
+        "#
+        );
+
+        header
+            .validate(&modelfile)
+            .expect("proposed Modelfile should be valid");
+        let too_long = modelfile
+            .build_on()
+            .parameter(Parameter::NumCtx(16384))
+            .build()
+            .expect("should build Modelfile");
+        assert!(matches!(
+            header.validate(&too_long),
+            Err(GgufError::ContextLength { .. })
+        ));
+
+        assert!(matches!(
+            GgufHeader::read(b"GGML".as_slice()),
+            Err(GgufError::Magic)
+        ));
+    }
+
+    #[test]
+    fn paths_are_not_parsed_as_modelfile_text() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("my \"best\" model.gguf");
+        std::fs::write(&path, write_gguf(&llama_model_info(), 1)).expect("should write model");
+
+        let modelfile = Modelfile::from_gguf(&path).expect("should propose Modelfile");
+
+        assert_eq!(modelfile.from.source(), &ModelSource::Gguf(path));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt as _;
+
+            let path = dir
+                .path()
+                .join(std::ffi::OsStr::from_bytes(b"model-\xff.gguf"));
+            std::fs::write(&path, write_gguf(&llama_model_info(), 1)).expect("should write model");
+
+            let modelfile = Modelfile::from_gguf(&path).expect("should propose Modelfile");
+
+            assert_eq!(modelfile.from.source(), &ModelSource::Gguf(path));
+        }
+    }
+}
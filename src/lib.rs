@@ -1,6 +1,13 @@
 #![doc = include_str!("../README.md")]
+pub mod api;
+pub mod blob;
+pub mod digest;
+pub mod gguf;
 pub mod message;
 pub mod modelfile;
+pub mod reference;
+pub mod store;
 
+pub use digest::Digest;
 pub use message::{Message, MessageRole};
 pub use modelfile::{builder::ModelfileBuilder, Modelfile};
@@ -2,40 +2,153 @@
 //! hosted with [Ollama].
 //!
 //! [Ollama]: https://github.com/ollama/ollama/tree/main
-use std::sync::Arc;
+use std::{collections::BTreeMap, fmt::Display, sync::Arc};
 
 use serde::{Deserialize, Serialize};
-use strum::{EnumDiscriminants, EnumString};
+use serde_json::{Map, Value};
+use strum::EnumString;
 
-#[derive(Debug, Clone, strum::Display, EnumDiscriminants, Serialize, Deserialize, PartialEq)]
-#[strum_discriminants(name(MessageRole))]
-#[strum_discriminants(derive(EnumString))]
-#[strum_discriminants(strum(serialize_all = "lowercase"))]
+use crate::modelfile::cst::Quoting;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Message {
-    #[strum(serialize = "assistant: {0}")]
     Assistant(Arc<str>),
-    #[strum(serialize = "user: {0}")]
     User(Arc<str>),
-    #[strum(serialize = "system: {0}")]
     System(Arc<str>),
+    /// The result of a tool call, for the model to answer from.
+    Tool(Arc<str>),
+    /// An assistant turn that calls tools.
+    ///
+    /// Modelfiles have no syntax for tool calls,
+    /// so the message is written like in the chat JSON,
+    /// as an object with the `tool_calls` and an optional `content`:
+    ///
+    /// ```text
+    /// MESSAGE assistant {"tool_calls": [{"function": {"name": "get_weather", "arguments": {"city": "Paris"}}}]}
+    /// ```
+    ///
+    /// `MESSAGE` content is always read back as text,
+    /// see [`ChatMessage::read_tool_calls`] to turn it into tool calls again.
+    ToolCalls {
+        content: Arc<str>,
+        tool_calls: Vec<ToolCall>,
+    },
+}
+
+/// Who a [`Message`] is from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum MessageRole {
+    Assistant,
+    User,
+    System,
+    Tool,
+}
+
+/// A call the model makes to a tool.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCall {
+    pub function: ToolCallFunction,
+    /// Fields this crate doesn't model, like the `id` of the call,
+    /// kept so they reach Ollama unchanged.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The tool called and the arguments it is called with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCallFunction {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: BTreeMap<String, Value>,
+    /// Fields this crate doesn't model, like the `index` of the call.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ToolCall {
+    /// The call as JSON, like in the chat API.
+    fn to_json(&self) -> Value {
+        let mut function = self.function.extra.clone();
+        function.insert("name".to_string(), Value::from(self.function.name.as_str()));
+        function.insert(
+            "arguments".to_string(),
+            Value::Object(self.function.arguments.clone().into_iter().collect()),
+        );
+
+        let mut call = self.extra.clone();
+        call.insert("function".to_string(), Value::Object(function));
+        Value::Object(call)
+    }
+}
+
+/// How [`Message::ToolCalls`] are written in a Modelfile.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolCallsContent {
+    #[serde(default)]
+    content: String,
+    tool_calls: Vec<ToolCall>,
 }
 
 impl Message {
     pub fn role(&self) -> &'static str {
         match self {
-            Message::Assistant(_) => "assistant",
+            Message::Assistant(_) | Message::ToolCalls { .. } => "assistant",
             Message::User(_) => "user",
             Message::System(_) => "system",
+            Message::Tool(_) => "tool",
         }
     }
 
     pub fn content(&self) -> Arc<str> {
         match self {
-            Message::Assistant(arc) | Message::User(arc) | Message::System(arc) => arc.clone(),
+            Message::Assistant(arc)
+            | Message::User(arc)
+            | Message::System(arc)
+            | Message::Tool(arc)
+            | Message::ToolCalls { content: arc, .. } => arc.clone(),
+        }
+    }
+
+    /// The tools the message calls, if it is an assistant turn that calls any.
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        match self {
+            Message::ToolCalls { tool_calls, .. } => tool_calls,
+            _ => &[],
+        }
+    }
+
+    /// The arguments of a `MESSAGE` instruction for the message,
+    /// with the content written with `quoting` where it can be.
+    pub(crate) fn arguments(&self, quoting: Quoting) -> String {
+        match self {
+            Message::ToolCalls {
+                content,
+                tool_calls,
+            } => {
+                let mut json = Map::new();
+                if !content.is_empty() {
+                    json.insert("content".to_string(), Value::from(&**content));
+                }
+                json.insert(
+                    "tool_calls".to_string(),
+                    tool_calls.iter().map(ToolCall::to_json).collect(),
+                );
+                format!("{} {}", self.role(), Value::Object(json))
+            }
+            message => format!("{} {}", message.role(), quoting.quote(&message.content())),
         }
     }
 }
 
+/// Writes the message like a `MESSAGE` instruction, without the keyword.
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.arguments(Quoting::Bare))
+    }
+}
+
 impl<'a> From<(MessageRole, &'a str)> for Message {
     fn from(value: (MessageRole, &'a str)) -> Self {
         let (role, message) = value;
@@ -44,6 +157,68 @@ impl<'a> From<(MessageRole, &'a str)> for Message {
             MessageRole::Assistant => Message::Assistant(message),
             MessageRole::User => Message::User(message),
             MessageRole::System => Message::System(message),
+            MessageRole::Tool => Message::Tool(message),
+        }
+    }
+}
+
+/// A [`Message`] in the shape the [Ollama API] uses,
+/// like `{"role": "user", "content": "hello"}`.
+///
+/// [Ollama API]: https://github.com/ollama/ollama/blob/main/docs/api.md
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ChatMessage {
+    /// Read an assistant message written like a [`Message::ToolCalls`]
+    /// in a Modelfile, a `{"tool_calls": [...]}` object, as tool calls.
+    ///
+    /// Only objects with a non-empty `tool_calls` list,
+    /// and nothing but the `content` besides, are tool calls.
+    /// Any other message is returned as it is.
+    pub fn read_tool_calls(self) -> Self {
+        if self.role != MessageRole::Assistant.to_string() || !self.tool_calls.is_empty() {
+            return self;
+        }
+
+        match serde_json::from_str::<ToolCallsContent>(self.content.trim()) {
+            Ok(calls) if !calls.tool_calls.is_empty() => ChatMessage {
+                role: self.role,
+                content: calls.content,
+                tool_calls: calls.tool_calls,
+            },
+            _ => self,
         }
     }
 }
+
+impl From<&Message> for ChatMessage {
+    fn from(message: &Message) -> Self {
+        ChatMessage {
+            role: message.role().to_string(),
+            content: message.content().to_string(),
+            tool_calls: message.tool_calls().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<ChatMessage> for Message {
+    type Error = strum::ParseError;
+
+    fn try_from(message: ChatMessage) -> Result<Self, Self::Error> {
+        let role: MessageRole = message.role.parse()?;
+        if role == MessageRole::Assistant && !message.tool_calls.is_empty() {
+            return Ok(Message::ToolCalls {
+                content: message.content.into(),
+                tool_calls: message.tool_calls,
+            });
+        }
+
+        Ok(Message::from((role, message.content.as_str())))
+    }
+}
@@ -0,0 +1,307 @@
+//! The model a [`super::Modelfile`] is built `FROM`.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    digest::Digest,
+    reference::{ModelReference, ReferenceError},
+};
+
+/// Represented by the `FROM` field in the [`crate::Modelfile`].
+///
+/// Keeps the text exactly as written,
+/// so [`Display`](std::fmt::Display) round-trips losslessly,
+/// along with what the text refers to, see [`ModelSource`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BaseModel {
+    text: String,
+    source: ModelSource,
+}
+
+/// What a [`BaseModel`] refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelSource {
+    /// A model in a registry, like `llama3.2:latest`.
+    Reference(ModelReference),
+    /// A blob, either `@sha256:<hex>`
+    /// or a path to a blob file named `sha256-<hex>`.
+    Blob(Digest),
+    /// A path to a GGUF file.
+    Gguf(PathBuf),
+    /// A path to a single safetensors file.
+    Safetensor(PathBuf),
+    /// A path to a directory, usually of safetensors files.
+    Directory(PathBuf),
+}
+
+impl BaseModel {
+    /// The `FROM` value exactly as written.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// What the `FROM` value refers to.
+    pub fn source(&self) -> &ModelSource {
+        &self.source
+    }
+}
+
+impl ModelSource {
+    fn parse(text: &str) -> Result<Self, ReferenceError> {
+        if let Some(digest) = text.strip_prefix('@') {
+            return digest.parse().map(ModelSource::Blob).map_err(|_| {
+                ReferenceError::InvalidPart {
+                    part: "digest",
+                    reference: text.to_string(),
+                }
+            });
+        }
+
+        if !is_path(text) {
+            return text.parse().map(ModelSource::Reference);
+        }
+
+        Ok(ModelSource::from_path(PathBuf::from(text)))
+    }
+
+    /// What a path refers to, by its file name.
+    fn from_path(path: PathBuf) -> Self {
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        if let Some(Ok(digest)) = file_name.map(Digest::from_str) {
+            return ModelSource::Blob(digest);
+        }
+
+        match file_extension(&path) {
+            Some("gguf") => ModelSource::Gguf(path),
+            Some("safetensors") => ModelSource::Safetensor(path),
+            _ => ModelSource::Directory(path),
+        }
+    }
+}
+
+/// `true` if `text` looks like a file path rather than a model reference.
+fn is_path(text: &str) -> bool {
+    let is_drive = text.as_bytes().get(1) == Some(&b':')
+        && text.chars().next().is_some_and(|c| c.is_ascii_alphabetic());
+
+    text.starts_with(['/', '.', '~', '\\'])
+        || text.contains('\\')
+        || is_drive
+        || matches!(
+            file_extension(Path::new(text)),
+            Some("gguf" | "safetensors")
+        )
+}
+
+/// The extension of a path, unless it ends in a separator.
+fn file_extension(path: &Path) -> Option<&str> {
+    if path.as_os_str().to_string_lossy().ends_with(['/', '\\']) {
+        return None;
+    }
+
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+impl FromStr for BaseModel {
+    type Err = ReferenceError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(BaseModel {
+            text: text.to_string(),
+            source: ModelSource::parse(text)?,
+        })
+    }
+}
+
+impl TryFrom<String> for BaseModel {
+    type Error = ReferenceError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BaseModel> for String {
+    fn from(value: BaseModel) -> Self {
+        value.text
+    }
+}
+
+impl From<ModelReference> for BaseModel {
+    fn from(reference: ModelReference) -> Self {
+        BaseModel {
+            text: reference.to_string(),
+            source: ModelSource::Reference(reference),
+        }
+    }
+}
+
+/// A local file or directory, without parsing the path as Modelfile text,
+/// so paths with spaces or quotes are kept as they are.
+impl From<PathBuf> for BaseModel {
+    fn from(path: PathBuf) -> Self {
+        BaseModel {
+            text: path.display().to_string(),
+            source: ModelSource::from_path(path),
+        }
+    }
+}
+
+impl From<Digest> for BaseModel {
+    fn from(digest: Digest) -> Self {
+        BaseModel {
+            text: format!("@{digest}"),
+            source: ModelSource::Blob(digest),
+        }
+    }
+}
+
+impl AsRef<str> for BaseModel {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl std::fmt::Display for BaseModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+
+    #[test]
+    fn base_models_are_classified() {
+        let base_models: Vec<(String, Result<ModelSource, ReferenceError>)> = [
+            "llama3.1:latest",
+            "registry.example.com/team/model:tag",
+            "/mnt/space/ollama/models/blobs/sha256-ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+            "@sha256:ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+            "./model.gguf",
+            "model.gguf",
+            "./model.safetensors",
+            "~/models/llama-safetensors",
+            "C:\\models\\llama",
+            "not a model",
+        ]
+        .into_iter()
+        .map(|text| {
+            let source = text.parse().map(|model: BaseModel| {
+                assert_eq!(model.to_string(), text);
+                model.source().clone()
+            });
+            (text.to_string(), source)
+        })
+        .collect();
+
+        assert_debug_snapshot!(base_models, @r#"
+        [
+            (
+                "llama3.1:latest",
+                Ok(
+                    Reference(
+                        ModelReference {
+                            registry: "registry.ollama.ai",
+                            namespace: "library",
+                            name: "llama3.1",
+                            tag: "latest",
+                        },
+                    ),
+                ),
+            ),
+            (
+                "registry.example.com/team/model:tag",
+                Ok(
+                    Reference(
+                        ModelReference {
+                            registry: "registry.example.com",
+                            namespace: "team",
+                            name: "model",
+                            tag: "tag",
+                        },
+                    ),
+                ),
+            ),
+            (
+                "/mnt/space/ollama/models/blobs/sha256-ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+                Ok(
+                    Blob(
+                        Digest {
+                            hex: "ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+                        },
+                    ),
+                ),
+            ),
+            (
+                "@sha256:ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+                Ok(
+                    Blob(
+                        Digest {
+                            hex: "ff1d1fc78170d787ee1201778e2dd65ea211654ca5fb7d69b5a2e7b123a50373",
+                        },
+                    ),
+                ),
+            ),
+            (
+                "./model.gguf",
+                Ok(
+                    Gguf(
+                        "./model.gguf",
+                    ),
+                ),
+            ),
+            (
+                "model.gguf",
+                Ok(
+                    Gguf(
+                        "model.gguf",
+                    ),
+                ),
+            ),
+            (
+                "./model.safetensors",
+                Ok(
+                    Safetensor(
+                        "./model.safetensors",
+                    ),
+                ),
+            ),
+            (
+                "~/models/llama-safetensors",
+                Ok(
+                    Directory(
+                        "~/models/llama-safetensors",
+                    ),
+                ),
+            ),
+            (
+                "C:\\models\\llama",
+                Ok(
+                    Directory(
+                        "C:\\models\\llama",
+                    ),
+                ),
+            ),
+            (
+                "not a model",
+                Err(
+                    InvalidPart {
+                        part: "name",
+                        reference: "not a model",
+                    },
+                ),
+            ),
+        ]
+        "#);
+    }
+}
@@ -7,7 +7,9 @@ use crate::message::Message;
 
 use super::{
     error::ModelfileError,
-    instruction::{Adapter, BaseModel, License, Messages, Parameters, SystemMessage, Template},
+    instruction::{
+        Adapter, BaseModel, License, Messages, Parameters, Requires, SystemMessage, Template,
+    },
     Instruction, Modelfile, Parameter,
 };
 
@@ -19,10 +21,14 @@ use super::{
 #[derive(Clone, Debug, Default)]
 pub struct ModelfileBuilder {
     pub from: Option<BaseModel>,
+    /// Every `FROM` after the first.
+    pub layers: Vec<BaseModel>,
+    pub requires: Option<Requires>,
     pub parameters: Parameters,
     pub template: Option<Template>,
     pub system: Option<SystemMessage>,
-    pub adapter: Option<Adapter>,
+    /// LoRA adapters, applied in order.
+    pub adapters: Vec<Adapter>,
     pub license: Option<License>,
     pub messages: Messages,
 }
@@ -31,10 +37,12 @@ impl ModelfileBuilder {
     pub fn build(self) -> Result<Modelfile, ModelfileError> {
         let ModelfileBuilder {
             from,
+            layers,
+            requires,
             parameters,
             template,
             system,
-            adapter,
+            adapters,
             license,
             messages,
         } = self;
@@ -44,10 +52,12 @@ impl ModelfileBuilder {
         if let Some(from) = from {
             Ok(Modelfile {
                 from,
+                layers,
+                requires,
                 parameters,
                 template,
                 system,
-                adapter,
+                adapters,
                 license,
                 messages,
             })
@@ -60,27 +70,35 @@ impl ModelfileBuilder {
 
     pub fn instruction(self, instruction: Instruction) -> Result<Self, ModelfileError> {
         match instruction {
-            Instruction::From(model) => self.from(model),
+            Instruction::From(model) => Ok(self.base_model(model)),
             Instruction::Parameter(parameter) => Ok(self.parameter(parameter)),
             Instruction::Template(template) => self.template(template),
             Instruction::System(system) => self.system(system),
-            Instruction::Adapter(tensor_file) => self.adapter(tensor_file),
+            Instruction::Adapter(tensor_file) => Ok(self.adapter(tensor_file)),
             Instruction::License(license) => Ok(self.license(license)),
             Instruction::Message(message) => Ok(self.message(message)),
+            Instruction::Requires(requires) => self.requires(requires),
             Instruction::Skip => Ok(self),
         }
     }
 
-    pub fn from(mut self, input: impl ToString) -> Result<Self, ModelfileError> {
+    /// Set the base model,
+    /// or add a layer, like a projector, if there already is one.
+    pub fn from(self, input: impl ToString) -> Result<Self, ModelfileError> {
+        Ok(self.base_model(input.to_string().parse::<BaseModel>()?))
+    }
+
+    /// Like [`Self::from`], for a base model that doesn't need parsing,
+    /// like a path from [`BaseModel::from`].
+    pub fn base_model(mut self, model: impl Into<BaseModel>) -> Self {
+        let model = model.into();
         if self.from.is_some() {
-            Err(ModelfileError::Builder(format!(
-                "Modelfile can only have one FROM instruction: {}",
-                input.to_string()
-            )))
+            self.layers.push(model);
         } else {
-            self.from = Some(input.to_string().into());
-            Ok(self)
+            self.from = Some(model);
         }
+
+        self
     }
 
     pub fn parameter(mut self, parameter: Parameter) -> Self {
@@ -88,40 +106,71 @@ impl ModelfileBuilder {
         self
     }
 
-    pub fn template(mut self, template: Template) -> Result<Self, ModelfileError> {
-        if self.template.is_some() {
+    pub fn requires(mut self, requires: Requires) -> Result<Self, ModelfileError> {
+        if self.requires.is_some() {
             Err(ModelfileError::Builder(format!(
-                "Modelfile can only have one TEMPLATE instruction: {template}",
+                "Modelfile can only have one REQUIRES instruction: {requires}",
             )))
         } else {
-            self.template = Some(template);
+            self.requires = Some(requires);
             Ok(self)
         }
     }
 
-    pub fn system(mut self, system: impl ToString) -> Result<Self, ModelfileError> {
-        if self.system.is_some() {
+    pub fn template(mut self, template: Template) -> Result<Self, ModelfileError> {
+        if self.template.is_some() {
             Err(ModelfileError::Builder(format!(
-                "Modelfile can only have one SYSTEM instruction: {}",
-                system.to_string(),
+                "Modelfile can only have one TEMPLATE instruction: {template}",
             )))
         } else {
-            self.system = Some(system.to_string().into());
+            self.template = Some(template);
             Ok(self)
         }
     }
 
-    pub fn adapter(mut self, adapter: Adapter) -> Result<Self, ModelfileError> {
-        if self.adapter.is_some() {
+    pub fn system(mut self, system: impl AsRef<str>) -> Result<Self, ModelfileError> {
+        let system = SystemMessage::from(system.as_ref());
+        if self.system.is_some() {
             Err(ModelfileError::Builder(format!(
-                "Modelfile can only have one ADAPTER instruction: {adapter:?}",
+                "Modelfile can only have one SYSTEM instruction: {system}",
             )))
         } else {
-            self.adapter = Some(adapter);
+            self.system = Some(system);
             Ok(self)
         }
     }
 
+    /// Add an adapter, applied after the ones already added.
+    pub fn adapter(mut self, adapter: impl Into<Adapter>) -> Self {
+        self.adapters.push(adapter.into());
+        self
+    }
+
+    /// Remove an adapter, keeping the order of the others.
+    pub fn remove_adapter(mut self, adapter: &Adapter) -> Result<Self, ModelfileError> {
+        let index = self.adapter_index(adapter)?;
+        self.adapters.remove(index);
+        Ok(self)
+    }
+
+    /// Replace an adapter with another one in the same place.
+    pub fn replace_adapter(
+        mut self,
+        adapter: &Adapter,
+        replacement: impl Into<Adapter>,
+    ) -> Result<Self, ModelfileError> {
+        let index = self.adapter_index(adapter)?;
+        self.adapters[index] = replacement.into();
+        Ok(self)
+    }
+
+    fn adapter_index(&self, adapter: &Adapter) -> Result<usize, ModelfileError> {
+        self.adapters
+            .iter()
+            .position(|existing| existing == adapter)
+            .ok_or_else(|| ModelfileError::Builder(format!("Modelfile has no ADAPTER {adapter}")))
+    }
+
     pub fn license(mut self, license: impl AsRef<str>) -> Self {
         if let Some(existing) = &self.license {
             self.license = Some(existing.extend(license.as_ref()).into());
@@ -152,20 +201,24 @@ impl From<Modelfile> for ModelfileBuilder {
     fn from(value: Modelfile) -> Self {
         let Modelfile {
             from,
+            layers,
+            requires,
             parameters,
             template,
             system,
-            adapter,
+            adapters,
             license,
             messages,
         } = value;
 
         ModelfileBuilder {
             from: Some(from),
+            layers,
+            requires,
             parameters,
             template,
             system,
-            adapter,
+            adapters,
             license,
             messages,
         }
@@ -0,0 +1,313 @@
+//! A lossless concrete syntax tree of a [`Modelfile`].
+//!
+//! Unlike [`Modelfile`], which only keeps the meaning of each instruction,
+//! a [`SyntaxTree`] keeps comments, blank lines, keyword casing
+//! and the quoting style of every value.
+//! Printing an unmodified [`SyntaxTree`] reproduces its input byte for byte.
+//!
+//! ```
+//! use modelfile::modelfile::cst::SyntaxTree;
+//!
+//! let input = "# my model\nfrom llama3.2\n\nsystem \"be nice\"\n";
+//! let tree: SyntaxTree = input.parse().expect("should parse");
+//!
+//! assert_eq!(tree.to_string(), input);
+//! ```
+
+use std::str::FromStr;
+
+use super::{
+    error::ModelfileError,
+    instruction::Comment,
+    parser::{parse_all, spanned_instructions},
+    Instruction, Modelfile,
+};
+
+/// A lossless concrete syntax tree of a [`Modelfile`].
+///
+/// See the [module docs](self) for details.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub(crate) nodes: Vec<Node>,
+}
+
+impl SyntaxTree {
+    /// Every node in the tree, in source order.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The instruction nodes in the tree, in source order.
+    pub fn instructions(&self) -> impl Iterator<Item = &InstructionNode> {
+        self.nodes.iter().filter_map(|node| match node {
+            Node::Instruction(instruction) => Some(instruction),
+            Node::Comment(_) | Node::Whitespace(_) => None,
+        })
+    }
+
+    /// Build the [`Modelfile`] described by this tree.
+    pub fn to_modelfile(&self) -> Result<Modelfile, ModelfileError> {
+        let instructions: Vec<Instruction> = self
+            .instructions()
+            .map(|node| node.instruction.clone())
+            .collect();
+
+        instructions.try_into()
+    }
+}
+
+impl FromStr for SyntaxTree {
+    type Err = ModelfileError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let nodes = parse_all(source, spanned_instructions)?
+            .into_iter()
+            .flat_map(|instruction| {
+                let text = instruction.span.text(source);
+                match instruction.into_inner() {
+                    Instruction::Skip => trivia(text),
+                    instruction => vec![Node::Instruction(InstructionNode::new(text, instruction))],
+                }
+            })
+            .collect();
+
+        Ok(SyntaxTree { nodes })
+    }
+}
+
+impl std::fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.nodes.iter().try_for_each(|node| write!(f, "{node}"))
+    }
+}
+
+/// A single piece of a [`SyntaxTree`].
+#[derive(Debug, Clone, PartialEq, derive_more::Display)]
+pub enum Node {
+    /// A `#` comment, up to but not including the line ending.
+    #[display("#{_0}")]
+    Comment(Comment),
+    /// Blank space between other nodes, including line endings.
+    Whitespace(String),
+    /// An instruction and its arguments, exactly as written.
+    Instruction(InstructionNode),
+}
+
+/// Split the text of skipped lines into comments and whitespace.
+fn trivia(mut text: &str) -> Vec<Node> {
+    let mut nodes = Vec::new();
+
+    while !text.is_empty() {
+        let (node, rest) = if let Some(comment) = text.strip_prefix('#') {
+            let end = comment.find(['\r', '\n']).unwrap_or(comment.len());
+            (Node::Comment(comment[..end].into()), &comment[end..])
+        } else {
+            let end = text
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(text.len());
+            (Node::Whitespace(text[..end].to_string()), &text[end..])
+        };
+
+        nodes.push(node);
+        text = rest;
+    }
+
+    nodes
+}
+
+/// How a value was quoted in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quoting {
+    /// A plain value running to the end of the line.
+    Bare,
+    /// A value in `"double quotes"`.
+    Single,
+    /// A value in `"""triple quotes"""`.
+    Triple,
+}
+
+const TRIPLE: &str = r#"""""#;
+
+impl Quoting {
+    fn detect(value: &str) -> Self {
+        if value.len() >= 2 * TRIPLE.len() && value.starts_with(TRIPLE) && value.ends_with(TRIPLE) {
+            Quoting::Triple
+        } else if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            Quoting::Single
+        } else {
+            Quoting::Bare
+        }
+    }
+
+    /// Write `text` with this quoting style,
+    /// falling back to a style that can represent the text
+    /// when this one can't.
+    pub fn quote(self, text: &str) -> String {
+        let fits_single = !text.contains('"');
+        let fits_bare = fits_single && !text.contains(['\r', '\n']) && text.trim() == text;
+
+        match self {
+            Quoting::Bare if fits_bare => text.to_string(),
+            Quoting::Bare | Quoting::Single if fits_single => format!("\"{text}\""),
+            _ => format!("{TRIPLE}{text}{TRIPLE}"),
+        }
+    }
+}
+
+/// An instruction in a [`SyntaxTree`],
+/// with the exact text it was written as.
+#[derive(Debug, Clone, PartialEq, derive_more::Display)]
+#[display("{keyword}{separator}{arguments}")]
+pub struct InstructionNode {
+    pub(crate) keyword: String,
+    pub(crate) separator: String,
+    pub(crate) arguments: String,
+    pub(crate) instruction: Instruction,
+}
+
+impl InstructionNode {
+    pub(crate) fn from_parts(
+        keyword: impl ToString,
+        separator: impl ToString,
+        arguments: impl ToString,
+        instruction: Instruction,
+    ) -> Self {
+        InstructionNode {
+            keyword: keyword.to_string(),
+            separator: separator.to_string(),
+            arguments: arguments.to_string(),
+            instruction,
+        }
+    }
+
+    fn new(text: &str, instruction: Instruction) -> Self {
+        let keyword_end = text
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(text.len());
+        let (keyword, rest) = text.split_at(keyword_end);
+        let separator_end = rest
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(rest.len());
+        let (separator, arguments) = rest.split_at(separator_end);
+
+        InstructionNode {
+            keyword: keyword.to_string(),
+            separator: separator.to_string(),
+            arguments: arguments.to_string(),
+            instruction,
+        }
+    }
+
+    /// The instruction keyword as written, like `FROM` or `from`.
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// The whitespace between the keyword and the arguments.
+    pub fn separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Everything after the keyword and separator, as written.
+    pub fn arguments(&self) -> &str {
+        &self.arguments
+    }
+
+    /// The value of the instruction as written, including any quotes.
+    ///
+    /// This is the same as [`Self::arguments`],
+    /// except for `PARAMETER` and `MESSAGE`,
+    /// where the parameter name or message role is left out.
+    pub fn value(&self) -> &str {
+        match self.instruction {
+            Instruction::Parameter(_) | Instruction::Message(_) => {
+                let name_end = self
+                    .arguments
+                    .find(|c: char| c.is_whitespace() || c == '"')
+                    .unwrap_or(self.arguments.len());
+                self.arguments[name_end..].trim_start()
+            }
+            _ => &self.arguments,
+        }
+    }
+
+    /// How [`Self::value`] was quoted.
+    pub fn quoting(&self) -> Quoting {
+        Quoting::detect(self.value())
+    }
+
+    /// The parsed instruction.
+    pub fn instruction(&self) -> &Instruction {
+        &self.instruction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use crate::modelfile::test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR};
+
+    use super::*;
+
+    #[test]
+    fn modelfiles_round_trip_byte_for_byte() {
+        for TestData { path, contents } in load_modelfiles(TEST_GOOD_DATA_DIR) {
+            dbg!(&path);
+            let tree: SyntaxTree = contents.parse().expect("should parse syntax tree");
+
+            assert_eq!(tree.to_string(), contents);
+            tree.to_modelfile()
+                .expect("should build Modelfile from syntax tree");
+        }
+    }
+
+    #[test]
+    fn trivia_and_quoting_are_kept() {
+        let input = "# header comment\n\
+            from llama3.2\n\
+            \n\
+            # the system prompt\n\
+            System \"\"\"You are\n  a helpful assistant.\"\"\"\n\
+            PARAMETER\tstop \"<|eot_id|>\"\n\
+            TEMPLATE {{ .Prompt }}\r\n\
+            MESSAGE user \"hello\"";
+
+        let tree: SyntaxTree = input.parse().expect("should parse syntax tree");
+
+        assert_eq!(tree.to_string(), input);
+
+        let nodes: Vec<String> = tree
+            .nodes()
+            .iter()
+            .map(|node| match node {
+                Node::Comment(comment) => format!("Comment({comment})"),
+                Node::Whitespace(space) => format!("Whitespace({space:?})"),
+                Node::Instruction(instruction) => format!(
+                    "{}({:?}, {:?})",
+                    instruction.keyword(),
+                    instruction.separator(),
+                    instruction.quoting(),
+                ),
+            })
+            .collect();
+
+        assert_debug_snapshot!(nodes, @r#"
+        [
+            "Comment( header comment)",
+            "Whitespace(\"\\n\")",
+            "from(\" \", Bare)",
+            "Whitespace(\"\\n\\n\")",
+            "Comment( the system prompt)",
+            "Whitespace(\"\\n\")",
+            "System(\" \", Triple)",
+            "Whitespace(\"\\n\")",
+            "PARAMETER(\"\\t\", Single)",
+            "Whitespace(\"\\n\")",
+            "TEMPLATE(\" \", Bare)",
+            "Whitespace(\"\\r\\n\")",
+            "MESSAGE(\" \", Single)",
+        ]
+        "#);
+    }
+}
@@ -0,0 +1,442 @@
+//! Edit a [`Modelfile`] without reformatting it.
+//!
+//! [`ModelfileDocument`] wraps a [`SyntaxTree`],
+//! so comments, blank lines, instruction order, keyword casing and quoting
+//! are kept for every line that isn't touched by an edit.
+//! Every edit is validated with the same rules as [`super::builder::ModelfileBuilder`],
+//! and an edit that would produce an invalid [`Modelfile`] leaves the document unchanged.
+//!
+//! ```
+//! use modelfile::modelfile::{edit::ModelfileDocument, Parameter};
+//!
+//! let input = "# tuned for long documents\nFROM llama3.2\nPARAMETER num_ctx 4096\n";
+//! let mut document: ModelfileDocument = input.parse().expect("should parse");
+//!
+//! document
+//!     .set_parameter(Parameter::NumCtx(8192))
+//!     .expect("should set parameter");
+//!
+//! assert_eq!(
+//!     document.to_string(),
+//!     "# tuned for long documents\nFROM llama3.2\nPARAMETER num_ctx 8192\n"
+//! );
+//! ```
+
+use std::str::FromStr;
+
+use super::{
+    cst::{InstructionNode, Node, Quoting, SyntaxTree},
+    error::ModelfileError,
+    Instruction, InstructionName, Modelfile, Multiline, Parameter, ParameterName,
+};
+
+/// A [`Modelfile`] that can be edited while keeping its formatting.
+///
+/// See the [module docs](self) for details.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelfileDocument {
+    tree: SyntaxTree,
+    modelfile: Modelfile,
+}
+
+impl ModelfileDocument {
+    /// The [`Modelfile`] described by the document.
+    pub fn modelfile(&self) -> &Modelfile {
+        &self.modelfile
+    }
+
+    /// The [`SyntaxTree`] of the document.
+    pub fn syntax_tree(&self) -> &SyntaxTree {
+        &self.tree
+    }
+
+    /// Replace the model in the first `FROM` instruction,
+    /// keeping any more `FROM` layers, like a projector.
+    pub fn set_from(&mut self, model: impl ToString) -> Result<(), ModelfileError> {
+        let instruction = Instruction::From(model.to_string().parse()?);
+        let mut nodes = self.tree.nodes.clone();
+
+        match matching(&nodes, |instruction| {
+            matches!(instruction, Instruction::From(_))
+        })
+        .first()
+        {
+            Some(&first) => {
+                if let Node::Instruction(node) = &mut nodes[first] {
+                    *node = replace(node, instruction);
+                }
+            }
+            None => insert(&mut nodes, instruction),
+        }
+
+        self.commit(nodes)
+    }
+
+    /// Set the `SYSTEM` message, adding the instruction if there is none.
+    pub fn set_system(&mut self, system: impl ToString) -> Result<(), ModelfileError> {
+        self.set(
+            |instruction| matches!(instruction, Instruction::System(_)),
+            Instruction::System(system.to_string().into()),
+        )
+    }
+
+    /// Remove the `SYSTEM` instruction.
+    ///
+    /// Returns `true` if there was one.
+    pub fn remove_system(&mut self) -> Result<bool, ModelfileError> {
+        self.remove(|instruction| matches!(instruction, Instruction::System(_)))
+            .map(|removed| removed > 0)
+    }
+
+    /// Set a parameter,
+    /// replacing every existing `PARAMETER` with the same name.
+    ///
+    /// The first existing parameter is changed in place.
+    /// If there is none, the parameter is added after the last `PARAMETER`.
+    pub fn set_parameter(&mut self, parameter: Parameter) -> Result<(), ModelfileError> {
+        let name = parameter.name().to_string();
+        self.set(
+            |instruction| {
+                matches!(instruction, Instruction::Parameter(existing) if existing.name() == name)
+            },
+            Instruction::Parameter(parameter),
+        )
+    }
+
+    /// Add a parameter after the last `PARAMETER`,
+    /// keeping existing parameters with the same name,
+    /// like an extra `stop` sequence.
+    pub fn add_parameter(&mut self, parameter: Parameter) -> Result<(), ModelfileError> {
+        let mut nodes = self.tree.nodes.clone();
+        insert(&mut nodes, Instruction::Parameter(parameter));
+        self.commit(nodes)
+    }
+
+    /// Remove every `PARAMETER` with the given name.
+    /// [`ParameterName::Other`] removes every parameter
+    /// this crate doesn't know about.
+    ///
+    /// Returns the number of parameters removed.
+    pub fn remove_parameter(&mut self, name: ParameterName) -> Result<usize, ModelfileError> {
+        self.remove(|instruction| has_parameter_name(instruction, name))
+    }
+
+    /// Replace the first instruction matching `predicate` in place
+    /// and remove any others,
+    /// or insert `instruction` if nothing matches.
+    fn set(
+        &mut self,
+        predicate: impl Fn(&Instruction) -> bool,
+        instruction: Instruction,
+    ) -> Result<(), ModelfileError> {
+        let mut nodes = self.tree.nodes.clone();
+        let matches = matching(&nodes, &predicate);
+
+        match matches.split_first() {
+            Some((&first, rest)) => {
+                for &index in rest.iter().rev() {
+                    remove_line(&mut nodes, index);
+                }
+                if let Node::Instruction(node) = &mut nodes[first] {
+                    *node = replace(node, instruction);
+                }
+            }
+            None => insert(&mut nodes, instruction),
+        }
+
+        self.commit(nodes)
+    }
+
+    fn remove(
+        &mut self,
+        predicate: impl Fn(&Instruction) -> bool,
+    ) -> Result<usize, ModelfileError> {
+        let mut nodes = self.tree.nodes.clone();
+        let matches = matching(&nodes, &predicate);
+
+        for &index in matches.iter().rev() {
+            remove_line(&mut nodes, index);
+        }
+
+        self.commit(nodes).map(|()| matches.len())
+    }
+
+    /// Validate the edited nodes and replace the document with them.
+    fn commit(&mut self, nodes: Vec<Node>) -> Result<(), ModelfileError> {
+        let tree = SyntaxTree { nodes };
+        self.modelfile = tree.to_modelfile()?;
+        self.tree = tree;
+        Ok(())
+    }
+}
+
+impl FromStr for ModelfileDocument {
+    type Err = ModelfileError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let tree: SyntaxTree = input.parse()?;
+        let modelfile = tree.to_modelfile()?;
+
+        Ok(ModelfileDocument { tree, modelfile })
+    }
+}
+
+impl std::fmt::Display for ModelfileDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tree)
+    }
+}
+
+fn has_parameter_name(instruction: &Instruction, name: ParameterName) -> bool {
+    matches!(instruction, Instruction::Parameter(parameter) if ParameterName::from(parameter) == name)
+}
+
+/// The indices of the instruction nodes matching `predicate`.
+fn matching(nodes: &[Node], predicate: impl Fn(&Instruction) -> bool) -> Vec<usize> {
+    nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node)| match node {
+            Node::Instruction(node) if predicate(&node.instruction) => Some(index),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A new node for `instruction` that keeps the formatting of `existing`.
+fn replace(existing: &InstructionNode, instruction: Instruction) -> InstructionNode {
+    let arguments = arguments(&instruction, existing.quoting());
+    InstructionNode::from_parts(
+        existing.keyword(),
+        existing.separator(),
+        arguments,
+        instruction,
+    )
+}
+
+/// Insert `instruction` on a new line
+/// after the last instruction of the same kind,
+/// or after the `FROM` instruction if there is none.
+fn insert(nodes: &mut Vec<Node>, instruction: Instruction) {
+    let name = InstructionName::from(&instruction);
+    let instruction_name = |node: &Node| match node {
+        Node::Instruction(node) => Some(InstructionName::from(&node.instruction)),
+        Node::Comment(_) | Node::Whitespace(_) => None,
+    };
+
+    let anchor = nodes
+        .iter()
+        .rposition(|node| instruction_name(node) == Some(name))
+        .or_else(|| {
+            nodes
+                .iter()
+                .position(|node| instruction_name(node) == Some(InstructionName::From))
+        });
+
+    // follow the keyword casing of the file
+    let lowercase = match anchor.map(|index| &nodes[index]) {
+        Some(Node::Instruction(node)) => node.keyword().chars().all(|c| c.is_ascii_lowercase()),
+        _ => false,
+    };
+    let keyword = name.to_string();
+    let keyword = if lowercase {
+        keyword.to_lowercase()
+    } else {
+        keyword.to_uppercase()
+    };
+
+    let arguments = arguments(&instruction, Quoting::Bare);
+    let node = Node::Instruction(InstructionNode::from_parts(
+        keyword,
+        " ",
+        arguments,
+        instruction,
+    ));
+
+    match anchor {
+        Some(index) => {
+            nodes.insert(index + 1, node);
+            nodes.insert(index + 1, Node::Whitespace("\n".to_string()));
+        }
+        None => {
+            if nodes
+                .last()
+                .is_some_and(|last| !last.to_string().ends_with('\n'))
+            {
+                nodes.push(Node::Whitespace("\n".to_string()));
+            }
+            nodes.push(node);
+            nodes.push(Node::Whitespace("\n".to_string()));
+        }
+    }
+}
+
+/// Remove the instruction node at `index` along with its line ending,
+/// so no empty line is left behind.
+fn remove_line(nodes: &mut Vec<Node>, index: usize) {
+    nodes.remove(index);
+
+    if let Some(Node::Whitespace(space)) = nodes.get_mut(index) {
+        let line_end = space.find('\n').map(|newline| newline + 1);
+        match line_end {
+            Some(end) if end == space.len() => {
+                nodes.remove(index);
+            }
+            Some(end) => {
+                space.drain(..end);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Write the arguments of `instruction`,
+/// using `quoting` for free text values where possible.
+fn arguments(instruction: &Instruction, quoting: Quoting) -> String {
+    let quote = |text: &Multiline| quoting.quote(text.as_ref());
+
+    match instruction {
+        Instruction::Skip => String::new(),
+        Instruction::From(model) => model.to_string(),
+        Instruction::Parameter(parameter) => parameter.arguments(quoting),
+        Instruction::Template(template) => quote(template),
+        Instruction::System(system) => quote(system),
+        Instruction::Adapter(adapter) => adapter.to_string(),
+        Instruction::Requires(requires) => requires.to_string(),
+        Instruction::License(license) => quote(license),
+        Instruction::Message(message) => message.arguments(quoting),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+
+    use crate::modelfile::test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR};
+
+    use super::*;
+
+    const INPUT: &str = "# a hand written Modelfile\n\
+        from llama3.2\n\
+        \n\
+        # sampling\n\
+        parameter temperature 0.7\n\
+        parameter stop <|eot_id|>\n\
+        parameter num_ctx 4096\n\
+        \n\
+        system \"You are a helpful assistant.\"\n";
+
+    #[test]
+    fn edits_keep_untouched_lines() {
+        let mut document: ModelfileDocument = INPUT.parse().expect("should parse document");
+
+        document
+            .set_parameter(Parameter::NumCtx(8192))
+            .expect("should set num_ctx");
+        document
+            .set_parameter(Parameter::TopK(20))
+            .expect("should add top_k");
+        document
+            .add_parameter(Parameter::Stop("<|end_header_id|>".into()))
+            .expect("should add a second stop");
+        document
+            .remove_parameter(ParameterName::Temperature)
+            .expect("should remove temperature");
+        document
+            .set_system("You are a pirate.\nAnswer like one.")
+            .expect("should set system");
+        document.set_from("llama3.1").expect("should set FROM");
+
+        assert_snapshot!(document, @r#"
+        # a hand written Modelfile
+        from llama3.1
+
+        # sampling
+        parameter stop <|eot_id|>
+        parameter num_ctx 8192
+        parameter top_k 20
+        parameter stop <|end_header_id|>
+
+        system "You are a pirate.
+        Answer like one."
+        "#);
+
+        assert_eq!(
+            document.modelfile().render(),
+            document
+                .to_string()
+                .parse::<Modelfile>()
+                .expect("should parse edited document")
+                .render()
+        );
+    }
+
+    #[test]
+    fn missing_instructions_are_added_after_from() {
+        let mut document: ModelfileDocument = "FROM llama3.2".parse().expect("should parse");
+
+        document.set_system("be brief").expect("should set system");
+        document
+            .set_parameter(Parameter::Seed(42))
+            .expect("should set seed");
+
+        assert_snapshot!(document, @r"
+        FROM llama3.2
+        PARAMETER seed 42
+        SYSTEM be brief
+        ");
+
+        assert!(document.remove_system().expect("should remove system"));
+        assert!(!document.remove_system().expect("should remove nothing"));
+    }
+
+    #[test]
+    fn replaced_parameters_keep_their_quotes() {
+        let input = "FROM qwen2.5\nPARAMETER stop \"<|im_end|>\"\nPARAMETER num_ctx 4096\n";
+        let mut document: ModelfileDocument = input.parse().expect("should parse document");
+        assert_eq!(document.to_string(), input);
+
+        document
+            .set_parameter(Parameter::Stop("<|im_start|>".into()))
+            .expect("should set stop");
+        document
+            .set_parameter(Parameter::NumCtx(8192))
+            .expect("should set num_ctx");
+
+        assert_snapshot!(document, @r#"
+        FROM qwen2.5
+        PARAMETER stop "<|im_start|>"
+        PARAMETER num_ctx 8192
+        "#);
+        assert_eq!(
+            &document
+                .to_string()
+                .parse::<Modelfile>()
+                .expect("should parse edited document"),
+            document.modelfile()
+        );
+    }
+
+    #[test]
+    fn fixture_edits_only_touch_one_line() {
+        for TestData { path, contents } in load_modelfiles(TEST_GOOD_DATA_DIR) {
+            dbg!(&path);
+            let mut document: ModelfileDocument = contents.parse().expect("should parse fixture");
+
+            document
+                .set_from("llama3.2:latest")
+                .expect("should replace FROM");
+
+            let edited = document.to_string();
+            let changed: Vec<(&str, &str)> = contents
+                .lines()
+                .zip(edited.lines())
+                .filter(|(before, after)| before != after)
+                .collect();
+
+            assert_eq!(contents.lines().count(), edited.lines().count());
+            assert_eq!(changed.len(), 1);
+            assert_eq!(changed[0].1, "FROM llama3.2:latest");
+        }
+    }
+}
@@ -1,5 +1,14 @@
+use std::{ops::Range, path::PathBuf};
+
 use thiserror::Error;
 
+use crate::reference::ReferenceError;
+
+use super::{
+    requires::Requires,
+    span::{LineIndex, Span},
+};
+
 #[derive(Debug, Clone, Error)]
 #[non_exhaustive]
 pub enum ModelfileError {
@@ -8,6 +17,118 @@ pub enum ModelfileError {
     Builder(String),
 
     /// Error parsing [`super::Modelfile`]
-    #[error("unable to parse Modelfile")]
-    Parse(String),
+    #[error("{0}")]
+    Parse(Box<ParseDiagnostic>),
+
+    /// Error parsing the model in a `FROM` instruction.
+    #[error("invalid FROM: {0}")]
+    BaseModel(#[from] ReferenceError),
+
+    /// A local file that has to be uploaded as a blob
+    /// before it can be referenced by digest.
+    #[error("{} must be uploaded as a blob first", .0.display())]
+    LocalFile(PathBuf),
+
+    /// The Ollama server is a version the Modelfile `REQUIRES` doesn't allow.
+    #[error("Modelfile requires Ollama {requires}, but the server is version {version}")]
+    Incompatible {
+        requires: Requires,
+        version: semver::Version,
+    },
+}
+
+impl From<ParseDiagnostic> for ModelfileError {
+    fn from(value: ParseDiagnostic) -> Self {
+        ModelfileError::Parse(Box::new(value))
+    }
+}
+
+/// A located description of why a [`super::Modelfile`] could not be parsed.
+///
+/// The [`Display`](std::fmt::Display) implementation renders the diagnostic
+/// like a compiler error,
+/// with the offending source line and a caret under the failing column:
+///
+/// ```text
+/// error: invalid value for num_ctx, expected integer in PARAMETER
+///  --> 2:19
+///   |
+/// 2 | PARAMETER num_ctx lots
+///   |                   ^
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseDiagnostic {
+    /// The keyword of the instruction being parsed, like `PARAMETER`,
+    /// if the error happened inside an instruction.
+    pub instruction: Option<String>,
+    /// Why the input was rejected, like `invalid value for num_ctx`,
+    /// when there is more to say than what was expected.
+    pub reason: Option<String>,
+    /// A description of what the parser expected to find.
+    pub expected: String,
+    /// Where in the source the error occurred.
+    pub span: Span,
+    /// The full source line containing the start of [`Self::span`].
+    pub line: String,
+}
+
+impl ParseDiagnostic {
+    pub fn new(
+        source: &str,
+        range: Range<usize>,
+        instruction: Option<&str>,
+        expected: impl Into<String>,
+    ) -> Self {
+        let index = LineIndex::new(source);
+
+        ParseDiagnostic {
+            instruction: instruction.map(ToString::to_string),
+            reason: None,
+            expected: expected.into(),
+            line: index.line_text(range.start).to_string(),
+            span: index.span(range),
+        }
+    }
+
+    /// A single line summary of the diagnostic, without the source snippet.
+    pub fn message(&self) -> String {
+        let expected = match &self.instruction {
+            Some(instruction) => format!("expected {} in {instruction}", self.expected),
+            None => format!("expected {}", self.expected),
+        };
+        match &self.reason {
+            Some(reason) => format!("{reason}, {expected}"),
+            None => expected,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_snippet(f, &self.message(), &self.span, &self.line)
+    }
+}
+
+/// Write `message` like a compiler error,
+/// with `line`, the source line containing `span`, and a caret under its start.
+pub(crate) fn write_snippet(
+    f: &mut std::fmt::Formatter<'_>,
+    message: &str,
+    span: &Span,
+    line: &str,
+) -> std::fmt::Result {
+    let line_number = span.start.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    // keep tabs so the caret lines up with the source line
+    let indent: String = line
+        .chars()
+        .take(span.start.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    writeln!(f, "error: {message}")?;
+    writeln!(f, "{gutter}--> {}", span.start)?;
+    writeln!(f, "{gutter} |")?;
+    writeln!(f, "{line_number} | {line}")?;
+    write!(f, "{gutter} | {indent}^")
 }
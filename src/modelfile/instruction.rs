@@ -1,23 +1,24 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::Path};
 
 use derive_more::derive::{AsMut, AsRef, Deref, From, IntoIterator};
 use serde::{Deserialize, Serialize};
 
 use crate::Message;
 
-use super::{Multiline, Parameter, TensorFile};
+use super::{
+    error::ModelfileError,
+    parser::{parameter_lines, parse_all},
+    Multiline, Parameter, TensorFile,
+};
 
 /// Represented by a line beginning with a `#` in the [`crate::Modelfile`].
-#[derive(AsRef, Debug, Deref, Clone, From, Serialize, Deserialize, derive_more::Display)]
-#[from(forward)]
-pub struct Comment(String);
-
-/// Represented by the `FROM` field in the [`crate::Modelfile`].
 #[derive(
     AsRef, Debug, Deref, Clone, From, Serialize, Deserialize, derive_more::Display, PartialEq,
 )]
 #[from(forward)]
-pub struct BaseModel(String);
+pub struct Comment(String);
+
+pub use super::{base_model::BaseModel, requires::Requires};
 
 /// Represented by the `PARAMETER` fields in the [`crate::Modelfile`].
 #[derive(
@@ -44,6 +45,33 @@ impl Display for Parameters {
     }
 }
 
+impl Parameters {
+    /// Parse parameters written one per line as `name value`,
+    /// like the `parameters` field of an Ollama `/api/show` response,
+    /// where names are padded so the values line up.
+    pub fn from_lines(input: &str) -> Result<Self, ModelfileError> {
+        parse_all(input, parameter_lines).map(Parameters)
+    }
+
+    /// Write the parameters one per line as `name value`,
+    /// padding names like Ollama does.
+    /// The inverse of [`Parameters::from_lines`].
+    pub fn to_lines(&self) -> String {
+        self.0
+            .iter()
+            .map(|parameter| {
+                let value = match parameter {
+                    // Ollama quotes every string value here
+                    Parameter::Stop(stop) => format!("\"{stop}\""),
+                    _ => parameter.value(),
+                };
+                format!("{:<30} {value}", parameter.name())
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
 impl FromIterator<Parameter> for Parameters {
     fn from_iter<T: IntoIterator<Item = Parameter>>(iter: T) -> Self {
         let parameters: Vec<Parameter> = iter.into_iter().collect();
@@ -78,6 +106,7 @@ pub struct License(Multiline);
     AsRef, Debug, Deref, Clone, From, Serialize, Deserialize, derive_more::Display, PartialEq,
 )]
 #[from(forward)]
+#[as_ref(forward)]
 pub struct SystemMessage(Multiline);
 
 /// Represented by the `ADAPTER` field in the [`crate::Modelfile`].
@@ -87,6 +116,13 @@ pub struct SystemMessage(Multiline);
 #[from(forward)]
 pub struct Adapter(TensorFile);
 
+impl Adapter {
+    /// See [`TensorFile::resolve`].
+    pub fn resolve(self, base: impl AsRef<Path>) -> Self {
+        Adapter(self.0.resolve(base))
+    }
+}
+
 /// Represented by `MESSAGE` fields in the [`crate::Modelfile`].
 #[derive(
     AsRef,
@@ -0,0 +1,2222 @@
+//! Converting between Ollama's Go `TEMPLATE`s
+//! and the Jinja `chat_template`s of [Hugging Face] models.
+//!
+//! Only the common shapes of chat templates are converted:
+//! loops over the messages, conditions on their roles,
+//! `add_generation_prompt`, `bos_token` and `eos_token`.
+//! Anything else is reported as a [`ConvertErrorKind::Unsupported`]
+//! instead of being guessed at.
+//!
+//! Jinja templates are read and written for the way Hugging Face renders them,
+//! with `trim_blocks` and `lstrip_blocks` enabled.
+//!
+//! [Hugging Face]: https://huggingface.co/docs/transformers/main/en/chat_templating
+
+use std::ops::Range;
+
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use super::{
+    error::write_snippet,
+    instruction::Template,
+    prompt::snake_case,
+    span::{LineIndex, Span},
+    template::{
+        Branch, Command, Node, Operand, Pipeline, TemplateError, TemplateErrorKind, FIELDS,
+    },
+    Multiline,
+};
+
+/// How deep Jinja blocks and expressions can nest,
+/// well within the [`MAX_DEPTH`](super::template::MAX_DEPTH)
+/// of the Go template they are converted to.
+const MAX_DEPTH: usize = 100;
+
+/// Fields Go templates print as JSON, and Jinja needs `| tojson` for.
+const OBJECT_FIELDS: &[&str] = &[
+    "Messages",
+    "Tools",
+    "ToolCalls",
+    "Function",
+    "Arguments",
+    "Parameters",
+    "Properties",
+    "Items",
+    "Images",
+    "Required",
+    "Enum",
+    "Defs",
+];
+
+/// The chat format of a Hugging Face model, from its `tokenizer_config.json`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TokenizerConfig {
+    /// The default chat template,
+    /// from either a single template or a list of named ones.
+    #[serde(default, deserialize_with = "chat_template")]
+    pub chat_template: Option<String>,
+    #[serde(default, deserialize_with = "token")]
+    pub bos_token: Option<String>,
+    #[serde(default, deserialize_with = "token")]
+    pub eos_token: Option<String>,
+}
+
+fn chat_template<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    struct Named {
+        name: String,
+        template: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ChatTemplate {
+        Single(String),
+        Named(Vec<Named>),
+    }
+
+    Ok(match Option::<ChatTemplate>::deserialize(deserializer)? {
+        Some(ChatTemplate::Single(template)) => Some(template),
+        Some(ChatTemplate::Named(templates)) => templates
+            .into_iter()
+            .find(|named| named.name == "default")
+            .map(|named| named.template),
+        None => None,
+    })
+}
+
+/// Special tokens are either a string or an object with their `content`.
+fn token<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Token {
+        Content(String),
+        Added { content: String },
+    }
+
+    Ok(
+        Option::<Token>::deserialize(deserializer)?.map(|token| match token {
+            Token::Content(content) | Token::Added { content } => content,
+        }),
+    )
+}
+
+impl TokenizerConfig {
+    /// The chat template as a Go [`Template`], if the model has one.
+    pub fn to_template(&self) -> Result<Option<Template>, ConvertError> {
+        self.chat_template
+            .as_deref()
+            .map(|chat_template| Template::from_jinja(chat_template, self.eos_token.as_deref()))
+            .transpose()
+    }
+}
+
+/// A located description of why a template could not be converted.
+///
+/// Positions are relative to the start of the template being converted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertError {
+    pub kind: ConvertErrorKind,
+    pub span: Span,
+    /// The full template line containing the start of [`Self::span`].
+    pub line: String,
+}
+
+/// Why a template could not be converted.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConvertErrorKind {
+    /// The Go template doesn't parse.
+    #[error(transparent)]
+    Template(TemplateErrorKind),
+    /// The Jinja template doesn't parse.
+    #[error("{0}")]
+    Syntax(String),
+    /// The template uses something the other language can't express.
+    #[error("can't convert {0}")]
+    Unsupported(String),
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_snippet(f, &self.kind.to_string(), &self.span, &self.line)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl From<TemplateError> for ConvertError {
+    fn from(error: TemplateError) -> Self {
+        ConvertError {
+            kind: ConvertErrorKind::Template(error.kind),
+            span: error.span,
+            line: error.line,
+        }
+    }
+}
+
+type Failure = (ConvertErrorKind, Range<usize>);
+
+fn unsupported(construct: impl Into<String>, range: Range<usize>) -> Failure {
+    (ConvertErrorKind::Unsupported(construct.into()), range)
+}
+
+fn locate(source: &str, (kind, range): Failure) -> ConvertError {
+    let index = LineIndex::new(source);
+    ConvertError {
+        kind,
+        line: index.line_text(range.start).to_string(),
+        span: index.span(range),
+    }
+}
+
+impl Template {
+    /// Convert a Jinja chat template to a Go template.
+    ///
+    /// `bos_token` is left out, Ollama's tokenizer adds it,
+    /// and `add_generation_prompt` is always true.
+    pub fn from_jinja(chat_template: &str, eos_token: Option<&str>) -> Result<Self, ConvertError> {
+        let nodes = parse_jinja(chat_template).map_err(|failure| locate(chat_template, failure))?;
+
+        let mut converter = ToGo {
+            eos_token: eos_token.unwrap_or_default(),
+            out: String::new(),
+            scopes: vec![Vec::new()],
+            loops: Vec::new(),
+            depth: 0,
+        };
+        converter
+            .scope(&nodes)
+            .map_err(|failure| locate(chat_template, failure))?;
+
+        let template = Template::from(Multiline::from(converter.out));
+        template.parse()?;
+
+        Ok(template)
+    }
+
+    /// Convert the Go template to a Jinja chat template.
+    ///
+    /// Only templates using `.Messages` can be converted,
+    /// `.System` becomes the joined system messages like in Ollama.
+    pub fn to_jinja(&self) -> Result<String, ConvertError> {
+        let source: &str = AsRef::<str>::as_ref(&**self);
+        let ast = self.parse()?;
+
+        let mut converter = ToJinja {
+            out: String::new(),
+            after_block: false,
+            variables: vec![Vec::new()],
+            loops: 0,
+            system: false,
+            messages: false,
+        };
+        converter
+            .nodes(&ast.nodes, &Dot::Root)
+            .map_err(|(kind, span)| locate(source, (kind, span.range)))?;
+
+        if !converter.messages {
+            return Err(ConvertError {
+                kind: ConvertErrorKind::Unsupported("a template without .Messages".to_string()),
+                line: LineIndex::new(source).line_text(0).to_string(),
+                span: Span::new(source, 0..0),
+            });
+        }
+
+        let mut jinja = String::new();
+        if converter.system {
+            jinja.push_str(
+                "{% set system = messages | selectattr('role', 'equalto', 'system') \
+                 | map(attribute='content') | join('\\n\\n') %}",
+            );
+        }
+        jinja.push_str(&converter.out);
+        // Jinja drops a single trailing newline
+        if jinja.ends_with('\n') {
+            jinja.push('\n');
+        }
+
+        Ok(jinja)
+    }
+}
+
+/// Text, comments and tags of a Jinja template,
+/// with whitespace control already applied.
+#[derive(Debug)]
+enum Item {
+    Text(String),
+    Comment(String),
+    Output {
+        tokens: Vec<Token>,
+        range: Range<usize>,
+    },
+    Block {
+        tokens: Vec<Token>,
+        range: Range<usize>,
+    },
+}
+
+/// Split a Jinja template into [`Item`]s,
+/// applying `-` markers, `trim_blocks` and `lstrip_blocks`.
+fn scan(source: &str) -> Result<Vec<Item>, Failure> {
+    // Jinja drops a single trailing newline
+    let source = source
+        .strip_suffix("\r\n")
+        .or_else(|| source.strip_suffix('\n'))
+        .unwrap_or(source);
+
+    let mut items = Vec::new();
+    let mut rest = 0;
+    let mut trim_next = false;
+    let mut trim_newline = false;
+
+    loop {
+        let next = source[rest..]
+            .match_indices('{')
+            .map(|(found, _)| rest + found)
+            .find(|&found| matches!(source.as_bytes().get(found + 1), Some(b'{' | b'%' | b'#')));
+        let mut text = &source[rest..next.unwrap_or(source.len())];
+        if trim_next {
+            text = text.trim_start();
+        } else if trim_newline {
+            text = text
+                .strip_prefix("\r\n")
+                .or_else(|| text.strip_prefix('\n'))
+                .unwrap_or(text);
+        }
+
+        let Some(start) = next else {
+            if !text.is_empty() {
+                items.push(Item::Text(text.to_string()));
+            }
+            return Ok(items);
+        };
+
+        let kind = source.as_bytes()[start + 1];
+        let marker = source.as_bytes().get(start + 2).copied();
+        let is_block = kind != b'{';
+        if marker == Some(b'-') {
+            text = text.trim_end();
+        } else if is_block && marker != Some(b'+') {
+            // lstrip_blocks: whitespace before a tag at the start of a line
+            let line_start = text.rfind('\n').map(|newline| newline + 1);
+            if let Some(line_start) = line_start.or((rest == 0).then_some(0)) {
+                if text[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+                    text = &text[..line_start];
+                }
+            }
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text.to_string()));
+        }
+
+        let inner = start + 2 + usize::from(matches!(marker, Some(b'-' | b'+')));
+        let close = match kind {
+            b'{' => "}}",
+            b'%' => "%}",
+            _ => "#}",
+        };
+        let end = if kind == b'#' {
+            source[inner..].find(close).map(|found| inner + found)
+        } else {
+            tag_end(source, inner, close)
+        }
+        .ok_or_else(|| {
+            (
+                ConvertErrorKind::Syntax(format!("unclosed tag, expected `{close}`")),
+                start..source.len(),
+            )
+        })?;
+
+        let right = source[inner..end].chars().next_back();
+        let content_end = if matches!(right, Some('-' | '+')) && end > inner {
+            end - 1
+        } else {
+            end
+        };
+        trim_next = right == Some('-');
+        trim_newline = is_block && right != Some('+') && !trim_next;
+        let range = start..end + 2;
+
+        items.push(match kind {
+            b'{' => Item::Output {
+                tokens: lex(source, inner..content_end)?,
+                range,
+            },
+            b'%' => Item::Block {
+                tokens: lex(source, inner..content_end)?,
+                range,
+            },
+            _ => Item::Comment(source[inner..content_end].trim().to_string()),
+        });
+        rest = end + 2;
+    }
+}
+
+/// The offset of `close`, skipping over quoted strings.
+fn tag_end(source: &str, start: usize, close: &str) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut offset = start;
+
+    while offset < bytes.len() {
+        if source[offset..].starts_with(close) {
+            return Some(offset);
+        }
+        if let quote @ (b'"' | b'\'') = bytes[offset] {
+            offset += 1;
+            while offset < bytes.len() && bytes[offset] != quote {
+                if bytes[offset] == b'\\' {
+                    offset += 1;
+                }
+                offset += 1;
+            }
+        }
+        offset += 1;
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    String(String),
+    Integer(i64),
+    Float(String),
+    /// Operators and punctuation, like `==` or `[`.
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "//", "**", "<", ">", "+", "-", "*", "/", "%", "~", "|", ".", "[", "]",
+    "(", ")", "{", "}", ",", ":", "=",
+];
+
+fn lex(source: &str, range: Range<usize>) -> Result<Vec<Token>, Failure> {
+    let mut tokens = Vec::new();
+    let mut offset = range.start;
+
+    while offset < range.end {
+        let rest = &source[offset..range.end];
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+
+        if c.is_whitespace() {
+            offset += c.len_utf8();
+            continue;
+        }
+
+        let (token, len) = if c == '\'' || c == '"' {
+            string(rest, offset)?
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = rest[..len].replace('_', "");
+            match number.parse() {
+                Ok(integer) => (Token::Integer(integer), len),
+                Err(_) => (Token::Float(number), len),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (Token::Name(rest[..len].to_string()), len)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            (Token::Symbol(symbol), symbol.len())
+        } else {
+            return Err((
+                ConvertErrorKind::Syntax(format!("unexpected `{c}`")),
+                offset..offset + c.len_utf8(),
+            ));
+        };
+
+        tokens.push(token);
+        offset += len;
+    }
+
+    Ok(tokens)
+}
+
+/// A quoted string at the start of `rest`, and its length in the source.
+fn string(rest: &str, offset: usize) -> Result<(Token, usize), Failure> {
+    let mut chars = rest.char_indices();
+    let quote = chars.next().map(|(_, c)| c);
+    let mut text = String::new();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if Some(c) == quote => return Ok((Token::String(text), index + 1)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => text.push('\n'),
+                Some((_, 't')) => text.push('\t'),
+                Some((_, 'r')) => text.push('\r'),
+                Some((_, escaped)) => text.push(escaped),
+                None => break,
+            },
+            c => text.push(c),
+        }
+    }
+
+    Err((
+        ConvertErrorKind::Syntax("unterminated string".to_string()),
+        offset..offset + rest.len(),
+    ))
+}
+
+/// A part of a Jinja template.
+#[derive(Debug, Clone, PartialEq)]
+enum JinjaNode {
+    Text(String),
+    Comment(String),
+    Output(Expression, Range<usize>),
+    If {
+        branches: Vec<(Expression, Vec<JinjaNode>, Range<usize>)>,
+        otherwise: Option<Vec<JinjaNode>>,
+    },
+    For {
+        target: String,
+        iterable: Expression,
+        body: Vec<JinjaNode>,
+        otherwise: Option<Vec<JinjaNode>>,
+        range: Range<usize>,
+    },
+    Set {
+        name: String,
+        value: Expression,
+        range: Range<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    String(String),
+    Integer(i64),
+    Float(String),
+    Bool(bool),
+    None,
+    Name(String),
+    /// `value.name`, or `value['name']`
+    Attribute(Box<Expression>, String),
+    /// `value[index]`
+    Item(Box<Expression>, Box<Expression>),
+    Slice(
+        Box<Expression>,
+        Option<Box<Expression>>,
+        Option<Box<Expression>>,
+    ),
+    Call(Box<Expression>, Vec<Expression>),
+    Filter(Box<Expression>, String, Vec<Expression>),
+    /// `value is name`, or `value is not name`
+    Test(Box<Expression>, String, bool),
+    Not(Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    List(Vec<Expression>),
+}
+
+/// What ended a list of nodes.
+struct Terminator {
+    keyword: String,
+    tokens: Vec<Token>,
+    range: Range<usize>,
+}
+
+fn parse_jinja(source: &str) -> Result<Vec<JinjaNode>, Failure> {
+    let mut items = scan(source)?.into_iter();
+    let (nodes, terminator) = list(&mut items, 0)?;
+
+    match terminator {
+        Some(Terminator { keyword, range, .. }) => Err((
+            ConvertErrorKind::Syntax(format!("unexpected `{keyword}`")),
+            range,
+        )),
+        None => Ok(nodes),
+    }
+}
+
+/// Nodes up to the next `end…`, `else` or `elif`, inside `depth` blocks.
+fn list(
+    items: &mut std::vec::IntoIter<Item>,
+    depth: usize,
+) -> Result<(Vec<JinjaNode>, Option<Terminator>), Failure> {
+    let mut nodes = Vec::new();
+
+    while let Some(item) = items.next() {
+        let (tokens, range) = match item {
+            Item::Text(text) => {
+                nodes.push(JinjaNode::Text(text));
+                continue;
+            }
+            Item::Comment(comment) => {
+                nodes.push(JinjaNode::Comment(comment));
+                continue;
+            }
+            Item::Output { tokens, range } => {
+                let expression = Parser::new(&tokens, &range).expression_only()?;
+                nodes.push(JinjaNode::Output(expression, range));
+                continue;
+            }
+            Item::Block { tokens, range } => (tokens, range),
+        };
+
+        let (keyword, rest) = match tokens.split_first() {
+            Some((Token::Name(keyword), rest)) => (keyword.clone(), rest.to_vec()),
+            _ => {
+                return Err((
+                    ConvertErrorKind::Syntax("expected a statement".to_string()),
+                    range,
+                ))
+            }
+        };
+
+        match keyword.as_str() {
+            "endif" | "endfor" | "else" | "elif" | "endgeneration" => {
+                return Ok((
+                    nodes,
+                    Some(Terminator {
+                        keyword,
+                        tokens: rest,
+                        range,
+                    }),
+                ))
+            }
+            "if" => {
+                let depth = nest(depth, &range)?;
+                let mut branches = Vec::new();
+                let mut condition = Parser::new(&rest, &range).expression_only()?;
+                let mut branch_range = range.clone();
+                let otherwise = loop {
+                    let (body, terminator) = list(items, depth)?;
+                    branches.push((condition, body, branch_range));
+                    match terminator {
+                        Some(Terminator {
+                            keyword,
+                            tokens,
+                            range,
+                        }) if keyword == "elif" => {
+                            condition = Parser::new(&tokens, &range).expression_only()?;
+                            branch_range = range;
+                        }
+                        Some(Terminator { keyword, .. }) if keyword == "else" => {
+                            break Some(end(items, "endif", &range, depth)?);
+                        }
+                        Some(Terminator { keyword, .. }) if keyword == "endif" => break None,
+                        _ => return Err(unclosed("if", &range)),
+                    }
+                };
+                nodes.push(JinjaNode::If {
+                    branches,
+                    otherwise,
+                });
+            }
+            "for" => {
+                let (target, iterable) = match rest.as_slice() {
+                    [Token::Name(target), Token::Name(in_), iterable @ ..] if in_ == "in" => {
+                        (target.clone(), iterable)
+                    }
+                    _ => return Err(unsupported("this `for` loop", range)),
+                };
+                let iterable = Parser::new(iterable, &range).expression_only()?;
+                let depth = nest(depth, &range)?;
+                let (body, terminator) = list(items, depth)?;
+                let otherwise = match terminator {
+                    Some(Terminator { keyword, .. }) if keyword == "endfor" => None,
+                    Some(Terminator { keyword, .. }) if keyword == "else" => {
+                        Some(end(items, "endfor", &range, depth)?)
+                    }
+                    _ => return Err(unclosed("for", &range)),
+                };
+                nodes.push(JinjaNode::For {
+                    target,
+                    iterable,
+                    body,
+                    otherwise,
+                    range,
+                });
+            }
+            "set" => {
+                let (name, value) = match rest.as_slice() {
+                    [Token::Name(name), Token::Symbol("="), value @ ..] => (name.clone(), value),
+                    _ => return Err(unsupported("this `set`", range)),
+                };
+                let value = Parser::new(value, &range).expression_only()?;
+                nodes.push(JinjaNode::Set { name, value, range });
+            }
+            // only marks the assistant's text for training
+            "generation" => {
+                let depth = nest(depth, &range)?;
+                nodes.extend(end(items, "endgeneration", &range, depth)?);
+            }
+            keyword => return Err(unsupported(format!("`{{% {keyword} %}}`"), range)),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+/// Nodes up to `keyword`, closing the tag at `range`.
+fn end(
+    items: &mut std::vec::IntoIter<Item>,
+    keyword: &str,
+    range: &Range<usize>,
+    depth: usize,
+) -> Result<Vec<JinjaNode>, Failure> {
+    match list(items, depth)? {
+        (nodes, Some(terminator)) if terminator.keyword == keyword => Ok(nodes),
+        (_, Some(terminator)) => Err((
+            ConvertErrorKind::Syntax(format!("unexpected `{}`", terminator.keyword)),
+            terminator.range,
+        )),
+        (_, None) => Err(unclosed(keyword.trim_start_matches("end"), range)),
+    }
+}
+
+/// The depth inside a block opened at `range`, `depth` blocks deep.
+fn nest(depth: usize, range: &Range<usize>) -> Result<usize, Failure> {
+    if depth >= MAX_DEPTH {
+        return Err(unsupported("blocks nested this deep", range.clone()));
+    }
+
+    Ok(depth + 1)
+}
+
+fn unclosed(keyword: &str, range: &Range<usize>) -> Failure {
+    (
+        ConvertErrorKind::Syntax(format!("`{keyword}` is never closed")),
+        range.clone(),
+    )
+}
+
+/// A recursive descent parser for Jinja expressions.
+struct Parser<'t> {
+    tokens: &'t [Token],
+    position: usize,
+    range: Range<usize>,
+    /// Nodes around the expression being parsed,
+    /// counting every node of the enclosing parentheses
+    /// so it is never less than the depth of the tree.
+    depth: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn new(tokens: &'t [Token], range: &Range<usize>) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+            range: range.clone(),
+            depth: 0,
+        }
+    }
+
+    /// Count another node, as long as the expression isn't nested too deep.
+    fn deeper(&mut self) -> Result<(), Failure> {
+        if self.depth >= MAX_DEPTH {
+            return Err(unsupported(
+                "expressions nested this deep",
+                self.range.clone(),
+            ));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn error(&self, expected: &str) -> Failure {
+        let found = match self.peek() {
+            Some(Token::Name(name)) => format!("`{name}`"),
+            Some(Token::String(text)) => format!("{text:?}"),
+            Some(Token::Integer(integer)) => integer.to_string(),
+            Some(Token::Float(float)) => float.clone(),
+            Some(Token::Symbol(symbol)) => format!("`{symbol}`"),
+            None => "the end of the tag".to_string(),
+        };
+        (
+            ConvertErrorKind::Syntax(format!("expected {expected}, found {found}")),
+            self.range.clone(),
+        )
+    }
+
+    fn peek(&self) -> Option<&'t Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(found)) if found == name)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        self.position += usize::from(found);
+        found
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        let found = self.is_name(name);
+        self.position += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Failure> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{symbol}`")))
+        }
+    }
+
+    /// An expression taking up the whole tag.
+    fn expression_only(mut self) -> Result<Expression, Failure> {
+        let expression = self.expression()?;
+        match self.peek() {
+            Some(Token::Name(name)) if name == "if" => {
+                Err(unsupported("inline `if` expressions", self.range.clone()))
+            }
+            Some(_) => Err(self.error("the end of the tag")),
+            None => Ok(expression),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, Failure> {
+        let depth = self.depth;
+        self.deeper()?;
+        let mut left = self.and()?;
+        while self.eat_name("or") {
+            self.deeper()?;
+            left = Expression::Binary("or", Box::new(left), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.not()?;
+        while self.eat_name("and") {
+            self.deeper()?;
+            left = Expression::Binary("and", Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression, Failure> {
+        if self.eat_name("not") {
+            self.deeper()?;
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.concatenation()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol(operator @ ("==" | "!=" | "<" | "<=" | ">" | ">="))) => {
+                    self.position += 1;
+                    *operator
+                }
+                Some(Token::Name(name)) if name == "in" => {
+                    self.position += 1;
+                    "in"
+                }
+                Some(Token::Name(name))
+                    if name == "not"
+                        && matches!(self.tokens.get(self.position + 1), Some(Token::Name(name)) if name == "in") =>
+                {
+                    self.position += 2;
+                    "not in"
+                }
+                Some(Token::Name(name)) if name == "is" => {
+                    self.position += 1;
+                    let negated = self.eat_name("not");
+                    let Some(Token::Name(test)) = self.peek() else {
+                        return Err(self.error("a test name"));
+                    };
+                    self.position += 1;
+                    self.deeper()?;
+                    left = Expression::Test(Box::new(left), test.clone(), negated);
+                    continue;
+                }
+                _ => return Ok(left),
+            };
+            self.deeper()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.concatenation()?));
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.sum()?;
+        while self.eat("~") {
+            self.deeper()?;
+            left = Expression::Binary("~", Box::new(left), Box::new(self.sum()?));
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.product()?;
+        loop {
+            let operator = if self.eat("+") {
+                "+"
+            } else if self.eat("-") {
+                "-"
+            } else {
+                return Ok(left);
+            };
+            self.deeper()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol(operator @ ("*" | "/" | "//" | "%" | "**"))) => *operator,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            self.deeper()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, Failure> {
+        if self.eat("-") {
+            self.deeper()?;
+            return Ok(match self.unary()? {
+                Expression::Integer(integer) => Expression::Integer(-integer),
+                value => Expression::Binary("-", Box::new(Expression::Integer(0)), Box::new(value)),
+            });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expression, Failure> {
+        let mut value = self.primary()?;
+
+        loop {
+            if matches!(self.peek(), Some(Token::Symbol("." | "[" | "(" | "|"))) {
+                self.deeper()?;
+            }
+            if self.eat(".") {
+                let Some(Token::Name(name)) = self.peek() else {
+                    return Err(self.error("an attribute name"));
+                };
+                self.position += 1;
+                value = Expression::Attribute(Box::new(value), name.clone());
+            } else if self.eat("[") {
+                let start = if matches!(self.peek(), Some(Token::Symbol(":"))) {
+                    None
+                } else {
+                    Some(Box::new(self.expression()?))
+                };
+                if self.eat(":") {
+                    let end = if matches!(self.peek(), Some(Token::Symbol("]"))) {
+                        None
+                    } else {
+                        Some(Box::new(self.expression()?))
+                    };
+                    value = Expression::Slice(Box::new(value), start, end);
+                } else {
+                    value = match start.map(|start| *start) {
+                        Some(Expression::String(name)) => {
+                            Expression::Attribute(Box::new(value), name)
+                        }
+                        Some(index) => Expression::Item(Box::new(value), Box::new(index)),
+                        None => return Err(self.error("an index")),
+                    };
+                }
+                self.expect("]")?;
+            } else if self.eat("(") {
+                value = Expression::Call(Box::new(value), self.arguments()?);
+            } else if self.eat("|") {
+                let Some(Token::Name(filter)) = self.peek() else {
+                    return Err(self.error("a filter name"));
+                };
+                self.position += 1;
+                let arguments = if self.eat("(") {
+                    self.arguments()?
+                } else {
+                    Vec::new()
+                };
+                value = Expression::Filter(Box::new(value), filter.clone(), arguments);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Arguments up to the closing `)`.
+    fn arguments(&mut self) -> Result<Vec<Expression>, Failure> {
+        let mut arguments = Vec::new();
+        while !self.eat(")") {
+            if matches!(self.tokens.get(self.position + 1), Some(Token::Symbol("="))) {
+                return Err(unsupported("keyword arguments", self.range.clone()));
+            }
+            arguments.push(self.expression()?);
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn primary(&mut self) -> Result<Expression, Failure> {
+        let Some(token) = self.peek() else {
+            return Err(self.error("a value"));
+        };
+        self.position += 1;
+
+        Ok(match token {
+            Token::String(text) => {
+                // adjacent strings are joined
+                let mut text = text.clone();
+                while let Some(Token::String(more)) = self.peek() {
+                    text.push_str(more);
+                    self.position += 1;
+                }
+                Expression::String(text)
+            }
+            Token::Integer(integer) => Expression::Integer(*integer),
+            Token::Float(float) => Expression::Float(float.clone()),
+            Token::Name(name) => match name.as_str() {
+                "true" | "True" => Expression::Bool(true),
+                "false" | "False" => Expression::Bool(false),
+                "none" | "None" => Expression::None,
+                name => Expression::Name(name.to_string()),
+            },
+            Token::Symbol("(") => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            Token::Symbol("[") => {
+                let mut items = Vec::new();
+                while !self.eat("]") {
+                    items.push(self.expression()?);
+                    if !self.eat(",") {
+                        self.expect("]")?;
+                        break;
+                    }
+                }
+                Expression::List(items)
+            }
+            Token::Symbol(_) => {
+                self.position -= 1;
+                return Err(self.error("a value"));
+            }
+        })
+    }
+}
+
+/// A Go expression, either a single operand or a command that needs parentheses as an argument.
+enum Go {
+    Operand(String),
+    Command(String),
+}
+
+impl Go {
+    fn argument(self) -> String {
+        match self {
+            Go::Operand(operand) => operand,
+            Go::Command(command) => format!("({command})"),
+        }
+    }
+
+    fn pipeline(self) -> String {
+        match self {
+            Go::Operand(text) | Go::Command(text) => text,
+        }
+    }
+}
+
+/// A Jinja loop, as Go variables.
+struct Loop {
+    index: String,
+    /// The Go expression looped over.
+    iterable: String,
+}
+
+/// Writes Go template source from Jinja nodes.
+struct ToGo<'a> {
+    eos_token: &'a str,
+    out: String,
+    /// Jinja names declared as Go variables, innermost scope last.
+    scopes: Vec<Vec<String>>,
+    loops: Vec<Loop>,
+    /// Blocks around the nodes being converted.
+    depth: usize,
+}
+
+impl ToGo<'_> {
+    /// Nodes in a new scope, declaring the variables Jinja would set inside `if`s up front,
+    /// since Jinja's `if` doesn't have its own scope but Go's does.
+    fn scope(&mut self, nodes: &[JinjaNode]) -> Result<(), Failure> {
+        let mut hoisted = Vec::new();
+        for node in nodes {
+            if let JinjaNode::If {
+                branches,
+                otherwise,
+            } = node
+            {
+                let bodies = branches
+                    .iter()
+                    .map(|(_, body, _)| body)
+                    .chain(otherwise.as_ref());
+                for body in bodies {
+                    set_in_ifs(body, &mut hoisted);
+                }
+            }
+        }
+
+        let mut scope = Vec::new();
+        for name in hoisted {
+            if scope.contains(&name) {
+                continue;
+            }
+            // start from the outer value, like Jinja
+            let initial = if self.is_declared(&name) {
+                format!("${name}")
+            } else {
+                "\"\"".to_string()
+            };
+            self.out
+                .push_str(&format!("{{{{ ${name} := {initial} }}}}"));
+            scope.push(name);
+        }
+
+        self.scopes.push(scope);
+        let result = self.nodes(nodes);
+        self.scopes.pop();
+        result
+    }
+
+    /// Convert nodes inside the block opened at `range`.
+    fn nested(
+        &mut self,
+        range: &Range<usize>,
+        convert: impl FnOnce(&mut Self) -> Result<(), Failure>,
+    ) -> Result<(), Failure> {
+        if self.depth >= MAX_DEPTH {
+            return Err(unsupported("blocks nested this deep", range.clone()));
+        }
+
+        self.depth += 1;
+        let result = convert(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.iter().any(|declared| declared == name))
+    }
+
+    fn nodes(&mut self, nodes: &[JinjaNode]) -> Result<(), Failure> {
+        for node in nodes {
+            match node {
+                JinjaNode::Text(text) => self.text(text),
+                JinjaNode::Comment(comment) => {
+                    if !comment.contains("*/") {
+                        self.out.push_str(&format!("{{{{/* {comment} */}}}}"));
+                    }
+                }
+                JinjaNode::Output(expression, range) => self.output(expression, range)?,
+                JinjaNode::If {
+                    branches,
+                    otherwise,
+                } => self.branches(branches, otherwise.as_deref())?,
+                JinjaNode::For {
+                    target,
+                    iterable,
+                    body,
+                    otherwise,
+                    range,
+                } => {
+                    let iterable = self.expression(iterable, range)?.argument();
+                    let index = ["$i", "$j", "$k", "$l"]
+                        .get(self.loops.len())
+                        .ok_or_else(|| unsupported("loops nested this deep", range.clone()))?;
+                    self.out
+                        .push_str(&format!("{{{{ range {index}, ${target} := {iterable} }}}}"));
+
+                    self.loops.push(Loop {
+                        index: index.to_string(),
+                        iterable,
+                    });
+                    self.scopes.push(vec![target.clone()]);
+                    let result = self.nested(range, |converter| converter.scope(body));
+                    self.scopes.pop();
+                    self.loops.pop();
+                    result?;
+
+                    if let Some(otherwise) = otherwise {
+                        self.out.push_str("{{ else }}");
+                        self.nested(range, |converter| converter.scope(otherwise))?;
+                    }
+                    self.out.push_str("{{ end }}");
+                }
+                JinjaNode::Set { name, value, range } => {
+                    let value = self.expression(value, range)?.pipeline();
+                    let declared = self.scopes.last().is_some_and(|scope| scope.contains(name));
+                    let operator = if declared { "=" } else { ":=" };
+                    self.out
+                        .push_str(&format!("{{{{ ${name} {operator} {value} }}}}"));
+                    if let Some(scope) = self.scopes.last_mut().filter(|_| !declared) {
+                        scope.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Text, with Go's delimiters quoted.
+    fn text(&mut self, text: &str) {
+        let mut parts = text.split("{{");
+        if let Some(first) = parts.next() {
+            self.out.push_str(first);
+        }
+        for part in parts {
+            self.out.push_str("{{ \"{{\" }}");
+            self.out.push_str(part);
+        }
+    }
+
+    /// `if`, `elif` and `else`, leaving out branches decided by `add_generation_prompt`.
+    fn branches(
+        &mut self,
+        branches: &[(Expression, Vec<JinjaNode>, Range<usize>)],
+        otherwise: Option<&[JinjaNode]>,
+    ) -> Result<(), Failure> {
+        let mut open = false;
+        let mut last = None;
+
+        for (condition, body, range) in branches {
+            last = Some(range);
+            let condition = &fold(condition);
+            match constant(condition) {
+                Some(false) => continue,
+                Some(true) if !open => {
+                    // the first branch that is taken, the rest never are
+                    return self.nested(range, |converter| converter.nodes(body));
+                }
+                _ => {}
+            }
+
+            let condition = self.expression(condition, range)?.pipeline();
+            let keyword = if open { "else if" } else { "if" };
+            self.out
+                .push_str(&format!("{{{{ {keyword} {condition} }}}}"));
+            open = true;
+            self.nested(range, |converter| converter.nodes(body))?;
+        }
+
+        if let (Some(otherwise), Some(range)) = (otherwise, last) {
+            if open {
+                self.out.push_str("{{ else }}");
+            }
+            self.nested(range, |converter| converter.nodes(otherwise))?;
+        }
+        if open {
+            self.out.push_str("{{ end }}");
+        }
+
+        Ok(())
+    }
+
+    /// `{{ value }}`, writing strings joined with `+` or `~` one by one.
+    fn output(&mut self, expression: &Expression, range: &Range<usize>) -> Result<(), Failure> {
+        let mut parts = Vec::new();
+        if is_text(expression) {
+            concatenated(expression, &mut parts);
+        } else {
+            parts.push(expression);
+        }
+
+        for part in parts {
+            match part {
+                Expression::String(text) => self.text(text),
+                Expression::Name(name) if name == "bos_token" => {}
+                Expression::Name(name) if name == "eos_token" && !self.is_declared(name) => {
+                    let eos_token = self.eos_token;
+                    self.text(eos_token);
+                }
+                part => {
+                    let value = self.expression(part, range)?.pipeline();
+                    self.out.push_str(&format!("{{{{ {value} }}}}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expression(&self, expression: &Expression, range: &Range<usize>) -> Result<Go, Failure> {
+        let argument = |expression| self.expression(expression, range).map(Go::argument);
+
+        Ok(match expression {
+            Expression::String(text) => Go::Operand(go_string(text)),
+            Expression::Integer(integer) => Go::Operand(integer.to_string()),
+            Expression::Float(float) => Go::Operand(float.clone()),
+            Expression::Bool(value) => Go::Operand(value.to_string()),
+            Expression::Name(name) if self.is_declared(name) => Go::Operand(format!("${name}")),
+            Expression::Name(name) => Go::Operand(
+                match name.as_str() {
+                    "messages" => "$.Messages",
+                    "tools" => "$.Tools",
+                    "add_generation_prompt" => "true",
+                    "bos_token" => "\"\"",
+                    "eos_token" => return Ok(Go::Operand(go_string(self.eos_token))),
+                    name => {
+                        return Err(unsupported(format!("the variable `{name}`"), range.clone()))
+                    }
+                }
+                .to_string(),
+            ),
+            Expression::Attribute(value, name) => {
+                if let Expression::Name(loop_) = &**value {
+                    if loop_ == "loop" && !self.is_declared(loop_) {
+                        return self.loop_variable(name, range);
+                    }
+                }
+                let field = FIELDS
+                    .iter()
+                    .find(|field| snake_case(field) == *name)
+                    .ok_or_else(|| unsupported(format!("the field `{name}`"), range.clone()))?;
+                Go::Operand(format!(
+                    "{}.{field}",
+                    self.expression(value, range)?.argument()
+                ))
+            }
+            Expression::Item(value, index) => {
+                if matches!(**index, Expression::Integer(index) if index < 0) {
+                    return Err(unsupported("negative indexes", range.clone()));
+                }
+                Go::Command(format!("index {} {}", argument(value)?, argument(index)?))
+            }
+            Expression::Slice(value, start, end) => {
+                let negative = [start, end]
+                    .into_iter()
+                    .flatten()
+                    .any(|bound| matches!(**bound, Expression::Integer(bound) if bound < 0));
+                if negative {
+                    return Err(unsupported("negative indexes", range.clone()));
+                }
+                let start = match start {
+                    Some(start) => argument(start)?,
+                    None => "0".to_string(),
+                };
+                let end = match end {
+                    Some(end) => format!(" {}", argument(end)?),
+                    None => String::new(),
+                };
+                Go::Command(format!("slice {} {start}{end}", argument(value)?))
+            }
+            Expression::Filter(value, filter, arguments) if arguments.is_empty() => {
+                let function = match filter.as_str() {
+                    "length" | "count" => "len",
+                    "tojson" => "json",
+                    "string" => "print",
+                    filter => {
+                        return Err(unsupported(format!("the filter `{filter}`"), range.clone()))
+                    }
+                };
+                Go::Command(format!("{function} {}", argument(value)?))
+            }
+            Expression::Not(value) => Go::Command(format!("not {}", argument(value)?)),
+            Expression::Binary(operator @ ("+" | "~"), _, _)
+                if *operator == "~" || is_text(expression) =>
+            {
+                let mut parts = Vec::new();
+                concatenated(expression, &mut parts);
+                let parts = parts
+                    .into_iter()
+                    .map(argument)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Go::Command(format!("print {}", parts.join(" ")))
+            }
+            Expression::Binary(operator, left, right) => {
+                let function = match *operator {
+                    "==" => "eq",
+                    "!=" => "ne",
+                    "<" => "lt",
+                    "<=" => "le",
+                    ">" => "gt",
+                    ">=" => "ge",
+                    "and" => "and",
+                    "or" => "or",
+                    "in" | "not in" => {
+                        return Err(unsupported(format!("`{operator}`"), range.clone()))
+                    }
+                    _ => return Err(unsupported("arithmetic", range.clone())),
+                };
+                Go::Command(format!(
+                    "{function} {} {}",
+                    argument(left)?,
+                    argument(right)?
+                ))
+            }
+            Expression::Call(function, _) => {
+                let name = match &**function {
+                    Expression::Name(name) | Expression::Attribute(_, name) => name.as_str(),
+                    _ => "a function",
+                };
+                return Err(unsupported(format!("calling `{name}`"), range.clone()));
+            }
+            Expression::Filter(_, filter, _) => {
+                return Err(unsupported(format!("the filter `{filter}`"), range.clone()))
+            }
+            Expression::Test(_, test, _) => {
+                return Err(unsupported(format!("the test `is {test}`"), range.clone()))
+            }
+            Expression::None => return Err(unsupported("`none`", range.clone())),
+            Expression::List(_) => return Err(unsupported("list literals", range.clone())),
+        })
+    }
+
+    /// `loop.first`, `loop.last` and friends, for the innermost loop.
+    fn loop_variable(&self, name: &str, range: &Range<usize>) -> Result<Go, Failure> {
+        let Some(Loop {
+            index, iterable, ..
+        }) = self.loops.last()
+        else {
+            return Err(unsupported("`loop` outside of a loop", range.clone()));
+        };
+
+        Ok(match name {
+            "index0" => Go::Operand(index.clone()),
+            "first" => Go::Command(format!("eq {index} 0")),
+            "last" => Go::Command(format!("eq (len (slice {iterable} {index})) 1")),
+            "length" => Go::Command(format!("len {iterable}")),
+            name => return Err(unsupported(format!("`loop.{name}`"), range.clone())),
+        })
+    }
+}
+
+/// Names `set` inside `if`s, but not inside nested loops.
+fn set_in_ifs(nodes: &[JinjaNode], names: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            JinjaNode::Set { name, .. } => names.push(name.clone()),
+            JinjaNode::If {
+                branches,
+                otherwise,
+            } => {
+                for (_, body, _) in branches {
+                    set_in_ifs(body, names);
+                }
+                set_in_ifs(otherwise.as_deref().unwrap_or_default(), names);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The condition with `add_generation_prompt` folded into the `and`s and `or`s around it.
+fn fold(condition: &Expression) -> Expression {
+    match condition {
+        Expression::Binary(operator @ ("and" | "or"), left, right) => {
+            let (left, right) = (fold(left), fold(right));
+            match (*operator, constant(&left), constant(&right)) {
+                ("and", Some(true), _) | ("or", Some(false), _) => right,
+                ("and", _, Some(true)) | ("or", _, Some(false)) => left,
+                ("and", Some(false), _) | ("and", _, Some(false)) => Expression::Bool(false),
+                ("or", Some(true), _) | ("or", _, Some(true)) => Expression::Bool(true),
+                _ => Expression::Binary(operator, Box::new(left), Box::new(right)),
+            }
+        }
+        Expression::Not(value) => Expression::Not(Box::new(fold(value))),
+        condition => condition.clone(),
+    }
+}
+
+/// `add_generation_prompt` is always true in Ollama.
+fn constant(condition: &Expression) -> Option<bool> {
+    match condition {
+        Expression::Name(name) if name == "add_generation_prompt" => Some(true),
+        Expression::Bool(value) => Some(*value),
+        Expression::Not(condition) => constant(condition).map(|value| !value),
+        _ => None,
+    }
+}
+
+/// `true` if the expression is a string, so `+` joins strings.
+fn is_text(expression: &Expression) -> bool {
+    match expression {
+        Expression::String(_) => true,
+        Expression::Name(name) => name.ends_with("_token"),
+        Expression::Attribute(_, name) => {
+            matches!(name.as_str(), "role" | "content" | "name" | "thinking")
+        }
+        Expression::Filter(_, filter, _) => matches!(filter.as_str(), "tojson" | "string"),
+        Expression::Binary("+" | "~", left, right) => is_text(left) || is_text(right),
+        _ => false,
+    }
+}
+
+/// The parts of `a + b ~ c`.
+fn concatenated<'e>(expression: &'e Expression, parts: &mut Vec<&'e Expression>) {
+    match expression {
+        Expression::Binary("+" | "~", left, right) => {
+            concatenated(left, parts);
+            concatenated(right, parts);
+        }
+        expression => parts.push(expression),
+    }
+}
+
+fn go_string(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+/// What `.` is in a Go template.
+#[derive(Clone)]
+enum Dot {
+    /// The values Ollama passes to the template.
+    Root,
+    /// A Jinja expression, and whether it is printed as JSON.
+    Value(String, bool),
+}
+
+/// A Go variable, as a Jinja expression.
+struct Variable {
+    name: String,
+    dot: Dot,
+    /// The number of `range`s around the declaration.
+    loops: usize,
+}
+
+/// Writes Jinja source from a Go template.
+struct ToJinja {
+    out: String,
+    /// `true` right after a `{% … %}` tag,
+    /// where Hugging Face's `trim_blocks` removes a newline.
+    after_block: bool,
+    /// Go variables, innermost scope last.
+    variables: Vec<Vec<Variable>>,
+    /// How many `range`s deep the template is.
+    loops: usize,
+    /// `true` if `.System` is used.
+    system: bool,
+    /// `true` if `.Messages` is used.
+    messages: bool,
+}
+
+type SpannedFailure = (ConvertErrorKind, Span);
+
+impl ToJinja {
+    fn nodes(&mut self, nodes: &[Node], dot: &Dot) -> Result<(), SpannedFailure> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.text(text),
+                Node::Comment(comment) => {
+                    self.out.push_str(&format!("{{# {} #}}", comment.trim()));
+                    self.after_block = true;
+                }
+                Node::Action { pipeline, span } => self.action(pipeline, dot, span)?,
+                Node::If(branch) => {
+                    self.branch(branch, dot, "if")?;
+                    self.block("endif");
+                }
+                Node::Range(branch) => self.range(branch, dot)?,
+                Node::With(Branch { span, .. }) => {
+                    return Err(unsupported_at("`with`", span));
+                }
+                Node::Define { span, .. }
+                | Node::Block { span, .. }
+                | Node::Template { span, .. } => {
+                    return Err(unsupported_at("named templates", span));
+                }
+                Node::Break(span) | Node::Continue(span) => {
+                    return Err(unsupported_at("`break` and `continue`", span));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Text, with Jinja's delimiters quoted,
+    /// and whitespace `trim_blocks` and `lstrip_blocks` would remove doubled.
+    fn text(&mut self, text: &str) {
+        if self.after_block && text.starts_with('\n') {
+            self.out.push('\n');
+        }
+        self.after_block = false;
+
+        let mut rest = text;
+        while let Some(found) = ["{{", "{%", "{#"]
+            .into_iter()
+            .filter_map(|open| rest.find(open))
+            .min()
+        {
+            self.out.push_str(&rest[..found]);
+            self.out
+                .push_str(&format!("{{{{ '{}' }}}}", &rest[found..found + 2]));
+            rest = &rest[found + 2..];
+        }
+        self.out.push_str(rest);
+    }
+
+    /// A `{% … %}` tag, keeping the indentation before it.
+    fn block(&mut self, tag: &str) {
+        let line_start = self.out.rfind('\n').map_or(0, |newline| newline + 1);
+        let indentation = &self.out[line_start..];
+        if !indentation.is_empty() && indentation.chars().all(|c| c == ' ' || c == '\t') {
+            let indentation = indentation.to_string();
+            self.out.truncate(line_start);
+            self.out.push_str(&format!("{{{{ '{indentation}' }}}}"));
+        }
+
+        self.out.push_str(&format!("{{% {tag} %}}"));
+        self.after_block = true;
+    }
+
+    fn output(&mut self, expression: &str) {
+        self.out.push_str(&format!("{{{{ {expression} }}}}"));
+        self.after_block = false;
+    }
+
+    fn action(
+        &mut self,
+        pipeline: &Pipeline,
+        dot: &Dot,
+        span: &Span,
+    ) -> Result<(), SpannedFailure> {
+        let (value, json) = self.pipeline(pipeline, dot, span)?;
+
+        let [variable] = pipeline.variables.as_slice() else {
+            if !pipeline.variables.is_empty() {
+                return Err(unsupported_at("declaring two variables", span));
+            }
+            self.output(&if json {
+                format!("{value} | tojson")
+            } else {
+                value
+            });
+            return Ok(());
+        };
+
+        let name = variable.trim_start_matches('$');
+        let declared = self
+            .variables
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| {
+                scope
+                    .iter()
+                    .find(|declared| declared.name == *variable)
+                    .map(|declared| (depth, declared.loops))
+            });
+        match declared {
+            // Jinja's `set` only reaches variables outside of loops by `namespace`
+            Some((_, loops)) if pipeline.assign && loops != self.loops => {
+                return Err(unsupported_at(
+                    format!("assigning {variable} from inside a `range`"),
+                    span,
+                ));
+            }
+            // Jinja's `if` doesn't have its own scope
+            Some((depth, loops))
+                if !pipeline.assign && loops == self.loops && depth + 1 < self.variables.len() =>
+            {
+                return Err(unsupported_at(format!("shadowing {variable}"), span));
+            }
+            None if pipeline.assign => {
+                return Err(unsupported_at(format!("the variable {variable}"), span));
+            }
+            _ => {}
+        }
+
+        self.block(&format!("set {name} = {value}"));
+        if !pipeline.assign {
+            let loops = self.loops;
+            if let Some(scope) = self.variables.last_mut() {
+                scope.push(Variable {
+                    name: variable.clone(),
+                    dot: Dot::Value(name.to_string(), json),
+                    loops,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// An `if`, with `else if` chains as `elif`.
+    fn branch(&mut self, branch: &Branch, dot: &Dot, keyword: &str) -> Result<(), SpannedFailure> {
+        if !branch.pipeline.variables.is_empty() {
+            return Err(unsupported_at("variables in conditions", &branch.span));
+        }
+        let (condition, _) = self.pipeline(&branch.pipeline, dot, &branch.span)?;
+        self.block(&format!("{keyword} {condition}"));
+        self.scoped(&branch.body, dot)?;
+
+        match branch.otherwise.as_deref() {
+            Some([Node::If(chained)]) => self.branch(chained, dot, "elif")?,
+            Some(otherwise) => {
+                self.block("else");
+                self.scoped(otherwise, dot)?;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn range(&mut self, branch: &Branch, dot: &Dot) -> Result<(), SpannedFailure> {
+        let span = &branch.span;
+        let (iterable, _) = self.pipeline(&branch.pipeline, dot, span)?;
+        let (index, element) = match branch.pipeline.variables.as_slice() {
+            [] => (None, None),
+            [element] => (None, Some(element)),
+            [index, element] => (Some(index), Some(element)),
+            _ => return Err(unsupported_at("this `range`", span)),
+        };
+        if branch.pipeline.assign {
+            return Err(unsupported_at("assigning variables", span));
+        }
+
+        let target = match element.map(|element| element.trim_start_matches('$')) {
+            Some(name) if name != "_" => name.to_string(),
+            _ => {
+                let name = match iterable.rsplit(['.', '[']).next() {
+                    Some("messages" | "'messages']") => "message",
+                    Some("tools" | "'tools']") => "tool",
+                    Some("'tool_calls']") => "tool_call",
+                    _ => "item",
+                };
+                if self.loops > 0 {
+                    format!("{name}{}", self.loops + 1)
+                } else {
+                    name.to_string()
+                }
+            }
+        };
+        let element_dot = Dot::Value(target.clone(), true);
+
+        self.block(&format!("for {target} in {iterable}"));
+        let mut scope = Vec::new();
+        if let Some(index) = index.filter(|index| *index != "$_") {
+            let name = index.trim_start_matches('$');
+            self.block(&format!("set {name} = loop.index0"));
+            scope.push(Variable {
+                name: index.clone(),
+                dot: Dot::Value(name.to_string(), false),
+                loops: self.loops + 1,
+            });
+        }
+        if let Some(element) = element {
+            scope.push(Variable {
+                name: element.clone(),
+                dot: element_dot.clone(),
+                loops: self.loops + 1,
+            });
+        }
+
+        self.variables.push(scope);
+        self.loops += 1;
+        let result = self.nodes(&branch.body, &element_dot);
+        self.loops -= 1;
+        self.variables.pop();
+        result?;
+
+        if let Some(otherwise) = &branch.otherwise {
+            self.block("else");
+            self.scoped(otherwise, dot)?;
+        }
+        self.block("endfor");
+
+        Ok(())
+    }
+
+    fn scoped(&mut self, nodes: &[Node], dot: &Dot) -> Result<(), SpannedFailure> {
+        self.variables.push(Vec::new());
+        let result = self.nodes(nodes, dot);
+        self.variables.pop();
+        result
+    }
+
+    /// The pipeline as a Jinja expression, and whether Go prints it as JSON.
+    fn pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        dot: &Dot,
+        span: &Span,
+    ) -> Result<(String, bool), SpannedFailure> {
+        let mut piped: Option<(String, bool)> = None;
+
+        for Command { args } in &pipeline.commands {
+            piped = Some(match args.split_first() {
+                Some((Operand::Function(function), args)) => {
+                    let mut values = Vec::new();
+                    for arg in args {
+                        values.push(self.operand(arg, dot, span)?.0);
+                    }
+                    values.extend(piped.map(|(value, _)| value));
+                    (self.call(function, &values, span)?, false)
+                }
+                Some((operand, [])) if piped.is_none() => self.operand(operand, dot, span)?,
+                _ => return Err(unsupported_at("arguments to a non-function", span)),
+            });
+        }
+
+        piped.ok_or_else(|| unsupported_at("an empty pipeline", span))
+    }
+
+    fn operand(
+        &mut self,
+        operand: &Operand,
+        dot: &Dot,
+        span: &Span,
+    ) -> Result<(String, bool), SpannedFailure> {
+        match operand {
+            Operand::Dot => match dot {
+                Dot::Root => Err(unsupported_at("`.` outside of a `range`", span)),
+                Dot::Value(value, json) => Ok((value.clone(), *json)),
+            },
+            Operand::Field(fields) => self.fields(dot, fields, span),
+            Operand::Variable { name, fields } => {
+                let variable = match name.as_str() {
+                    "$" => Some(Dot::Root),
+                    name => self
+                        .variables
+                        .iter()
+                        .rev()
+                        .flatten()
+                        .find(|variable| variable.name == name)
+                        .map(|variable| variable.dot.clone()),
+                }
+                .ok_or_else(|| unsupported_at(format!("the variable {name}"), span))?;
+                match (&variable, fields.is_empty()) {
+                    (Dot::Value(value, json), true) => Ok((value.clone(), *json)),
+                    _ => self.fields(&variable, fields, span),
+                }
+            }
+            Operand::Pipeline { pipeline, fields } => {
+                let (value, json) = self.pipeline(pipeline, dot, span)?;
+                if fields.is_empty() {
+                    Ok((value, json))
+                } else {
+                    self.fields(&Dot::Value(format!("({value})"), json), fields, span)
+                }
+            }
+            Operand::String(text) => Ok((go_string(text), false)),
+            Operand::Number(number) if number.parse::<f64>().is_ok() => Ok((number.clone(), false)),
+            Operand::Bool(value) => Ok((value.to_string(), false)),
+            Operand::Nil => Ok(("none".to_string(), false)),
+            Operand::Function(function) => Ok((self.call(function, &[], span)?, false)),
+            Operand::Number(_) | Operand::Char(_) => {
+                Err(unsupported_at("this number literal", span))
+            }
+        }
+    }
+
+    /// `.A.B` on `dot`.
+    fn fields(
+        &mut self,
+        dot: &Dot,
+        fields: &[String],
+        span: &Span,
+    ) -> Result<(String, bool), SpannedFailure> {
+        let (mut value, mut rest) = match (dot, fields.split_first()) {
+            (Dot::Root, Some((first, rest))) => {
+                let value = match first.as_str() {
+                    "Messages" => {
+                        self.messages = true;
+                        "messages"
+                    }
+                    "Tools" => "tools",
+                    // only set for templates without `.Messages`
+                    "Prompt" | "Suffix" | "Response" => "''",
+                    "System" => {
+                        self.system = true;
+                        "system"
+                    }
+                    field => return Err(unsupported_at(format!(".{field}"), span)),
+                };
+                (value.to_string(), rest)
+            }
+            (Dot::Root, None) => return Err(unsupported_at("`$`", span)),
+            (Dot::Value(value, _), _) => (value.clone(), fields),
+        };
+        let mut json =
+            matches!(fields.last(), Some(field) if OBJECT_FIELDS.contains(&field.as_str()));
+
+        while let Some((field, after)) = rest.split_first() {
+            value = format!("{value}['{}']", snake_case(field));
+            rest = after;
+        }
+        if fields.is_empty() {
+            json = matches!(dot, Dot::Value(_, true));
+        }
+
+        Ok((value, json))
+    }
+
+    fn call(&self, function: &str, args: &[String], span: &Span) -> Result<String, SpannedFailure> {
+        let wrong_args =
+            || unsupported_at(format!("`{function}` with {} arguments", args.len()), span);
+
+        Ok(match (function, args) {
+            ("eq", [left, rest @ ..]) if !rest.is_empty() => {
+                let comparisons: Vec<String> = rest
+                    .iter()
+                    .map(|right| format!("{left} == {right}"))
+                    .collect();
+                format!("({})", comparisons.join(" or "))
+            }
+            ("ne" | "lt" | "le" | "gt" | "ge", [left, right]) => {
+                let operator = match function {
+                    "ne" => "!=",
+                    "lt" => "<",
+                    "le" => "<=",
+                    "gt" => ">",
+                    _ => ">=",
+                };
+                format!("({left} {operator} {right})")
+            }
+            ("and" | "or", [_, ..]) => format!("({})", args.join(&format!(" {function} "))),
+            ("not", [value]) => format!("(not {value})"),
+            ("len", [value]) => format!("({value} | length)"),
+            ("json", [value]) => format!("({value} | tojson)"),
+            ("index", [value, indexes @ ..]) => indexes
+                .iter()
+                .fold(value.clone(), |value, index| format!("{value}[{index}]")),
+            ("slice", [value]) => value.clone(),
+            ("slice", [value, start]) => format!("{value}[{start}:]"),
+            ("slice", [value, start, end]) => format!("{value}[{start}:{end}]"),
+            ("print", [_, ..]) => format!("({})", args.join(" ~ ")),
+            (
+                "eq" | "ne" | "lt" | "le" | "gt" | "ge" | "and" | "or" | "not" | "len" | "json"
+                | "index" | "slice" | "print",
+                _,
+            ) => return Err(wrong_args()),
+            (function, _) => {
+                return Err(unsupported_at(format!("the function `{function}`"), span))
+            }
+        })
+    }
+}
+
+fn unsupported_at(construct: impl Into<String>, span: &Span) -> SpannedFailure {
+    (
+        ConvertErrorKind::Unsupported(construct.into()),
+        span.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+    use minijinja::{context, Environment};
+
+    use crate::{
+        message::ChatMessage,
+        modelfile::{prompt::TemplateValues, test_data::TEST_GOOD_DATA_DIR, Modelfile},
+    };
+
+    use super::*;
+
+    const CHATML: &str = "{% for message in messages %}{{'<|im_start|>' + message['role'] + '\\n' + message['content'] + '<|im_end|>' + '\\n'}}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\\n' }}{% endif %}";
+
+    const LLAMA3: &str = "{% set loop_messages = messages %}{% for message in loop_messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n'+ message['content'] | trim + '<|eot_id|>' %}{% if loop.index0 == 0 %}{% set content = bos_token + content %}{% endif %}{{ content }}{% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\n\n' }}{% endif %}";
+
+    const ZEPHYR: &str = "{% for message in messages %}
+{% if message['role'] == 'user' %}
+{{ '<|user|>\n' + message['content'] + eos_token }}
+{% elif message['role'] == 'system' %}
+{{ '<|system|>\n' + message['content'] + eos_token }}
+{% elif message['role'] == 'assistant' %}
+{{ '<|assistant|>\n'  + message['content'] + eos_token }}
+{% endif %}
+{% if loop.last and add_generation_prompt %}
+{{ '<|assistant|>' }}
+{% endif %}
+{% endfor %}";
+
+    const GEMMA: &str = "{{ bos_token }}{% set system = '' %}{% for message in messages %}{% if message['role'] == 'system' %}{% set system = message['content'] + '\n\n' %}{% else %}{% set role = 'model' if message['role'] == 'assistant' else message['role'] %}{{ '<start_of_turn>' + role + '\n' }}{% if loop.first %}{{ system }}{% endif %}{{ message['content'] }}<end_of_turn>
+{% endif %}{% endfor %}{% if add_generation_prompt %}{{'<start_of_turn>model\n'}}{% endif %}";
+
+    const GEMMA_IF: &str = "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'assistant' %}{% set role = 'model' %}{% else %}{% set role = message['role'] %}{% endif %}{{ '<start_of_turn>' + role + '\n' + message['content'] }}<end_of_turn>
+{% endfor %}{% if add_generation_prompt %}{{'<start_of_turn>model\n'}}{% endif %}";
+
+    fn message(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+        }
+    }
+
+    fn chats() -> Vec<Vec<ChatMessage>> {
+        vec![
+            vec![message("user", "Why is the sky blue?")],
+            vec![
+                message("system", "Talk like a pirate."),
+                message("user", "Why is the sky blue?"),
+                message("assistant", "Rayleigh scattering, matey."),
+                message("user", "Explain it like I'm {{ five }}."),
+            ],
+        ]
+    }
+
+    /// Render like Hugging Face, with Ollama's tokenizer adding the BOS token.
+    fn render_jinja(jinja: &str, messages: &[ChatMessage], eos_token: &str) -> String {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.template_from_str(jinja)
+            .expect("Jinja should compile")
+            .render(context! {
+                messages => messages,
+                bos_token => "",
+                eos_token => eos_token,
+                add_generation_prompt => true,
+            })
+            .expect("Jinja should render")
+    }
+
+    fn render_go(template: &Template, messages: &[ChatMessage]) -> String {
+        template
+            .parse()
+            .expect("template should parse")
+            .render(&TemplateValues {
+                messages: messages.to_vec(),
+                ..TemplateValues::default()
+            })
+            .expect("template should render")
+    }
+
+    #[test]
+    fn jinja_templates_render_the_same() {
+        for jinja in [CHATML, ZEPHYR, GEMMA_IF] {
+            let template = Template::from_jinja(jinja, Some("</s>")).expect("should convert");
+            let back = template.to_jinja().expect("should convert back");
+
+            for chat in chats() {
+                let expected = render_jinja(jinja, &chat, "</s>");
+                assert_eq!(render_go(&template, &chat), expected, "{template}");
+                assert_eq!(render_jinja(&back, &chat, "</s>"), expected, "{back}");
+            }
+        }
+
+        let template = Template::from_jinja(ZEPHYR, Some("</s>")).expect("should convert");
+        assert_snapshot!(AsRef::<str>::as_ref(&*template), @r#"
+        {{ range $i, $message := $.Messages }}{{ if eq $message.Role "user" }}<|user|>
+        {{ $message.Content }}</s>
+        {{ else if eq $message.Role "system" }}<|system|>
+        {{ $message.Content }}</s>
+        {{ else if eq $message.Role "assistant" }}<|assistant|>
+        {{ $message.Content }}</s>
+        {{ end }}{{ if eq (len (slice $.Messages $i)) 1 }}<|assistant|>
+        {{ end }}{{ end }}
+        "#);
+    }
+
+    #[test]
+    fn go_templates_render_the_same() {
+        for name in ["llama3.2.latest", "mistral-nemo.latest", "qwen2.5.latest"] {
+            let modelfile: Modelfile =
+                std::fs::read_to_string(format!("{TEST_GOOD_DATA_DIR}{name}.Modelfile"))
+                    .expect("should read fixture")
+                    .parse()
+                    .expect("fixture should parse");
+            let template = modelfile.template.expect("fixture should have a template");
+            let jinja = template.to_jinja().expect("should convert");
+
+            for chat in chats() {
+                assert_eq!(
+                    render_jinja(&jinja, &chat, ""),
+                    render_go(&template, &chat),
+                    "{name}:\n{jinja}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tokenizer_configs_are_read() {
+        let config: TokenizerConfig = serde_json::from_str(
+            r#"{
+                "bos_token": {"content": "<s>", "lstrip": false},
+                "eos_token": "</s>",
+                "chat_template": [
+                    {"name": "tool_use", "template": "{{ tools }}"},
+                    {"name": "default", "template": "{% for message in messages %}{{ message.content }}{% endfor %}"}
+                ]
+            }"#,
+        )
+        .expect("should read tokenizer config");
+
+        assert_eq!(config.bos_token.as_deref(), Some("<s>"));
+        let template = config
+            .to_template()
+            .expect("should convert")
+            .expect("should have a template");
+        assert_snapshot!(AsRef::<str>::as_ref(&*template), @"{{ range $i, $message := $.Messages }}{{ $message.Content }}{{ end }}");
+    }
+
+    #[test]
+    fn nesting_stops_at_the_depth_limit() {
+        let nested = |depth: usize| {
+            "{% for message in messages %}".to_string()
+                + &"{% if message.content %}\n".repeat(depth - 1)
+                + "{{ message.content }}"
+                + &"{% endif %}".repeat(depth - 1)
+                + "{% endfor %}"
+        };
+        Template::from_jinja(&nested(MAX_DEPTH), None)
+            .expect("template within the limit should convert");
+        assert_snapshot!(
+            Template::from_jinja(&nested(MAX_DEPTH + 1), None).expect_err("should be too deep"),
+            @r"
+        error: can't convert blocks nested this deep
+           --> 100:1
+            |
+        100 | {% if message.content %}
+            | ^
+        "
+        );
+        assert!(Template::from_jinja(&nested(20_000), None).is_err());
+
+        let parentheses = format!("{{{{ {}1{} }}}}", "(".repeat(1000), ")".repeat(1000));
+        let concatenated = format!("{{{{ 'a'{} }}}}", " ~ 'a'".repeat(20_000));
+        for jinja in [parentheses, concatenated] {
+            assert_eq!(
+                Template::from_jinja(&jinja, None)
+                    .expect_err("should be too deep")
+                    .kind,
+                ConvertErrorKind::Unsupported("expressions nested this deep".to_string())
+            );
+        }
+
+        // blocks built without the parser are limited too
+        let mut nodes = vec![JinjaNode::Text("!".to_string())];
+        for _ in 0..=MAX_DEPTH {
+            nodes = vec![JinjaNode::If {
+                branches: vec![(Expression::Name("tools".to_string()), nodes, 0..1)],
+                otherwise: None,
+            }];
+        }
+        let mut converter = ToGo {
+            eos_token: "",
+            out: String::new(),
+            scopes: vec![Vec::new()],
+            loops: Vec::new(),
+            depth: 0,
+        };
+        let (kind, _) = converter.scope(&nodes).expect_err("should be too deep");
+        assert_snapshot!(kind, @"can't convert blocks nested this deep");
+    }
+
+    #[test]
+    fn unsupported_constructs_are_reported() {
+        let mut errors = String::new();
+        for jinja in [
+            LLAMA3,
+            GEMMA,
+            "{% if messages[0]['role'] == 'system' %}{{ raise_exception('no system messages') }}{% endif %}",
+            "{{ messages[-1]['content'] }}",
+            "{% for message in messages %}{% if message.role not in ['user', 'assistant'] %}!{% endif %}{% endfor %}",
+            "{% for message in messages %}{{ loop.index }}{% endfor %}",
+            "{% macro turn(message) %}{% endmacro %}",
+            "{{ date_string }}",
+            "{% for message in messages %}",
+            "{{ 'unterminated }}",
+        ] {
+            let error = Template::from_jinja(jinja, None).expect_err("should not convert");
+            errors.push_str(&format!("{error}\n"));
+        }
+        for go in [
+            "{{ .Prompt }}",
+            "{{ range .Messages }}{{ with .Content }}{{ . }}{{ end }}{{ end }}",
+            "{{ range .Messages }}{{ if eq .Role \"system\" }}{{ break }}{{ end }}{{ end }}",
+            "{{ range .Messages }}{{ .Content | printf \"%q\" }}{{ end }}",
+        ] {
+            let error = Template::from(go)
+                .to_jinja()
+                .expect_err("should not convert");
+            errors.push_str(&format!("{error}\n"));
+        }
+
+        assert_snapshot!(errors, @r#"
+        error: can't convert the filter `trim`
+         --> 1:69
+          |
+        1 | {% set loop_messages = messages %}{% for message in loop_messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>
+          |                                                                     ^
+        error: can't convert inline `if` expressions
+         --> 3:15
+          |
+        3 | ' %}{% else %}{% set role = 'model' if message['role'] == 'assistant' else message['role'] %}{{ '<start_of_turn>' + role + '
+          |               ^
+        error: can't convert calling `raise_exception`
+         --> 1:41
+          |
+        1 | {% if messages[0]['role'] == 'system' %}{{ raise_exception('no system messages') }}{% endif %}
+          |                                         ^
+        error: can't convert negative indexes
+         --> 1:1
+          |
+        1 | {{ messages[-1]['content'] }}
+          | ^
+        error: can't convert `not in`
+         --> 1:30
+          |
+        1 | {% for message in messages %}{% if message.role not in ['user', 'assistant'] %}!{% endif %}{% endfor %}
+          |                              ^
+        error: can't convert `loop.index`
+         --> 1:30
+          |
+        1 | {% for message in messages %}{{ loop.index }}{% endfor %}
+          |                              ^
+        error: can't convert `{% macro %}`
+         --> 1:1
+          |
+        1 | {% macro turn(message) %}{% endmacro %}
+          | ^
+        error: can't convert the variable `date_string`
+         --> 1:1
+          |
+        1 | {{ date_string }}
+          | ^
+        error: `for` is never closed
+         --> 1:1
+          |
+        1 | {% for message in messages %}
+          | ^
+        error: unclosed tag, expected `}}`
+         --> 1:1
+          |
+        1 | {{ 'unterminated }}
+          | ^
+        error: can't convert a template without .Messages
+         --> 1:1
+          |
+        1 | {{ .Prompt }}
+          | ^
+        error: can't convert `with`
+         --> 1:22
+          |
+        1 | {{ range .Messages }}{{ with .Content }}{{ . }}{{ end }}{{ end }}
+          |                      ^
+        error: can't convert `break` and `continue`
+         --> 1:48
+          |
+        1 | {{ range .Messages }}{{ if eq .Role "system" }}{{ break }}{{ end }}{{ end }}
+          |                                                ^
+        error: can't convert the function `printf`
+         --> 1:22
+          |
+        1 | {{ range .Messages }}{{ .Content | printf "%q" }}{{ end }}
+          |                      ^
+        "#);
+    }
+}
@@ -11,18 +11,32 @@ use std::{
 
 use builder::ModelfileBuilder;
 use derive_more::derive::{AsRef, From};
-use error::ModelfileError;
-use instruction::{Adapter, BaseModel, License, Messages, Parameters, SystemMessage, Template};
-use parser::instructions;
+use error::{ModelfileError, ParseDiagnostic};
+use instruction::{
+    Adapter, BaseModel, License, Messages, Parameters, Requires, SystemMessage, Template,
+};
+use parser::{parse_all, recovering_instructions, spanned_instructions};
 use serde::{Deserialize, Serialize};
-use strum::{AsRefStr, EnumDiscriminants, EnumIter, EnumString, IntoStaticStr, VariantArray};
+use span::Spanned;
+use strum::{AsRefStr, EnumDiscriminants, IntoStaticStr};
+
+use crate::{digest::Digest, message::Message};
 
-use crate::message::Message;
+pub use parameter::{Parameter, ParameterName};
 
+pub mod base_model;
 pub mod builder;
+pub mod cst;
+pub mod edit;
 pub mod error;
 pub mod instruction;
+pub mod jinja;
+pub mod parameter;
 mod parser;
+pub mod prompt;
+pub mod requires;
+pub mod span;
+pub mod template;
 
 #[cfg(test)]
 pub mod test_data;
@@ -32,10 +46,19 @@ const HEADER_COMMENT: &str = "# This file was generated by the Ollama-CLI client
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Modelfile {
     pub(crate) from: BaseModel,
+    /// More `FROM` layers after the base model,
+    /// like the projector of a multimodal model.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) layers: Vec<BaseModel>,
+    /// The Ollama versions that can run the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) requires: Option<Requires>,
     pub(crate) parameters: Parameters,
     pub(crate) template: Option<Template>,
     pub(crate) system: Option<SystemMessage>,
-    pub(crate) adapter: Option<Adapter>,
+    /// LoRA adapters, applied in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) adapters: Vec<Adapter>,
     pub(crate) license: Option<License>,
     pub(crate) messages: Messages,
 }
@@ -45,12 +68,14 @@ impl Modelfile {
         let mut renderer = Renderer::default();
         renderer.push_raw(HEADER_COMMENT);
         renderer.push("FROM", self.from.as_str());
+        for layer in &self.layers {
+            renderer.push("FROM", layer.as_str());
+        }
         renderer.newline();
 
-        renderer.push_opt(
-            "ADAPTER",
-            self.adapter.clone().map(|file| file.to_string()).as_ref(),
-        );
+        renderer.push_opt("REQUIRES", self.requires.as_ref());
+
+        renderer.push_vec("ADAPTER", &self.adapters);
 
         renderer.push_opt("SYSTEM", self.system.as_ref());
         renderer.push_opt("TEMPLATE", self.template.as_ref());
@@ -64,36 +89,192 @@ impl Modelfile {
     pub fn instructions(self) -> impl Iterator<Item = Instruction> {
         let Modelfile {
             from,
+            layers,
+            requires,
             parameters,
             template,
             system,
-            adapter,
+            adapters,
             license,
             messages,
         } = self;
 
-        std::iter::once(Instruction::from(from))
+        std::iter::once(from)
+            .chain(layers)
+            .map(Instruction::From)
+            .chain(requires.into_iter().map(Instruction::Requires))
             .chain(parameters.into_iter().map(Instruction::Parameter))
             .chain(template.into_iter().map(Instruction::Template))
             .chain(system.into_iter().map(Instruction::System))
-            .chain(adapter.into_iter().map(Instruction::Adapter))
+            .chain(adapters.into_iter().map(Instruction::Adapter))
             .chain(messages.into_iter().map(Instruction::Message))
             .chain(license.into_iter().map(Instruction::License))
     }
 
+    /// Resolve relative file paths, like the `ADAPTER`s,
+    /// against `base`, the directory containing the Modelfile.
+    ///
+    /// See [`TensorFile::resolve`].
+    pub fn resolve_paths(mut self, base: impl AsRef<Path>) -> Self {
+        let base = base.as_ref();
+        self.adapters = self
+            .adapters
+            .into_iter()
+            .map(|adapter| adapter.resolve(base))
+            .collect();
+        self
+    }
+
     pub fn build_on(self) -> ModelfileBuilder {
         self.into()
     }
+
+    /// Parse a [`Modelfile`] with the given [`ParseOptions`].
+    ///
+    /// [`FromStr`] parses with the default, lenient, options.
+    pub fn parse_with(input: &str, options: ParseOptions) -> Result<Self, ModelfileError> {
+        let instructions = parse_all(input, spanned_instructions)?;
+        options
+            .check(input, &instructions)
+            .map_err(ModelfileError::Parse)?;
+
+        instructions
+            .into_iter()
+            .map(Spanned::into_inner)
+            .collect::<Vec<Instruction>>()
+            .try_into()
+    }
+
+    /// Parse the instructions in `input`
+    /// along with the [`span::Span`] each one was parsed from,
+    /// including the full body of multiline instructions.
+    ///
+    /// Empty lines and comments are not included.
+    pub fn parse_spanned(input: &str) -> Result<Vec<Spanned<Instruction>>, ModelfileError> {
+        let instructions = parse_all(input, spanned_instructions)?;
+
+        Ok(instructions
+            .into_iter()
+            .filter(|instruction| !matches!(instruction.value, Instruction::Skip))
+            .collect())
+    }
+
+    /// Parse the instructions in `input` like [`Modelfile::parse_spanned`],
+    /// but keep going after errors.
+    ///
+    /// When an instruction fails to parse,
+    /// parsing resumes at the next line that starts with an instruction keyword,
+    /// so every problem in the file is reported at once
+    /// along with the instructions that could be parsed.
+    pub fn parse_recovering(input: &str) -> PartialParse {
+        let (instructions, errors) = recovering_instructions(input);
+
+        PartialParse {
+            instructions: instructions
+                .into_iter()
+                .filter(|instruction| !matches!(instruction.value, Instruction::Skip))
+                .collect(),
+            errors,
+        }
+    }
+}
+
+/// Options that control how a [`Modelfile`] is parsed.
+///
+/// By default parsing is lenient:
+/// `PARAMETER` keys this crate doesn't know about
+/// are kept as [`Parameter::Other`] and logged as a warning.
+/// In strict mode they are a parse error instead.
+///
+/// ```
+/// use modelfile::modelfile::{Modelfile, ParseOptions};
+///
+/// let input = "FROM llama3.2\nPARAMETER brand_new_key 1\n";
+///
+/// assert!(Modelfile::parse_with(input, ParseOptions::default()).is_ok());
+/// assert!(Modelfile::parse_with(input, ParseOptions::strict()).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Reject unknown `PARAMETER` keys instead of keeping them.
+    pub strict: bool,
+}
+
+impl ParseOptions {
+    /// Options that reject anything this crate doesn't understand.
+    pub fn strict() -> Self {
+        ParseOptions { strict: true }
+    }
+
+    /// Options that keep anything this crate doesn't understand.
+    pub fn lenient() -> Self {
+        ParseOptions { strict: false }
+    }
+
+    /// Check parsed instructions for unknown parameters,
+    /// failing in strict mode and warning otherwise.
+    fn check(
+        self,
+        source: &str,
+        instructions: &[Spanned<Instruction>],
+    ) -> Result<(), Box<ParseDiagnostic>> {
+        for instruction in instructions {
+            let Instruction::Parameter(Parameter::Other { name, .. }) = &instruction.value else {
+                continue;
+            };
+
+            // the name is the first word after the keyword
+            let text = instruction.span.text(source);
+            let arguments = text.get("PARAMETER".len()..).unwrap_or_default();
+            let start = instruction.span.range.end - arguments.trim_start().len();
+            let range = start..start + name.len();
+
+            if self.strict {
+                return Err(Box::new(ParseDiagnostic::new(
+                    source,
+                    range,
+                    Some("PARAMETER"),
+                    "parameter name",
+                )));
+            }
+
+            tracing::warn!(
+                parameter = name,
+                position = %instruction.span.start,
+                "unknown parameter"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of [`Modelfile::parse_recovering`].
+#[derive(Debug, Clone)]
+pub struct PartialParse {
+    /// Every instruction that was parsed successfully, in source order.
+    pub instructions: Vec<Spanned<Instruction>>,
+    /// Every parse error, in source order.
+    pub errors: Vec<ParseDiagnostic>,
+}
+
+impl PartialParse {
+    /// `true` if the whole input was parsed without errors.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
 }
 
 impl From<BaseModel> for Modelfile {
     fn from(from: BaseModel) -> Self {
         Modelfile {
             from,
+            layers: Default::default(),
+            requires: Default::default(),
             parameters: Default::default(),
             template: Default::default(),
             system: Default::default(),
-            adapter: Default::default(),
+            adapters: Default::default(),
             license: Default::default(),
             messages: Default::default(),
         }
@@ -194,19 +375,7 @@ impl FromStr for Modelfile {
     type Err = ModelfileError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let instructions: Vec<Instruction> = instructions(input)
-            .map_err(|error| ModelfileError::Parse(error.to_string()))
-            .and_then(|(rest, instructions)| {
-                if rest.is_empty() {
-                    Ok(instructions)
-                } else {
-                    Err(ModelfileError::Parse(
-                        "parser did not consume all input".to_string(),
-                    ))
-                }
-            })?;
-
-        instructions.try_into()
+        Modelfile::parse_with(input, ParseOptions::default())
     }
 }
 
@@ -232,6 +401,7 @@ impl TryFrom<Vec<Instruction>> for Modelfile {
     From,
     Serialize,
     Deserialize,
+    PartialEq,
     strum::Display,
     AsRefStr,
     IntoStaticStr,
@@ -261,6 +431,9 @@ pub enum Instruction {
     Adapter(Adapter),
     License(License),
     Message(Message),
+    /// The Ollama versions that can run the model,
+    /// see [`Modelfile::check_version`].
+    Requires(Requires),
 }
 
 impl From<TensorFile> for Instruction {
@@ -270,128 +443,117 @@ impl From<TensorFile> for Instruction {
 }
 
 /// A file that represents a Tensor.
-/// Either a GGUF or safetensor file.
+/// Either a GGUF or safetensor file,
+/// a directory of safetensor files,
+/// or a blob already uploaded to Ollama.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TensorFile {
     Gguf(PathBuf),
     Safetensor(PathBuf),
+    /// A directory containing safetensor files.
+    Directory(PathBuf),
+    /// A blob referenced by its digest, written as `@sha256:<hex>`.
+    Blob(Digest),
 }
 
-impl AsRef<Path> for TensorFile {
-    fn as_ref(&self) -> &Path {
+impl TensorFile {
+    /// Pick the kind of tensor file from its path.
+    ///
+    /// Paths ending in `.gguf` or `.safetensors` are files,
+    /// anything else, like `./lora-v1.2` or a path ending in a separator,
+    /// is a directory.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path: PathBuf = path.into();
+
+        if ends_with_separator(&path) {
+            return TensorFile::Directory(path);
+        }
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gguf") => TensorFile::Gguf(path),
+            Some("safetensors") => TensorFile::Safetensor(path),
+            _ => TensorFile::Directory(path),
+        }
+    }
+
+    /// The path to the tensor file, unless it is a [`TensorFile::Blob`].
+    pub fn path(&self) -> Option<&Path> {
         match self {
-            TensorFile::Gguf(path_buf) => path_buf.as_ref(),
-            TensorFile::Safetensor(path_buf) => path_buf.as_ref(),
+            TensorFile::Gguf(path) | TensorFile::Safetensor(path) | TensorFile::Directory(path) => {
+                Some(path)
+            }
+            TensorFile::Blob(_) => None,
         }
     }
-}
 
-impl Display for TensorFile {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.as_ref().display().to_string())
+    /// Resolve a relative path against `base`,
+    /// usually the directory containing the Modelfile,
+    /// and expand a leading `~` to the home directory.
+    ///
+    /// Absolute paths, Windows drive paths and blobs are left alone,
+    /// and so are `~user` paths, or `~` paths when there is no
+    /// `HOME`, or `USERPROFILE` on Windows, to expand them with.
+    pub fn resolve(self, base: impl AsRef<Path>) -> Self {
+        let resolve = |path: PathBuf| resolve_path(path, base.as_ref());
+
+        match self {
+            TensorFile::Gguf(path) => TensorFile::Gguf(resolve(path)),
+            TensorFile::Safetensor(path) => TensorFile::Safetensor(resolve(path)),
+            TensorFile::Directory(path) => TensorFile::Directory(resolve(path)),
+            TensorFile::Blob(digest) => TensorFile::Blob(digest),
+        }
     }
 }
 
-/// A parameter for the model.
-/// [docs]
+/// See [`TensorFile::resolve`].
 ///
-/// [docs]: https://github.com/ollama/ollama/blob/main/docs/modelfile.md#parameter
-#[derive(Debug, Clone, EnumDiscriminants, strum::Display, Serialize, Deserialize, PartialEq)]
-#[strum_discriminants(name(ParameterName))]
-#[strum_discriminants(derive(
-    EnumIter,
-    Hash,
-    PartialOrd,
-    Ord,
-    IntoStaticStr,
-    EnumString,
-    VariantArray,
-    Serialize,
-    Deserialize
-))]
-#[strum_discriminants(strum(serialize_all = "snake_case"))]
-pub enum Parameter {
-    /// Enable Mirostat sampling for controlling perplexity.
-    /// (default: 0, 0 = disabled, 1 = Mirostat, 2 = Mirostat 2.0)
-    #[strum(to_string = "mirostat {0}")]
-    Mirostat(usize),
-    /// Influences how quickly the algorithm responds
-    /// to feedback from the generated text.
-    /// A lower learning rate will result in slower adjustments,
-    /// while a higher learning rate will make the algorithm more responsive.
-    /// (Default: 0.1)
-    #[strum(to_string = "mirostat_eta {0}")]
-    MirostatEta(f32),
-    /// Controls the balance between coherence and diversity of the output.
-    /// A lower value will result in more focused and coherent text.
-    /// (Default: 5.0)
-    #[strum(to_string = "mirostat_tau {0}")]
-    MirostatTau(f32),
-    /// Sets the size of the context window
-    /// used to generate the next token.
-    /// (Default: 2048)
-    #[strum(to_string = "num_ctx {0}")]
-    NumCtx(usize),
-    /// Sets how far back for the model
-    /// to look back to prevent repetition.
-    /// (Default: 64, 0 = disabled, -1 = num_ctx)
-    #[strum(to_string = "repeat_last_n {0}")]
-    RepeatLastN(usize),
-    /// Sets how strongly to penalize repetitions.
-    /// A higher value (e.g., 1.5) will penalize repetitions more strongly,
-    /// while a lower value (e.g., 0.9) will be more lenient.
-    /// (Default: 1.1)
-    #[strum(to_string = "repeat_penalty {0}")]
-    RepeatPenalty(f32),
-    /// The temperature of the model.
-    /// Increasing the temperature will make the model answer more creatively.
-    /// (Default: 0.8)
-    #[strum(to_string = "temperature {0}")]
-    Temperature(f32),
-    /// Sets the random number seed to use for generation.
-    /// Setting this to a specific number will make the model generate the same text
-    /// for the same prompt.
-    /// (Default: 0)
-    #[strum(to_string = "seed {0}")]
-    Seed(usize),
-    /// Sets the stop sequences to use.
-    /// When this pattern is encountered the LLM will stop generating text and return.
-    /// Multiple stop patterns may be set by specifying multiple separate stop parameters
-    /// in a modelfile.
-    #[strum(to_string = "stop {0}")]
-    Stop(String),
-    /// Tail free sampling is used to reduce the impact
-    /// of less probable tokens from the output.
-    /// A higher value (e.g., 2.0) will reduce the impact more,
-    /// while a value of 1.0 disables this setting.
-    /// (default: 1)
-    #[strum(to_string = "tfs_z {0}")]
-    TfsZ(f32),
-    /// Maximum number of tokens to predict when generating text.
-    /// (Default: 128, -1 = infinite generation, -2 = fill context)
-    #[strum(to_string = "num_predict {0}")]
-    NumPredict(usize),
-    /// Reduces the probability of generating nonsense.
-    /// A higher value (e.g. 100) will give more diverse answers,
-    /// while a lower value (e.g. 10) will be more conservative.
-    /// (Default: 40)
-    #[strum(to_string = "top_k {0}")]
-    TopK(usize),
-    /// Works together with top-k.
-    /// A higher value (e.g., 0.95) will lead to more diverse text,
-    /// while a lower value (e.g., 0.5) will generate more focused and conservative text.
-    /// (Default: 0.9)
-    #[strum(to_string = "top_p {0}")]
-    TopP(f32),
-    /// Alternative to the top_p,
-    /// and aims to ensure a balance of quality and variety.
-    /// The parameter p represents the minimum probability for a token to be considered,
-    /// relative to the probability of the most likely token.
-    /// For example, with p=0.05 and the most likely token having a probability of 0.9,
-    /// logits with a value less than 0.045 are filtered out.
-    /// (Default: 0.0)
-    #[strum(to_string = "min_p {0}")]
-    MinP(f32),
+/// A trailing separator is kept, it marks the path as a directory.
+pub(crate) fn resolve_path(path: PathBuf, base: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    let is_home = text.starts_with('~');
+    let is_drive = text.as_bytes().get(1) == Some(&b':')
+        && text.chars().next().is_some_and(|c| c.is_ascii_alphabetic());
+
+    let resolved = match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ if path.is_relative() && !is_home && !is_drive => {
+            base.join(path.strip_prefix(".").unwrap_or(&path))
+        }
+        _ => return path,
+    };
+
+    let mut resolved = resolved.into_os_string();
+    if ends_with_separator(&path) && !ends_with_separator(Path::new(&resolved)) {
+        resolved.push(std::path::MAIN_SEPARATOR_STR);
+    }
+    resolved.into()
+}
+
+/// The home directory of the user, from `HOME`, or `USERPROFILE` on Windows.
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+fn ends_with_separator(path: &Path) -> bool {
+    path.as_os_str().to_string_lossy().ends_with(['/', '\\'])
+}
+
+impl Display for TensorFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TensorFile::Gguf(path) | TensorFile::Safetensor(path) | TensorFile::Directory(path) => {
+                let path = path.display().to_string();
+                if path.contains(char::is_whitespace) {
+                    write!(f, "\"{path}\"")
+                } else {
+                    f.write_str(&path)
+                }
+            }
+            TensorFile::Blob(digest) => write!(f, "@{digest}"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -400,6 +562,7 @@ mod tests {
     use test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR};
 
     use super::{test_data::TEST_BAD_DATA_DIR, *};
+    use crate::message::ChatMessage;
 
     #[test]
     fn modelfiles_are_parsed() {
@@ -485,6 +648,33 @@ mod tests {
         assert_snapshot!(render);
     }
 
+    #[test]
+    fn projectors_are_rendered_as_more_froms() {
+        let contents = std::fs::read_to_string(format!(
+            "{TEST_GOOD_DATA_DIR}/x.llama3.2-vision.latest.Modelfile"
+        ))
+        .expect("should read test data");
+        let modelfile: Modelfile = contents.parse().expect("should parse Modelfile");
+
+        let render = modelfile.render();
+        let round_trip: Modelfile = render.parse().expect("should parse rendered Modelfile");
+        assert_eq!(round_trip, modelfile);
+
+        let froms: Vec<String> = modelfile
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Instruction::From(from) => Some(from.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_debug_snapshot!(froms, @r#"
+        [
+            "/mnt/space/ollama/models/blobs/sha256-652e85aa1e14c9087a4ccc3ab516fb794cbcf152f8b4b8d3c0b828da4ada62d9",
+            "/mnt/space/ollama/models/blobs/sha256-622429e8d31810962dd984bc98559e706db2fb1d40e99cb073beb7148d909d73",
+        ]
+        "#);
+    }
+
     #[test]
     fn snapshot_parameters() {
         let param = Parameter::Stop("<eos>".into());
@@ -494,7 +684,7 @@ mod tests {
 
     #[test]
     fn minimal_modelfile_produces_iterator() {
-        let base_model = BaseModel::from("llama8.2");
+        let base_model: BaseModel = "llama8.2".parse().expect("should parse base model");
 
         let modelfile = Modelfile::from(base_model);
 
@@ -504,6 +694,529 @@ mod tests {
         assert_eq!(instructions.len(), 1);
     }
 
+    #[test]
+    fn instructions_have_spans() {
+        let input = "# a comment\nFROM llama3.2\n\nTEMPLATE \"\"\"{{ .System }}\n{{ .Prompt }}\"\"\"\nPARAMETER num_ctx 4096\n";
+
+        let instructions =
+            Modelfile::parse_spanned(input).expect("should parse spanned instructions");
+
+        let spans: Vec<(InstructionName, String, String, &str)> = instructions
+            .iter()
+            .map(|instruction| {
+                (
+                    InstructionName::from(&instruction.value),
+                    instruction.span.start.to_string(),
+                    instruction.span.end.to_string(),
+                    instruction.span.text(input),
+                )
+            })
+            .collect();
+
+        assert_debug_snapshot!(spans, @r#"
+        [
+            (
+                From,
+                "2:1",
+                "2:14",
+                "FROM llama3.2",
+            ),
+            (
+                Template,
+                "4:1",
+                "5:17",
+                "TEMPLATE \"\"\"{{ .System }}\n{{ .Prompt }}\"\"\"",
+            ),
+            (
+                Parameter,
+                "6:1",
+                "6:23",
+                "PARAMETER num_ctx 4096",
+            ),
+        ]
+        "#);
+    }
+
+    #[test]
+    fn parse_errors_are_located() {
+        let error = "FROM llama3.2\nPARAMETER num_ctx lots\n"
+            .parse::<Modelfile>()
+            .expect_err("should not parse a bad parameter value");
+
+        assert_snapshot!(error, @r"
+        error: invalid value for num_ctx, expected integer in PARAMETER
+         --> 2:19
+          |
+        2 | PARAMETER num_ctx lots
+          |                   ^
+        ");
+
+        let error = Modelfile::parse_with(
+            "FROM llama3.2\nPARAMETER\tnot_a_parameter 1\n",
+            ParseOptions::strict(),
+        )
+        .expect_err("should not parse an unknown parameter in strict mode");
+
+        assert_snapshot!(error, @r"
+        error: expected parameter name in PARAMETER
+         --> 2:11
+          |
+        2 | PARAMETER	not_a_parameter 1
+          |          	^
+        ");
+
+        let error = "FROM llama3.2\n\nSYSTEM \"\"\"unterminated\nsystem message\n"
+            .parse::<Modelfile>()
+            .expect_err("should not parse an unterminated string");
+
+        assert_snapshot!(error, @r#"
+        error: expected closing quotes in SYSTEM
+         --> 3:1
+          |
+        3 | SYSTEM """unterminated
+          | ^
+        "#);
+
+        let error = "FROM llama3.2\nINSTRUCTION unknown\n"
+            .parse::<Modelfile>()
+            .expect_err("should not parse an unknown instruction");
+
+        assert_snapshot!(error, @r"
+        error: expected an instruction (FROM, PARAMETER, TEMPLATE, SYSTEM, ADAPTER, LICENSE, MESSAGE, REQUIRES) or a comment
+         --> 2:1
+          |
+        2 | INSTRUCTION unknown
+          | ^
+        ");
+    }
+
+    #[test]
+    fn bad_values_are_errors_not_panics() {
+        let errors: Vec<String> = [
+            "FROM llama3.2\nPARAMETER seed 999999999999999999999999999999\n",
+            "FROM llama3.2\nADAPTER @sha256:not-a-digest\n",
+            "FROM llama3.2\nMESSAGE narrator hello\n",
+        ]
+        .into_iter()
+        .map(|input| {
+            let error = input
+                .parse::<Modelfile>()
+                .expect_err("should not parse a bad value");
+            let ModelfileError::Parse(diagnostic) = error else {
+                panic!("should be a parse error");
+            };
+            format!("{}: {}", diagnostic.span.start, diagnostic.message())
+        })
+        .collect();
+
+        assert_debug_snapshot!(errors, @r#"
+        [
+            "2:16: invalid value for seed, expected integer in PARAMETER",
+            "2:10: expected sha256 digest in ADAPTER",
+            "2:9: expected message role in MESSAGE",
+        ]
+        "#);
+    }
+
+    #[test]
+    fn adapters_are_rendered_and_resolved() {
+        let adapters: Vec<(String, String)> = include_str!("./testdata/adapters.txt")
+            .lines()
+            .map(|line| {
+                let modelfile: Modelfile = format!("FROM llama3.2\n{line}\n")
+                    .parse()
+                    .expect("should parse adapter");
+                let [adapter] =
+                    <[Adapter; 1]>::try_from(modelfile.clone().resolve_paths("/models").adapters)
+                        .expect("should have an adapter");
+
+                let rendered: Modelfile = modelfile
+                    .render()
+                    .parse()
+                    .expect("should parse rendered adapter");
+                assert_eq!(rendered, modelfile);
+
+                // resolved paths keep their kind
+                let resolved: Modelfile = format!("FROM llama3.2\nADAPTER {adapter}\n")
+                    .parse()
+                    .expect("should parse resolved adapter");
+                assert_eq!(resolved.adapters, std::slice::from_ref(&adapter));
+
+                let home = home_dir().unwrap_or_default().display().to_string();
+                let adapter = adapter.to_string();
+                let adapter = match adapter.strip_prefix(&home) {
+                    Some(rest) if !home.is_empty() => format!("$HOME{rest}"),
+                    _ => adapter,
+                };
+                (line.to_string(), adapter)
+            })
+            .collect();
+
+        assert_debug_snapshot!(adapters, @r#"
+        [
+            (
+                "adapter ./ollama-lora.gguf",
+                "/models/ollama-lora.gguf",
+            ),
+            (
+                "ADAPTER ./ollama-lora.safetensors",
+                "/models/ollama-lora.safetensors",
+            ),
+            (
+                "ADAPTER ./adapters/sql-lora/",
+                "/models/adapters/sql-lora/",
+            ),
+            (
+                "ADAPTER ~/adapters/sql-lora",
+                "$HOME/adapters/sql-lora",
+            ),
+            (
+                "ADAPTER ./adapters/lora-v1.2",
+                "/models/adapters/lora-v1.2",
+            ),
+            (
+                "ADAPTER qwen2.5-lora",
+                "/models/qwen2.5-lora",
+            ),
+            (
+                "ADAPTER ./lora.v1/",
+                "/models/lora.v1/",
+            ),
+            (
+                "ADAPTER \"./my adapters/ollama-lora.gguf\"",
+                "\"/models/my adapters/ollama-lora.gguf\"",
+            ),
+            (
+                "ADAPTER C:\\Users\\me\\ollama-lora.gguf",
+                "C:\\Users\\me\\ollama-lora.gguf",
+            ),
+            (
+                "ADAPTER @sha256:6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa",
+                "@sha256:6a0746a1ec1aef3e7ec53868f220ff6e389f6f8ef87a01d77c96807de94ca2aa",
+            ),
+        ]
+        "#);
+    }
+
+    #[test]
+    fn tensor_files_are_classified() {
+        assert_debug_snapshot!(
+            [
+                "./lora.gguf",
+                "./lora.safetensors",
+                "./lora.safetensors/",
+                "./adapters/lora-v1.2",
+            ]
+            .map(TensorFile::from_path),
+            @r#"
+        [
+            Gguf(
+                "./lora.gguf",
+            ),
+            Safetensor(
+                "./lora.safetensors",
+            ),
+            Directory(
+                "./lora.safetensors/",
+            ),
+            Directory(
+                "./adapters/lora-v1.2",
+            ),
+        ]
+        "#
+        );
+    }
+
+    #[test]
+    fn stacked_adapters_keep_their_order() {
+        let modelfile: Modelfile =
+            "FROM llama3.2\nADAPTER ./domain-lora.gguf\nADAPTER ./style-lora.safetensors\n"
+                .parse()
+                .expect("should parse adapters");
+
+        let rendered: Modelfile = modelfile
+            .render()
+            .parse()
+            .expect("should parse rendered adapters");
+        assert_eq!(rendered, modelfile);
+
+        let json = serde_json::to_string(&modelfile).expect("should serialize Modelfile");
+        let deserialized: Modelfile =
+            serde_json::from_str(&json).expect("should deserialize Modelfile");
+        assert_eq!(deserialized, modelfile);
+
+        let adapters: Vec<String> = modelfile
+            .clone()
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Instruction::Adapter(adapter) => Some(adapter.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_debug_snapshot!(adapters, @r#"
+        [
+            "./domain-lora.gguf",
+            "./style-lora.safetensors",
+        ]
+        "#);
+
+        let domain = modelfile.adapters[0].clone();
+        let style = modelfile.adapters[1].clone();
+        let edited = modelfile
+            .build_on()
+            .adapter(TensorFile::Gguf("./tone-lora.gguf".into()))
+            .replace_adapter(&domain, TensorFile::Gguf("./legal-lora.gguf".into()))
+            .and_then(|builder| builder.remove_adapter(&style))
+            .and_then(ModelfileBuilder::build)
+            .expect("should edit adapters");
+        assert_snapshot!(edited.render(), @r"
+        # This file was generated by the Ollama-CLI client
+        FROM llama3.2
+
+        ADAPTER ./legal-lora.gguf
+        ADAPTER ./tone-lora.gguf
+        ");
+
+        assert!(edited.build_on().remove_adapter(&style).is_err());
+    }
+
+    #[test]
+    fn system_messages_are_built_from_any_string() {
+        let pirate = String::from("You are a pirate.");
+        let borrowed = "FROM llama3.2"
+            .parse::<Modelfile>()
+            .expect("should parse FROM")
+            .build_on()
+            .system(&pirate)
+            .and_then(ModelfileBuilder::build)
+            .expect("should build from a borrowed String");
+        let parsed: Modelfile = "FROM llama3.2\nSYSTEM You are a pirate."
+            .parse()
+            .expect("should parse SYSTEM");
+
+        assert_eq!(borrowed, parsed);
+        assert!(parsed
+            .build_on()
+            .system(std::borrow::Cow::Borrowed("You are a parrot."))
+            .is_err());
+    }
+
+    #[test]
+    fn tool_messages_round_trip() {
+        let modelfile: Modelfile = r#"FROM llama3.2
+MESSAGE user What's the weather in Paris?
+MESSAGE assistant {"tool_calls": [{"id": "call_1", "function": {"index": 0, "name": "get_weather", "arguments": {"city": "Paris"}}}]}
+MESSAGE tool 22 degrees and sunny
+MESSAGE assistant It's 22 degrees and sunny in Paris.
+"#
+        .parse()
+        .expect("should parse tool messages");
+
+        let messages: &[Message] = modelfile.messages.as_ref();
+        assert!(messages
+            .iter()
+            .all(|message| message.tool_calls().is_empty()));
+        assert_eq!(
+            modelfile
+                .to_create_request("weather")
+                .expect("should create request")
+                .messages[1]
+                .content,
+            r#"{"tool_calls": [{"id": "call_1", "function": {"index": 0, "name": "get_weather", "arguments": {"city": "Paris"}}}]}"#
+        );
+
+        let chat = modelfile
+            .to_create_request("weather")
+            .expect("should create request")
+            .read_tool_calls()
+            .messages;
+        let json = serde_json::to_string_pretty(&chat).expect("should serialize chat messages");
+        assert_snapshot!(json, @r#"
+        [
+          {
+            "role": "user",
+            "content": "What's the weather in Paris?"
+          },
+          {
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [
+              {
+                "function": {
+                  "name": "get_weather",
+                  "arguments": {
+                    "city": "Paris"
+                  },
+                  "index": 0
+                },
+                "id": "call_1"
+              }
+            ]
+          },
+          {
+            "role": "tool",
+            "content": "22 degrees and sunny"
+          },
+          {
+            "role": "assistant",
+            "content": "It's 22 degrees and sunny in Paris."
+          }
+        ]
+        "#);
+
+        let calls: Vec<Message> = serde_json::from_str::<Vec<ChatMessage>>(&json)
+            .expect("should deserialize chat messages")
+            .into_iter()
+            .map(Message::try_from)
+            .collect::<Result<_, _>>()
+            .expect("should convert chat messages");
+        let created = calls
+            .into_iter()
+            .fold(
+                ModelfileBuilder::default()
+                    .from("llama3.2")
+                    .expect("should parse model"),
+                ModelfileBuilder::message,
+            )
+            .build()
+            .expect("should build Modelfile");
+
+        assert_snapshot!(created.render(), @r#"
+        # This file was generated by the Ollama-CLI client
+        FROM llama3.2
+
+        MESSAGE user What's the weather in Paris?
+        MESSAGE assistant {"tool_calls":[{"function":{"arguments":{"city":"Paris"},"index":0,"name":"get_weather"},"id":"call_1"}]}
+        MESSAGE tool 22 degrees and sunny
+        MESSAGE assistant It's 22 degrees and sunny in Paris.
+        "#);
+
+        let toml = toml::to_string(&created).expect("should serialize tool messages");
+        assert_eq!(
+            toml::from_str::<Modelfile>(&toml).expect("should deserialize tool messages"),
+            created
+        );
+
+        let shown: Modelfile = created
+            .render()
+            .parse()
+            .expect("should parse rendered tool messages");
+        assert_eq!(
+            shown
+                .to_create_request("weather")
+                .expect("should create request")
+                .read_tool_calls()
+                .messages,
+            chat
+        );
+    }
+
+    #[test]
+    fn only_assistant_tool_call_objects_are_read_as_tool_calls() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+        };
+        let calls = r#"{"tool_calls": [{"function": {"name": "get_weather"}}]}"#;
+        let unchanged = [
+            message("user", calls),
+            message("tool", calls),
+            message("assistant", r#"{"tool_calls": []}"#),
+            message(
+                "assistant",
+                r#"{"tool_calls": [{"function": {"name": "get_weather"}}], "note": "text"}"#,
+            ),
+            message("assistant", "{not json"),
+        ];
+
+        for message in unchanged {
+            assert_eq!(message.clone().read_tool_calls(), message);
+        }
+        assert_eq!(
+            message("assistant", calls)
+                .read_tool_calls()
+                .tool_calls
+                .iter()
+                .map(|call| call.function.name.as_str())
+                .collect::<Vec<_>>(),
+            ["get_weather"]
+        );
+    }
+
+    #[test]
+    fn parse_recovering_reports_every_error() {
+        let input = "FROM llama3.2\n\
+            PARAMETER num_ctx lots\n\
+            SYSTEM \"\"\"You are a helpful assistant.\"\"\"\n\
+            this line is not an instruction\n\
+            and neither is this one\n\
+            PARAMETER temprature 0.7\n\
+            PARAMETER temperature 0.7\n";
+
+        let PartialParse {
+            instructions,
+            errors,
+        } = Modelfile::parse_recovering(input);
+
+        let names: Vec<InstructionName> = instructions
+            .iter()
+            .map(|instruction| InstructionName::from(&instruction.value))
+            .collect();
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|error| format!("{}: {}", error.span.start, error.message()))
+            .collect();
+
+        assert_debug_snapshot!((names, errors), @r#"
+        (
+            [
+                From,
+                System,
+                Parameter,
+                Parameter,
+            ],
+            [
+                "2:19: invalid value for num_ctx, expected integer in PARAMETER",
+                "4:1: expected an instruction (FROM, PARAMETER, TEMPLATE, SYSTEM, ADAPTER, LICENSE, MESSAGE, REQUIRES) or a comment",
+            ],
+        )
+        "#);
+    }
+
+    #[test]
+    fn unknown_parameters_round_trip() {
+        let input =
+            "FROM llama3.2\nPARAMETER num_ctx 4096\nPARAMETER brand_new_key \"some value\"\n";
+
+        let modelfile: Modelfile = input.parse().expect("should keep unknown parameters");
+
+        assert_debug_snapshot!(modelfile.parameters, @r#"
+        Parameters(
+            [
+                NumCtx(
+                    4096,
+                ),
+                Other {
+                    name: "brand_new_key",
+                    value: "some value",
+                },
+            ],
+        )
+        "#);
+
+        let rendered: Modelfile = modelfile
+            .render()
+            .parse()
+            .expect("should parse rendered Modelfile");
+        assert_eq!(rendered, modelfile);
+
+        let serialized = toml::to_string(&modelfile).expect("should serialize Modelfile");
+        let deserialized: Modelfile =
+            toml::from_str(&serialized).expect("should deserialize Modelfile");
+        assert_eq!(deserialized, modelfile);
+    }
+
     #[test]
     fn modelfile_instructions_snapshot() {
         let test_data: Vec<TestData> = load_modelfiles(TEST_GOOD_DATA_DIR);
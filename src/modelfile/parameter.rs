@@ -0,0 +1,604 @@
+//! The `PARAMETER` keys that [Ollama] accepts in a [`super::Modelfile`].
+//!
+//! Every parameter is declared once in the `parameters!` table below,
+//! which generates [`Parameter`], [`ParameterName`],
+//! and the parser for each parameter's value.
+//! Adding a key only means adding a line to the table.
+//!
+//! Keys that aren't in the table are kept as [`Parameter::Other`],
+//! so Modelfiles using parameters newer than this crate still parse.
+//! See [`super::ParseOptions`] to reject them instead.
+//!
+//! [Ollama]: https://github.com/ollama/ollama/blob/main/docs/modelfile.md#parameter
+
+use std::fmt::Display;
+
+use nom::{combinator::map_res, error::context, sequence::terminated, Parser as _};
+use serde::{Deserialize, Serialize};
+use strum::{EnumDiscriminants, EnumIter, EnumString, IntoStaticStr, VariantArray};
+use thiserror::Error;
+
+use super::{
+    cst::Quoting,
+    parser::{
+        bool_parameter_value, end_of_value, float_parameter_value, int_parameter_value,
+        signed_int_parameter_value, string_parameter_value, ParseResult,
+    },
+};
+
+/// The type of value a [`Parameter`] takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Integer,
+    Float,
+    Boolean,
+    String,
+}
+
+/// A type that can be the value of a [`Parameter`].
+pub(crate) trait ParameterValue: Sized + Display {
+    const KIND: ValueKind;
+
+    fn parse(input: &str) -> ParseResult<'_, Self>;
+
+    /// Write the value as it should appear in a Modelfile.
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ParameterValue for usize {
+    const KIND: ValueKind = ValueKind::Integer;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        int_parameter_value(input)
+    }
+}
+
+impl ParameterValue for i32 {
+    const KIND: ValueKind = ValueKind::Integer;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        signed_int_parameter_value(input)
+    }
+}
+
+impl ParameterValue for i64 {
+    const KIND: ValueKind = ValueKind::Integer;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        signed_int_parameter_value(input)
+    }
+}
+
+impl ParameterValue for f32 {
+    const KIND: ValueKind = ValueKind::Float;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        float_parameter_value(input)
+    }
+}
+
+impl ParameterValue for bool {
+    const KIND: ValueKind = ValueKind::Boolean;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        bool_parameter_value(input)
+    }
+}
+
+impl ParameterValue for NumPredict {
+    const KIND: ValueKind = ValueKind::Integer;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        context(
+            "-1, -2 or a number of tokens",
+            map_res(signed_int_parameter_value::<i64>, NumPredict::try_from),
+        )
+        .parse(input)
+    }
+}
+
+impl ParameterValue for RepeatLastN {
+    const KIND: ValueKind = ValueKind::Integer;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        context(
+            "-1 or a number of tokens",
+            map_res(signed_int_parameter_value::<i64>, RepeatLastN::try_from),
+        )
+        .parse(input)
+    }
+}
+
+impl ParameterValue for String {
+    const KIND: ValueKind = ValueKind::String;
+
+    fn parse(input: &str) -> ParseResult<'_, Self> {
+        string_parameter_value(input)
+    }
+
+    /// Quote values that would otherwise be trimmed or unquoted when parsed.
+    fn render(&self) -> String {
+        if self.is_empty() || self.trim() != self || self.starts_with('"') {
+            format!("\"{self}\"")
+        } else {
+            self.clone()
+        }
+    }
+}
+
+/// Declare every [`Parameter`] in one place.
+///
+/// Each entry is the name used in a Modelfile,
+/// the variant name in `snake_case`,
+/// and the variant of [`Parameter`] with the type of its value.
+macro_rules! parameters {
+    ($(
+        $(#[$meta:meta])*
+        $name:literal => $variant:ident($value:ty),
+    )*) => {
+        /// A parameter for the model.
+        /// [docs]
+        ///
+        /// [docs]: https://github.com/ollama/ollama/blob/main/docs/modelfile.md#parameter
+        #[derive(Debug, Clone, EnumDiscriminants, Serialize, Deserialize, PartialEq)]
+        #[strum_discriminants(name(ParameterName))]
+        #[strum_discriminants(derive(
+            EnumIter,
+            Hash,
+            PartialOrd,
+            Ord,
+            IntoStaticStr,
+            strum::Display,
+            EnumString,
+            VariantArray,
+            Serialize,
+            Deserialize
+        ))]
+        #[strum_discriminants(strum(serialize_all = "snake_case"))]
+        pub enum Parameter {
+            $(
+                $(#[$meta])*
+                $variant($value),
+            )*
+            /// A parameter this crate doesn't know about,
+            /// with its value kept as a string.
+            Other { name: String, value: String },
+        }
+
+        impl Parameter {
+            /// The name of the parameter as it is written in a Modelfile.
+            pub fn name(&self) -> &str {
+                match self {
+                    $(Parameter::$variant(_) => ParameterName::$variant.into(),)*
+                    Parameter::Other { name, .. } => name,
+                }
+            }
+
+            /// The value of the parameter as it is written in a Modelfile.
+            pub fn value(&self) -> String {
+                match self {
+                    $(Parameter::$variant(value) => value.render(),)*
+                    Parameter::Other { value, .. } => value.render(),
+                }
+            }
+
+            /// Parse the value of the parameter called `name`.
+            ///
+            /// Unknown names are parsed as [`Parameter::Other`].
+            pub(crate) fn parse_value<'a>(name: &str, input: &'a str) -> ParseResult<'a, Parameter> {
+                match name.parse() {
+                    $(Ok(ParameterName::$variant) => context(
+                        ParameterName::$variant.value_context(),
+                        terminated(<$value as ParameterValue>::parse, end_of_value),
+                    )
+                    .map(Parameter::$variant)
+                    .parse(input),)*
+                    Ok(ParameterName::Other) | Err(_) => string_parameter_value
+                        .map(|value| Parameter::Other {
+                            name: name.to_string(),
+                            value,
+                        })
+                        .parse(input),
+                }
+            }
+        }
+
+        impl ParameterName {
+            /// The type of value this parameter takes.
+            pub fn kind(self) -> ValueKind {
+                match self {
+                    $(ParameterName::$variant => <$value as ParameterValue>::KIND,)*
+                    ParameterName::Other => ValueKind::String,
+                }
+            }
+
+            /// The [`context`] label of the value, like `invalid value for num_ctx`.
+            fn value_context(self) -> &'static str {
+                match self {
+                    $(ParameterName::$variant => concat!("invalid value for ", $name),)*
+                    ParameterName::Other => "value",
+                }
+            }
+        }
+    };
+}
+
+parameters! {
+    /// Enable Mirostat sampling for controlling perplexity.
+    /// (default: 0, 0 = disabled, 1 = Mirostat, 2 = Mirostat 2.0)
+    "mirostat" => Mirostat(usize),
+    /// Influences how quickly the algorithm responds
+    /// to feedback from the generated text.
+    /// A lower learning rate will result in slower adjustments,
+    /// while a higher learning rate will make the algorithm more responsive.
+    /// (Default: 0.1)
+    "mirostat_eta" => MirostatEta(f32),
+    /// Controls the balance between coherence and diversity of the output.
+    /// A lower value will result in more focused and coherent text.
+    /// (Default: 5.0)
+    "mirostat_tau" => MirostatTau(f32),
+    /// Sets the size of the context window
+    /// used to generate the next token.
+    /// (Default: 2048)
+    "num_ctx" => NumCtx(usize),
+    /// Sets how far back for the model
+    /// to look back to prevent repetition.
+    /// (Default: 64, 0 = disabled, -1 = num_ctx)
+    "repeat_last_n" => RepeatLastN(RepeatLastN),
+    /// Sets how strongly to penalize repetitions.
+    /// A higher value (e.g., 1.5) will penalize repetitions more strongly,
+    /// while a lower value (e.g., 0.9) will be more lenient.
+    /// (Default: 1.1)
+    "repeat_penalty" => RepeatPenalty(f32),
+    /// The temperature of the model.
+    /// Increasing the temperature will make the model answer more creatively.
+    /// (Default: 0.8)
+    "temperature" => Temperature(f32),
+    /// Sets the random number seed to use for generation.
+    /// Setting this to a specific number will make the model generate the same text
+    /// for the same prompt.
+    /// (Default: -1, -1 = random)
+    "seed" => Seed(i64),
+    /// Sets the stop sequences to use.
+    /// When this pattern is encountered the LLM will stop generating text and return.
+    /// Multiple stop patterns may be set by specifying multiple separate stop parameters
+    /// in a modelfile.
+    "stop" => Stop(String),
+    /// Tail free sampling is used to reduce the impact
+    /// of less probable tokens from the output.
+    /// A higher value (e.g., 2.0) will reduce the impact more,
+    /// while a value of 1.0 disables this setting.
+    /// (default: 1)
+    "tfs_z" => TfsZ(f32),
+    /// Maximum number of tokens to predict when generating text.
+    /// (Default: 128, -1 = infinite generation, -2 = fill context)
+    "num_predict" => NumPredict(NumPredict),
+    /// Reduces the probability of generating nonsense.
+    /// A higher value (e.g. 100) will give more diverse answers,
+    /// while a lower value (e.g. 10) will be more conservative.
+    /// (Default: 40)
+    "top_k" => TopK(usize),
+    /// Works together with top-k.
+    /// A higher value (e.g., 0.95) will lead to more diverse text,
+    /// while a lower value (e.g., 0.5) will generate more focused and conservative text.
+    /// (Default: 0.9)
+    "top_p" => TopP(f32),
+    /// Alternative to the top_p,
+    /// and aims to ensure a balance of quality and variety.
+    /// The parameter p represents the minimum probability for a token to be considered,
+    /// relative to the probability of the most likely token.
+    /// For example, with p=0.05 and the most likely token having a probability of 0.9,
+    /// logits with a value less than 0.045 are filtered out.
+    /// (Default: 0.0)
+    "min_p" => MinP(f32),
+    /// Number of tokens from the start of the prompt
+    /// to keep when the context window is full.
+    /// (Default: 4, -1 = all)
+    "num_keep" => NumKeep(i32),
+    /// Locally typical sampling.
+    /// Only tokens whose probability is close to the expected
+    /// information content of the distribution are considered.
+    /// (Default: 1.0, 1.0 = disabled)
+    "typical_p" => TypicalP(f32),
+    /// Penalizes tokens that have already appeared in the output,
+    /// regardless of how often.
+    /// (Default: 0.0)
+    "presence_penalty" => PresencePenalty(f32),
+    /// Penalizes tokens by how often they have already appeared in the output.
+    /// (Default: 0.0)
+    "frequency_penalty" => FrequencyPenalty(f32),
+    /// Whether the newline token is subject to the repeat penalty.
+    /// (Default: true)
+    "penalize_newline" => PenalizeNewline(bool),
+    /// Enable NUMA support.
+    /// (Default: false)
+    "numa" => Numa(bool),
+    /// Number of prompt tokens processed in parallel.
+    /// (Default: 512)
+    "num_batch" => NumBatch(usize),
+    /// Number of layers to offload to the GPU.
+    /// (Default: -1, -1 = decided by Ollama, 0 = CPU only)
+    "num_gpu" => NumGpu(i32),
+    /// The GPU to use for small tensors when splitting a model across GPUs.
+    /// (Default: 0)
+    "main_gpu" => MainGpu(usize),
+    /// Reduce VRAM usage at the cost of performance.
+    /// (Default: false)
+    "low_vram" => LowVram(bool),
+    /// Only load the vocabulary, not the weights.
+    /// (Default: false)
+    "vocab_only" => VocabOnly(bool),
+    /// Memory map the model weights instead of reading them into memory.
+    /// (Default: decided by Ollama)
+    "use_mmap" => UseMmap(bool),
+    /// Lock the model weights in memory so they can't be swapped out.
+    /// (Default: false)
+    "use_mlock" => UseMlock(bool),
+    /// Number of threads to use during generation.
+    /// (Default: decided by Ollama)
+    "num_thread" => NumThread(usize),
+}
+
+impl Parameter {
+    /// The arguments of a `PARAMETER` instruction for the parameter,
+    /// with a text value written in double quotes if `quoting` asks for them
+    /// and the value can be quoted.
+    pub(crate) fn arguments(&self, quoting: Quoting) -> String {
+        let value = self.value();
+        let quote = quoting == Quoting::Single
+            && ParameterName::from(self).kind() == ValueKind::String
+            && !value.contains('"');
+
+        if quote {
+            format!("{} \"{value}\"", self.name())
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl Display for Parameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name(), self.value())
+    }
+}
+
+/// An integer parameter value outside of the range the parameter accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("{value} is not a valid value for {parameter}")]
+pub struct OutOfRange {
+    pub parameter: ParameterName,
+    pub value: i64,
+}
+
+/// The value of [`Parameter::NumPredict`].
+///
+/// Serialized as the integer Ollama uses,
+/// with `-1` and `-2` for the sentinel values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub enum NumPredict {
+    /// Generate until the model stops. (`-1`)
+    Infinite,
+    /// Generate until the context is full. (`-2`)
+    FillContext,
+    /// Generate at most this many tokens.
+    Tokens(u32),
+}
+
+impl TryFrom<i64> for NumPredict {
+    type Error = OutOfRange;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            -1 => Ok(NumPredict::Infinite),
+            -2 => Ok(NumPredict::FillContext),
+            tokens => u32::try_from(tokens)
+                .map(NumPredict::Tokens)
+                .map_err(|_| OutOfRange {
+                    parameter: ParameterName::NumPredict,
+                    value,
+                }),
+        }
+    }
+}
+
+impl From<NumPredict> for i64 {
+    fn from(value: NumPredict) -> Self {
+        match value {
+            NumPredict::Infinite => -1,
+            NumPredict::FillContext => -2,
+            NumPredict::Tokens(tokens) => tokens.into(),
+        }
+    }
+}
+
+impl Display for NumPredict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", i64::from(*self))
+    }
+}
+
+/// The value of [`Parameter::RepeatLastN`].
+///
+/// Serialized as the integer Ollama uses,
+/// with `-1` for [`RepeatLastN::ContextSize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub enum RepeatLastN {
+    /// Look back over the whole context window, `num_ctx`. (`-1`)
+    ContextSize,
+    /// Look back this many tokens.
+    /// `Tokens(0)` disables the repeat penalty.
+    Tokens(u32),
+}
+
+impl TryFrom<i64> for RepeatLastN {
+    type Error = OutOfRange;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            -1 => Ok(RepeatLastN::ContextSize),
+            tokens => u32::try_from(tokens)
+                .map(RepeatLastN::Tokens)
+                .map_err(|_| OutOfRange {
+                    parameter: ParameterName::RepeatLastN,
+                    value,
+                }),
+        }
+    }
+}
+
+impl From<RepeatLastN> for i64 {
+    fn from(value: RepeatLastN) -> Self {
+        match value {
+            RepeatLastN::ContextSize => -1,
+            RepeatLastN::Tokens(tokens) => tokens.into(),
+        }
+    }
+}
+
+impl Display for RepeatLastN {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", i64::from(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use strum::IntoEnumIterator as _;
+
+    use crate::modelfile::{error::ModelfileError, parser::INVALID_VALUE, Modelfile};
+
+    use super::*;
+
+    #[test]
+    fn value_labels_name_the_parameter() {
+        for name in ParameterName::iter().filter(|name| *name != ParameterName::Other) {
+            assert_eq!(name.value_context(), format!("{INVALID_VALUE}{name}"));
+        }
+    }
+
+    #[test]
+    fn sentinel_values_round_trip() {
+        let input = "FROM llama3.2\n\
+            PARAMETER num_predict -1\n\
+            PARAMETER num_predict -2\n\
+            PARAMETER num_predict 128\n\
+            PARAMETER repeat_last_n -1\n\
+            PARAMETER repeat_last_n 0\n\
+            PARAMETER seed -1\n";
+
+        let modelfile: Modelfile = input.parse().expect("should parse sentinel values");
+        let parameters: Vec<String> = modelfile
+            .parameters
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_debug_snapshot!((&modelfile.parameters, parameters), @r#"
+        (
+            Parameters(
+                [
+                    NumPredict(
+                        Infinite,
+                    ),
+                    NumPredict(
+                        FillContext,
+                    ),
+                    NumPredict(
+                        Tokens(
+                            128,
+                        ),
+                    ),
+                    RepeatLastN(
+                        ContextSize,
+                    ),
+                    RepeatLastN(
+                        Tokens(
+                            0,
+                        ),
+                    ),
+                    Seed(
+                        -1,
+                    ),
+                ],
+            ),
+            [
+                "num_predict -1",
+                "num_predict -2",
+                "num_predict 128",
+                "repeat_last_n -1",
+                "repeat_last_n 0",
+                "seed -1",
+            ],
+        )
+        "#);
+
+        let serialized = toml::to_string(&modelfile).expect("should serialize Modelfile");
+        let deserialized: Modelfile =
+            toml::from_str(&serialized).expect("should deserialize Modelfile");
+        assert_eq!(deserialized, modelfile);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let error = "FROM llama3.2\nPARAMETER num_predict -3\n"
+            .parse::<Modelfile>()
+            .expect_err("should not parse -3 for num_predict");
+
+        assert_eq!(
+            error.to_string().lines().next(),
+            Some("error: invalid value for num_predict, expected -1, -2 or a number of tokens in PARAMETER")
+        );
+        assert_eq!(
+            NumPredict::try_from(-3),
+            Err(OutOfRange {
+                parameter: ParameterName::NumPredict,
+                value: -3
+            })
+        );
+    }
+
+    #[test]
+    fn values_must_run_to_the_end_of_the_line() {
+        let errors: Vec<String> = [
+            "PARAMETER num_ctx 4096abc",
+            "PARAMETER use_mmap tru",
+            "PARAMETER use_mmap 10",
+            "PARAMETER temperature nan",
+            "PARAMETER temperature inf",
+        ]
+        .into_iter()
+        .map(|line| {
+            let error = format!("FROM llama3.2\n{line}\n")
+                .parse::<Modelfile>()
+                .expect_err("should not parse a bad value");
+            let ModelfileError::Parse(diagnostic) = error else {
+                panic!("should be a parse error");
+            };
+            format!("{}: {}", diagnostic.span.start, diagnostic.message())
+        })
+        .collect();
+
+        assert_debug_snapshot!(errors, @r#"
+        [
+            "2:23: invalid value for num_ctx, expected end of line in PARAMETER",
+            "2:21: invalid value for use_mmap, expected end of line in PARAMETER",
+            "2:21: invalid value for use_mmap, expected end of line in PARAMETER",
+            "2:23: invalid value for temperature, expected number in PARAMETER",
+            "2:23: invalid value for temperature, expected number in PARAMETER",
+        ]
+        "#);
+
+        let modelfile: Modelfile =
+            "FROM llama3.2\r\nPARAMETER num_ctx 4096  \r\nPARAMETER use_mmap true"
+                .parse()
+                .expect("should allow trailing spaces and line endings");
+        assert_eq!(modelfile.parameters.iter().count(), 2);
+    }
+}
@@ -9,40 +9,226 @@
 //! - [x] SYSTEM
 //! - [x] ADAPTER
 //! - [x] LICENSE
-//! - [ ] MESSAGE
+//! - [x] MESSAGE
 //! - [x] case insensitivity
 //!
 //! [Modelfile spec]: https://github.com/ollama/ollama/blob/main/docs/modelfile.md
-use std::path::PathBuf;
+use std::str::FromStr;
 
 use nom::{
     branch::alt,
     bytes::{
-        complete::tag_no_case,
-        streaming::{tag, take_until, take_while, take_while1},
+        complete::{tag, tag_no_case, take_while, take_while1},
+        streaming::take_until,
     },
-    character::complete::{self, multispace1},
-    combinator::{eof, value},
-    error::context,
+    character::complete::{self, char, digit1, multispace0, multispace1, space1},
+    combinator::{cut, eof, map_res, opt, peek, recognize, value, verify},
+    error::{context, VerboseError, VerboseErrorKind},
     multi::{many1, many_till},
-    sequence::{delimited, pair, preceded, terminated},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult, Parser as _,
 };
 
 use crate::message::{Message, MessageRole};
 
-use super::{Instruction, Parameter, ParameterName, TensorFile};
+use super::{
+    base_model::BaseModel,
+    error::{ModelfileError, ParseDiagnostic},
+    requires::Requires,
+    span::{LineIndex, Spanned},
+    Instruction, Parameter, TensorFile,
+};
 
 const TRIPLE_QUOTES: &str = r#"""""#;
 const SINGLE_QUOTE: &str = r#"""#;
 
-pub fn instructions(input: &str) -> IResult<&str, Vec<Instruction>> {
-    many_till(instruction, eof)
-        .map(|(instruction, _eof)| instruction)
-        .parse(input)
+/// The keywords that start an [`Instruction`].
+/// Each is also the [`context`] label of the parser for that instruction.
+pub const KEYWORDS: &[&str] = &[
+    "FROM",
+    "PARAMETER",
+    "TEMPLATE",
+    "SYSTEM",
+    "ADAPTER",
+    "LICENSE",
+    "MESSAGE",
+    "REQUIRES",
+];
+
+/// The start of the [`context`] label of a parameter value,
+/// like `invalid value for num_ctx`.
+pub const INVALID_VALUE: &str = "invalid value for ";
+
+/// The result of the parsers in this module.
+///
+/// [`VerboseError`] keeps the [`context`] labels attached by the parsers,
+/// which are used to build a [`ParseDiagnostic`].
+pub type ParseResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
+/// Run a parser for a list of items over the whole `source`,
+/// turning any error into a located [`ParseDiagnostic`].
+pub fn parse_all<'a, T>(
+    source: &'a str,
+    parser: impl FnOnce(&'a str) -> ParseResult<'a, Vec<T>>,
+) -> Result<Vec<T>, ModelfileError> {
+    let (rest, items) = parser(source).map_err(|error| diagnostic(source, error))?;
+
+    if rest.is_empty() {
+        Ok(items)
+    } else {
+        let offset = source.len() - rest.len();
+        Err(ParseDiagnostic::new(source, offset..offset, None, "end of input").into())
+    }
+}
+
+/// Build a [`ParseDiagnostic`] from a parser error.
+///
+/// The innermost [`context`] label that isn't an instruction keyword
+/// is used as the expected token,
+/// and the innermost instruction keyword as the instruction being parsed.
+pub fn diagnostic(source: &str, error: nom::Err<VerboseError<&str>>) -> ParseDiagnostic {
+    let offset_of = |input: &str| source.len() - input.len();
+
+    match error {
+        nom::Err::Incomplete(_) => {
+            ParseDiagnostic::new(source, source.len()..source.len(), None, "more input")
+        }
+        // every instruction parser commits with `cut` once its keyword matches,
+        // so a recoverable error means no instruction matched at all
+        nom::Err::Error(VerboseError { errors }) => {
+            let offset = errors
+                .last()
+                .map(|(input, _)| offset_of(input))
+                .unwrap_or(0);
+            ParseDiagnostic::new(
+                source,
+                offset..offset,
+                None,
+                format!("an instruction ({}) or a comment", KEYWORDS.join(", ")),
+            )
+        }
+        nom::Err::Failure(VerboseError { errors }) => {
+            let offset = errors
+                .first()
+                .map(|(input, _)| offset_of(input))
+                .unwrap_or(0);
+            let instruction = errors.iter().find_map(|(_, kind)| match kind {
+                VerboseErrorKind::Context(label) if KEYWORDS.contains(label) => Some(*label),
+                _ => None,
+            });
+            let reason = errors.iter().find_map(|(_, kind)| match kind {
+                VerboseErrorKind::Context(label) if label.starts_with(INVALID_VALUE) => {
+                    Some(label.to_string())
+                }
+                _ => None,
+            });
+            let expected = errors
+                .iter()
+                .find_map(|(_, kind)| match kind {
+                    VerboseErrorKind::Context(label)
+                        if !KEYWORDS.contains(label) && !label.starts_with(INVALID_VALUE) =>
+                    {
+                        Some(label.to_string())
+                    }
+                    _ => None,
+                })
+                .or_else(|| {
+                    errors.first().map(|(_, kind)| match kind {
+                        VerboseErrorKind::Char(c) => format!("'{c}'"),
+                        VerboseErrorKind::Context(label) => label.to_string(),
+                        VerboseErrorKind::Nom(kind) => kind.description().to_lowercase(),
+                    })
+                })
+                .unwrap_or_else(|| "valid input".to_string());
+
+            ParseDiagnostic {
+                reason,
+                ..ParseDiagnostic::new(source, offset..offset, instruction, expected)
+            }
+        }
+    }
+}
+
+/// Parse all instructions,
+/// recording the [`super::span::Span`] each one was parsed from.
+pub fn spanned_instructions<'a>(source: &'a str) -> ParseResult<'a, Vec<Spanned<Instruction>>> {
+    let index = LineIndex::new(source);
+
+    let result = many_till(
+        |input: &'a str| {
+            let start = source.len() - input.len();
+            let (rest, instruction) = instruction(input)?;
+            let end = source.len() - rest.len();
+            Ok((rest, Spanned::new(instruction, index.span(start..end))))
+        },
+        eof,
+    )
+    .map(|(instructions, _eof)| instructions)
+    .parse(source);
+
+    result
+}
+
+/// Parse all instructions in `source`,
+/// collecting every error instead of stopping at the first one.
+///
+/// After an instruction fails to parse,
+/// parsing resumes at the next line that starts with one of the [`KEYWORDS`].
+pub fn recovering_instructions(source: &str) -> (Vec<Spanned<Instruction>>, Vec<ParseDiagnostic>) {
+    let index = LineIndex::new(source);
+    let mut instructions = Vec::new();
+    let mut errors = Vec::new();
+    let mut input = source;
+
+    while !input.is_empty() {
+        let start = source.len() - input.len();
+        match instruction(input) {
+            Ok((rest, instruction)) => {
+                let end = source.len() - rest.len();
+                instructions.push(Spanned::new(instruction, index.span(start..end)));
+                input = rest;
+            }
+            Err(error) => {
+                errors.push(diagnostic(source, error));
+                input = next_keyword_line(input);
+            }
+        }
+    }
+
+    (instructions, errors)
+}
+
+/// Skip past the current line to the next line that starts with an instruction keyword,
+/// or to the end of the input if there is none.
+fn next_keyword_line(input: &str) -> &str {
+    let mut rest = input;
+
+    while let Some(newline) = rest.find('\n') {
+        rest = &rest[newline + 1..];
+
+        let starts_with_keyword = KEYWORDS.iter().any(|keyword| {
+            rest.get(..keyword.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+                && rest[keyword.len()..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+        });
+
+        if starts_with_keyword {
+            return rest;
+        }
+    }
+
+    &input[input.len()..]
 }
 
-pub fn instruction(input: &str) -> IResult<&str, Instruction> {
+/// Parse a single instruction.
+///
+/// Each instruction parser commits with [`cut`] once its keyword has matched,
+/// so errors point into the instruction that failed
+/// instead of the last alternative tried.
+pub fn instruction(input: &str) -> ParseResult<'_, Instruction> {
     context(
         "instruction",
         alt((
@@ -54,30 +240,74 @@ pub fn instruction(input: &str) -> IResult<&str, Instruction> {
             adapter,
             license,
             message,
+            requires,
         )),
     )
     .parse(input)
+    .map_err(|error| match error {
+        // the only streaming parser is `take_until` in quoted strings
+        nom::Err::Incomplete(_) => {
+            let mut errors = vec![(input, VerboseErrorKind::Context("closing quotes"))];
+            if let Some(keyword) = KEYWORDS.iter().find(|keyword| {
+                input
+                    .get(..keyword.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+            }) {
+                errors.push((input, VerboseErrorKind::Context(keyword)));
+            }
+            nom::Err::Failure(VerboseError { errors })
+        }
+        error => error,
+    })
 }
 
 /// Takes an input string and returns a `ModelName`.
 /// Parses a line that starts with `FROM`
 /// that specifies the [`ModelId`]
-pub fn from(input: &str) -> IResult<&str, Instruction> {
+pub fn from(input: &str) -> ParseResult<'_, Instruction> {
     let from_tag = tag_no_case("FROM");
     let space = take_while1(|c| c == ' ');
 
-    context("FROM", preceded(pair(from_tag, space), model_id))
-        .map(|model| Instruction::From(model.into()))
-        .parse(input)
+    context(
+        "FROM",
+        preceded(
+            from_tag,
+            cut(preceded(
+                context("whitespace", space),
+                context(
+                    "model name, path or digest",
+                    map_res(model_id, str::parse::<BaseModel>),
+                ),
+            )),
+        ),
+    )
+    .map(Instruction::From)
+    .parse(input)
+}
+
+/// The Ollama version the model needs, like `REQUIRES 0.5.0`.
+pub fn requires(input: &str) -> ParseResult<'_, Instruction> {
+    context(
+        "REQUIRES",
+        preceded(
+            tag_no_case("REQUIRES"),
+            cut(preceded(
+                context("whitespace", take_while1(|c| c == ' ' || c == '\t')),
+                context("version", map_res(model_id, str::parse::<Requires>)),
+            )),
+        ),
+    )
+    .map(Instruction::Requires)
+    .parse(input)
 }
 
-pub fn model_id(input: &str) -> IResult<&str, &str> {
-    complete::not_line_ending.parse(input)
+pub fn model_id(input: &str) -> ParseResult<'_, &str> {
+    complete::not_line_ending.map(str::trim_end).parse(input)
 }
 
 /// Parse a comment line.
 /// Comments start with a `#` and take a single line.
-pub fn comment(input: &str) -> IResult<&str, ()> {
+pub fn comment(input: &str) -> ParseResult<'_, ()> {
     let comment_delimiter = tag("#");
 
     context(
@@ -88,7 +318,7 @@ pub fn comment(input: &str) -> IResult<&str, ()> {
 }
 
 /// Consume empty lines and comments
-pub fn skip_lines(input: &str) -> IResult<&str, Instruction> {
+pub fn skip_lines(input: &str) -> ParseResult<'_, Instruction> {
     let skip = alt((value((), many1(comment)), value((), multispace1)));
     // let skip = many1(comment);
 
@@ -108,20 +338,19 @@ pub fn skip_lines(input: &str) -> IResult<&str, Instruction> {
 /// Good thing the authors are geniuses,
 /// otherwise this would be totally unhinged.
 ///
-/// We're not going to worry about parsing the template
-/// in this project.
-/// Leave that to the geniuses at Ollama.
+/// The body is kept as text here,
+/// [`super::instruction::Template::parse`] parses it as a Go template.
 ///
 /// [the spec]: https://github.com/ollama/ollama/blob/main/docs/modelfile.md#template
-fn template(input: &str) -> IResult<&str, Instruction> {
+fn template(input: &str) -> ParseResult<'_, Instruction> {
     let template = tag_no_case("TEMPLATE");
     let space = take_while(|c| c == ' ');
     context(
         "TEMPLATE",
         preceded(
-            pair(template, space),
+            template,
             // alt((triple_quote_string, single_quoted_multiline_string)),
-            multiline,
+            cut(preceded(space, multiline)),
         ),
     )
     .map(|template| Instruction::Template(template.into()))
@@ -131,7 +360,7 @@ fn template(input: &str) -> IResult<&str, Instruction> {
 /// A string surrounded by trippled quotes.
 /// """Like this!
 /// And they can be on multiple lines."""
-pub fn triple_quote_string(input: &str) -> IResult<&str, &str> {
+pub fn triple_quote_string(input: &str) -> ParseResult<'_, &str> {
     delimited(
         tag(TRIPLE_QUOTES),
         take_until(TRIPLE_QUOTES),
@@ -143,7 +372,7 @@ pub fn triple_quote_string(input: &str) -> IResult<&str, &str> {
 /// A multiline string with single quotes.
 /// Why is this allowed?
 /// The inmates are running the asylum.
-pub fn single_quoted_multiline_string(input: &str) -> IResult<&str, &str> {
+pub fn single_quoted_multiline_string(input: &str) -> ParseResult<'_, &str> {
     delimited(
         tag(SINGLE_QUOTE),
         take_until(SINGLE_QUOTE),
@@ -152,117 +381,101 @@ pub fn single_quoted_multiline_string(input: &str) -> IResult<&str, &str> {
     .parse(input)
 }
 
-pub fn parameter_name(input: &str) -> IResult<&str, ParameterName> {
-    alt((
-        terminated(
-            tag::<&str, &str, _>(ParameterName::Mirostat.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::MirostatEta.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::MirostatTau.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::NumCtx.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::RepeatLastN.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::RepeatPenalty.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::Seed.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::Temperature.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::Stop.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::TfsZ.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::NumPredict.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::TopK.into()),
-            multispace1,
-        ),
-        terminated(
-            tag::<&str, &str, _>(ParameterName::TopP.into()),
-            multispace1,
-        ),
+/// The name of a [`Parameter`], followed by whitespace.
+///
+/// Any name is accepted here, see [`Parameter::parse_value`].
+pub fn parameter_name(input: &str) -> ParseResult<'_, &str> {
+    context(
+        "parameter name",
         terminated(
-            tag::<&str, &str, _>(ParameterName::MinP.into()),
+            take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'),
             multispace1,
         ),
-    ))(input)
-    .map(|(rest, name)| {
-        (
-            rest,
-            name.parse::<ParameterName>()
-                .expect("should be able to parse parameter name from parse parameter name"),
-        )
-    })
+    )
+    .parse(input)
 }
 
-pub fn float_parameter_value(input: &str) -> IResult<&str, f32> {
-    nom::number::complete::float(input)
+/// A finite number, `nan` and `inf` are not valid parameter values.
+pub fn float_parameter_value(input: &str) -> ParseResult<'_, f32> {
+    context(
+        "number",
+        verify(nom::number::complete::float, |value: &f32| {
+            value.is_finite()
+        }),
+    )
+    .parse(input)
 }
 
-pub fn int_parameter_value(input: &str) -> IResult<&str, usize> {
-    nom::character::complete::digit1(input).map(|(s, int)| {
-        (
-            s,
-            int.parse().expect("should be able to parse int from input"),
-        )
-    })
+pub fn int_parameter_value(input: &str) -> ParseResult<'_, usize> {
+    context("integer", map_res(digit1, str::parse)).parse(input)
 }
 
-pub fn string_parameter_value(input: &str) -> IResult<&str, String> {
-    complete::not_line_ending.map(Into::into).parse(input)
+pub fn signed_int_parameter_value<T: FromStr>(input: &str) -> ParseResult<'_, T> {
+    context(
+        "integer",
+        map_res(recognize(pair(opt(char('-')), digit1)), str::parse),
+    )
+    .parse(input)
+}
+
+/// A boolean value, spelled any way Go's `strconv.ParseBool` accepts.
+pub fn bool_parameter_value(input: &str) -> ParseResult<'_, bool> {
+    context(
+        "boolean",
+        alt((
+            value(true, alt((tag("true"), tag("TRUE"), tag("True")))),
+            value(false, alt((tag("false"), tag("FALSE"), tag("False")))),
+            value(true, alt((tag("1"), tag("t"), tag("T")))),
+            value(false, alt((tag("0"), tag("f"), tag("F")))),
+        )),
+    )
+    .parse(input)
+}
+
+/// The end of a parameter value:
+/// trailing spaces and then the end of the line.
+pub fn end_of_value(input: &str) -> ParseResult<'_, ()> {
+    context(
+        "end of line",
+        value(
+            (),
+            pair(complete::space0, peek(alt((complete::line_ending, eof)))),
+        ),
+    )
+    .parse(input)
+}
+
+/// A string value running to the end of the line.
+/// Double quotes around the whole value are removed.
+pub fn string_parameter_value(input: &str) -> ParseResult<'_, String> {
+    let quoted = terminated(
+        delimited(
+            char('"'),
+            take_while(|c: char| !matches!(c, '"' | '\r' | '\n')),
+            char('"'),
+        ),
+        pair(complete::space0, peek(alt((complete::line_ending, eof)))),
+    );
+
+    alt((quoted, complete::not_line_ending))
+        .map(Into::into)
+        .parse(input)
 }
 
-pub fn parameter(input: &str) -> IResult<&str, Parameter> {
+pub fn parameter(input: &str) -> ParseResult<'_, Parameter> {
     let (input, name) = parameter_name(input)?;
-    match name {
-        ParameterName::Mirostat => int_parameter_value.map(Parameter::Mirostat).parse(input),
-        ParameterName::MirostatEta => float_parameter_value
-            .map(Parameter::MirostatEta)
-            .parse(input),
-        ParameterName::MirostatTau => float_parameter_value
-            .map(Parameter::MirostatTau)
-            .parse(input),
-        ParameterName::NumCtx => int_parameter_value.map(Parameter::NumCtx).parse(input),
-        ParameterName::RepeatLastN => int_parameter_value.map(Parameter::RepeatLastN).parse(input),
-        ParameterName::RepeatPenalty => float_parameter_value
-            .map(Parameter::RepeatPenalty)
-            .parse(input),
-        ParameterName::Temperature => float_parameter_value
-            .map(Parameter::Temperature)
-            .parse(input),
-        ParameterName::Seed => int_parameter_value.map(Parameter::Seed).parse(input),
-        ParameterName::Stop => string_parameter_value.map(Parameter::Stop).parse(input),
-        ParameterName::TfsZ => float_parameter_value.map(Parameter::TfsZ).parse(input),
-        ParameterName::NumPredict => int_parameter_value.map(Parameter::NumPredict).parse(input),
-        ParameterName::TopK => int_parameter_value.map(Parameter::TopK).parse(input),
-        ParameterName::TopP => float_parameter_value.map(Parameter::TopP).parse(input),
-        ParameterName::MinP => float_parameter_value.map(Parameter::MinP).parse(input),
-    }
+    Parameter::parse_value(name, input)
+}
+
+/// Parameters one per line, without the `PARAMETER` keyword,
+/// like the `parameters` block of an `/api/show` response.
+pub fn parameter_lines(input: &str) -> ParseResult<'_, Vec<Parameter>> {
+    many_till(
+        preceded(multispace0, cut(parameter)),
+        preceded(multispace0, eof),
+    )
+    .map(|(parameters, _eof)| parameters)
+    .parse(input)
 }
 
 /// Parameters are key value pairs
@@ -270,17 +483,21 @@ pub fn parameter(input: &str) -> IResult<&str, Parameter> {
 /// to an arbitrary string value
 ///
 /// https://github.com/ollama/ollama/blob/main/docs/modelfile.md#parameter
-pub fn parameter_line(input: &str) -> IResult<&str, Instruction> {
+pub fn parameter_line(input: &str) -> ParseResult<'_, Instruction> {
     let parameter_tag = tag_no_case("PARAMETER");
 
     context(
         "PARAMETER",
-        preceded(pair(parameter_tag, multispace1), parameter).map(Into::into),
+        preceded(
+            parameter_tag,
+            cut(preceded(context("whitespace", multispace1), parameter)),
+        )
+        .map(Into::into),
     )
     .parse(input)
 }
 
-pub fn multiline(input: &str) -> IResult<&str, &str> {
+pub fn multiline(input: &str) -> ParseResult<'_, &str> {
     context(
         "multiline",
         alt((
@@ -295,12 +512,15 @@ pub fn multiline(input: &str) -> IResult<&str, &str> {
 /// The system message to be used in the template, if applicable
 ///
 /// https://github.com/ollama/ollama/blob/main/docs/modelfile.md#system
-pub fn system(input: &str) -> IResult<&str, Instruction> {
+pub fn system(input: &str) -> ParseResult<'_, Instruction> {
     let template = tag_no_case("system");
     context(
         "SYSTEM",
-        preceded(pair(template, multispace1), multiline)
-            .map(|system| Instruction::System(system.into())),
+        preceded(
+            template,
+            cut(preceded(context("whitespace", multispace1), multiline)),
+        )
+        .map(|system| Instruction::System(system.into())),
     )
     .parse(input)
 }
@@ -308,64 +528,99 @@ pub fn system(input: &str) -> IResult<&str, Instruction> {
 /// Takes an input string and returns a `ModelName`.
 /// Parses a line that starts with `FROM`
 /// that specifies the [`ModelId`]
-pub fn adapter(input: &str) -> IResult<&str, Instruction> {
+pub fn adapter(input: &str) -> ParseResult<'_, Instruction> {
     let adapter_tag = tag_no_case("adapter");
 
     context(
         "ADAPTER",
-        preceded(pair(adapter_tag, multispace1), tensor_file),
+        preceded(
+            adapter_tag,
+            cut(preceded(context("whitespace", multispace1), tensor_file)),
+        ),
     )
     .map(Into::into)
     .parse(input)
 }
 
-pub fn tensor_file(input: &str) -> IResult<&str, TensorFile> {
-    context(
-        "TensorFile",
-        filename.map(|filename| {
-            if filename.ends_with(".gguf") {
-                TensorFile::Gguf(PathBuf::from(filename))
-            } else if filename.ends_with(".safetensors") {
-                TensorFile::Safetensor(PathBuf::from(filename))
-            } else {
-                panic!("bad file extensions");
-            }
-        }),
+/// A path to a tensor file or directory, or `@` and a blob digest.
+pub fn tensor_file(input: &str) -> ParseResult<'_, TensorFile> {
+    let blob = preceded(
+        char('@'),
+        cut(context(
+            "sha256 digest",
+            map_res(take_while1(|c: char| !c.is_whitespace()), str::parse),
+        )),
     )
-    .parse(input)
-}
+    .map(TensorFile::Blob);
+
+    let file = context(
+        "path to a .gguf or .safetensors file or a directory",
+        file_path.map(TensorFile::from_path),
+    );
 
-pub fn filename(input: &str) -> IResult<&str, &str> {
-    nom::bytes::complete::take_while1(is_file_char).parse(input)
+    alt((blob, file)).parse(input)
 }
 
-pub fn is_file_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '/' || c == '.' || c == '-' || c == '_'
+/// A file path in double quotes,
+/// or everything up to the end of the line.
+pub fn file_path(input: &str) -> ParseResult<'_, &str> {
+    let quoted = delimited(
+        char('"'),
+        take_while1(|c: char| c != '"' && c != '\r' && c != '\n'),
+        char('"'),
+    );
+    let bare = verify(
+        complete::not_line_ending.map(str::trim_end),
+        |path: &str| !path.is_empty(),
+    );
+
+    alt((quoted, bare)).parse(input)
 }
 
-pub fn license(input: &str) -> IResult<&str, Instruction> {
+pub fn license(input: &str) -> ParseResult<'_, Instruction> {
     let license_tag = tag_no_case("license");
     context(
         "LICENSE",
-        preceded(pair(license_tag, multispace1), multiline)
-            .map(|license| Instruction::License(license.into())),
+        preceded(
+            license_tag,
+            cut(preceded(context("whitespace", multispace1), multiline)),
+        )
+        .map(|license| Instruction::License(license.into())),
     )
     .parse(input)
 }
 
-pub fn message(input: &str) -> IResult<&str, Instruction> {
+/// A message in the conversation history, like `MESSAGE user Hello!`.
+///
+/// Like Ollama, the role is case-insensitive
+/// and separated from the content by spaces or tabs.
+/// The content can be `"""triple quoted"""` to span several lines.
+///
+/// https://github.com/ollama/ollama/blob/main/docs/modelfile.md#message
+pub fn message(input: &str) -> ParseResult<'_, Instruction> {
     let message_tag = tag_no_case("message");
-    let user_tag = tag("user");
-    let assistant_tag = tag("assistant");
-    let system_tag = tag("system");
-    let role = alt((user_tag, assistant_tag, system_tag));
+    // the whole word, so `users` isn't read as `user`
+    let role = take_while1(|c: char| c.is_ascii_alphabetic());
 
     context(
         "MESSAGE",
-        preceded(pair(message_tag, multispace1), pair(role, multiline)).map(|(role, message)| {
-            let role: MessageRole = role.parse().expect("should be able to parse role from tag");
-            Message::from((role, message))
-        }),
+        preceded(
+            message_tag,
+            cut(preceded(
+                context("whitespace", multispace1),
+                separated_pair(
+                    context(
+                        "message role",
+                        map_res(role, |role: &str| {
+                            role.to_ascii_lowercase().parse::<MessageRole>()
+                        }),
+                    ),
+                    context("whitespace", space1),
+                    multiline,
+                ),
+            )),
+        )
+        .map(|(role, content)| Message::from((role, content))),
     )
     .map(Into::into)
     .parse(input)
@@ -380,6 +635,7 @@ mod tests {
         load_modelfiles, TestData, TEST_COMMENTS, TEST_FROM, TEST_GOOD_DATA_DIR, TEST_MODEL_IDS,
         TEST_SINGLE_QUOTE_MULTILINE, TEST_TRIPLE_QUOTES,
     };
+    use crate::modelfile::ParameterName;
 
     use super::*;
 
@@ -393,7 +649,8 @@ mod tests {
         } in modelfiles
         {
             dbg!(&path);
-            instructions(&case).expect("should be able to parse instructions from Modelfile");
+            spanned_instructions(&case)
+                .expect("should be able to parse instructions from Modelfile");
         }
     }
 
@@ -402,8 +659,8 @@ mod tests {
         for case in TEST_FROM {
             dbg!(&case);
             instruction(case).expect("should be able to parse single instruction");
-            instructions(case)
-                .expect("should be able to parse single instruction with `instructions`");
+            spanned_instructions(case)
+                .expect("should be able to parse single instruction with `spanned_instructions`");
         }
     }
 
@@ -502,6 +759,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn every_parameter_has_test_data() {
+        let test_data = include_str!("./testdata/parameters.txt");
+        let tested: Vec<ParameterName> = test_data
+            .lines()
+            .map(|line| {
+                let (_, Instruction::Parameter(parameter)) =
+                    parameter_line(line).expect("should be able to parse parameter line")
+                else {
+                    panic!("should parse a parameter");
+                };
+                ParameterName::from(&parameter)
+            })
+            .collect();
+
+        for name in ParameterName::iter() {
+            assert!(tested.contains(&name), "no test data for {name:?}");
+        }
+    }
+
     #[test]
     fn system_messages_are_parsed() {
         let test_data = include_str!("./testdata/systems.txt");
@@ -537,4 +814,71 @@ mod tests {
             message(case).expect("should be able to parse a single message");
         }
     }
+
+    #[test]
+    fn messages_parse_like_ollama() {
+        let test_data = include_str!("./testdata/messages_conformance.txt");
+        let mut results = String::new();
+        for case in test_data.split(":endcase\n").filter(|s| !s.is_empty()) {
+            let result = match instruction(case) {
+                Ok((_, Instruction::Message(parsed))) => {
+                    let rendered = format!("MESSAGE {parsed}\n");
+                    let (_, reparsed) = message(&rendered).expect("should parse rendered message");
+                    assert_eq!(reparsed, Instruction::Message(parsed.clone()));
+                    format!("{parsed:?}")
+                }
+                Ok((_, instruction)) => panic!("should parse a message, not {instruction:?}"),
+                Err(error) => diagnostic(case, error)
+                    .to_string()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            results.push_str(&format!("{case:?}\n  {result}\n"));
+        }
+
+        assert_snapshot!(results, @r#"
+        "MESSAGE User Is Toronto in Canada?\n"
+          User("Is Toronto in Canada?")
+        "MESSAGE ASSISTANT yes\n"
+          Assistant("yes")
+        "message system Answer with yes or no.\n"
+          System("Answer with yes or no.")
+        "MESSAGE user\tSeparated by a tab\n"
+          User("Separated by a tab")
+        "MESSAGE user    Separated by several spaces\n"
+          User("Separated by several spaces")
+        "MESSAGE user \"\"\"Is Ottawa\nin Canada?\"\"\"\n"
+          User("Is Ottawa\nin Canada?")
+        "MESSAGE assistant \"\"\"  keeps its padding  \"\"\"\n"
+          Assistant("  keeps its padding  ")
+        "MESSAGE assistant \"Double quoted\"\n"
+          Assistant("Double quoted")
+        "MESSAGE user A \"quoted\" word\n"
+          User("A \"quoted\" word")
+        "MESSAGE Tool 22 degrees and sunny\n"
+          Tool("22 degrees and sunny")
+        "MESSAGE assistant {\"tool_calls\": [{\"function\": {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}}]}\n"
+          Assistant("{\"tool_calls\": [{\"function\": {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}}]}")
+        "MESSAGE assistant \"\"\"{\n  \"content\": \"Checking.\",\n  \"tool_calls\": [{\"function\": {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}}]\n}\"\"\"\n"
+          Assistant("{\n  \"content\": \"Checking.\",\n  \"tool_calls\": [{\"function\": {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}}]\n}")
+        "MESSAGE user {\"tool_calls\": []}\n"
+          User("{\"tool_calls\": []}")
+        "MESSAGE assistant {\"tool_calls\": []}\n"
+          Assistant("{\"tool_calls\": []}")
+        "MESSAGE assistant {\"tool_calls\": [{\"function\": {\"name\": \"get_weather\"}}], \"note\": \"not a tool call\"}\n"
+          Assistant("{\"tool_calls\": [{\"function\": {\"name\": \"get_weather\"}}], \"note\": \"not a tool call\"}")
+        "MESSAGE user\"no separator\"\n"
+          error: expected whitespace in MESSAGE
+        "MESSAGE users hello\n"
+          error: expected message role in MESSAGE
+        "MESSAGE robot hello\n"
+          error: expected message role in MESSAGE
+        "MESSAGE user\nhello\n"
+          error: expected whitespace in MESSAGE
+        "MESSAGE user \"\"\"unterminated\nmessage\n"
+          error: expected closing quotes in MESSAGE
+        "#);
+    }
 }
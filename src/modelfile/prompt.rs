@@ -0,0 +1,1300 @@
+//! Rendering a `TEMPLATE` into the exact prompt a model will see.
+//!
+//! [`TemplateAst::execute`] runs a parsed template like Go's `text/template`,
+//! and [`TemplateAst::render`] fills in the values the way [Ollama] does,
+//! so [`Modelfile::preview`] shows the prompt Ollama would send for a chat
+//! without running Ollama.
+//!
+//! [Ollama]: https://github.com/ollama/ollama/blob/main/template/template.go
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::{json, Number, Value};
+use thiserror::Error;
+
+use crate::message::ChatMessage;
+
+use super::{
+    span::Span,
+    template::{Branch, Command, Node, Operand, Pipeline, TemplateAst, TemplateError, MAX_DEPTH},
+    Modelfile,
+};
+
+/// The template Ollama uses for models without a `TEMPLATE`.
+const DEFAULT_TEMPLATE: &str = "{{ .Prompt }}";
+
+/// What a chat gives the template, like Ollama's `template.Values`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateValues {
+    pub messages: Vec<ChatMessage>,
+    /// Tools in the shape of the Ollama API,
+    /// like `{"type": "function", "function": {"name": …}}`.
+    pub tools: Vec<Value>,
+    /// Only used for fill-in-the-middle, together with [`Self::suffix`].
+    pub prompt: String,
+    pub suffix: String,
+}
+
+/// Errors executing a template.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RenderError {
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+    #[error("error calling {function}: {message}")]
+    Call { function: String, message: String },
+    #[error("{0}")]
+    Execute(String),
+}
+
+impl Modelfile {
+    /// The prompt Ollama would send to the model for a chat of `messages`.
+    ///
+    /// Like Ollama, the `MESSAGE`s of the Modelfile come first,
+    /// and the `SYSTEM` message is added unless the chat starts with its own.
+    pub fn preview(&self, messages: &[ChatMessage]) -> Result<String, RenderError> {
+        let template = match &self.template {
+            Some(template) => template.parse()?,
+            None => DEFAULT_TEMPLATE.parse()?,
+        };
+
+        let mut chat: Vec<ChatMessage> = self.messages.iter().map(ChatMessage::from).collect();
+        chat.extend_from_slice(messages);
+        if let Some(system) = &self.system {
+            if messages
+                .first()
+                .is_none_or(|message| message.role != "system")
+            {
+                let system = ChatMessage {
+                    role: "system".to_string(),
+                    content: AsRef::<str>::as_ref(&**system).to_string(),
+                    tool_calls: Vec::new(),
+                };
+                chat.insert(0, system);
+            }
+        }
+
+        template.render(&TemplateValues {
+            messages: chat,
+            ..TemplateValues::default()
+        })
+    }
+}
+
+impl TemplateAst {
+    /// Render the template like Ollama does.
+    ///
+    /// Templates that use `.Messages` see the whole chat at once.
+    /// Older templates only know `.System`, `.Prompt` and `.Response`,
+    /// so they are repeated for every turn,
+    /// and the last turn stops at `.Response`.
+    pub fn render(&self, values: &TemplateValues) -> Result<String, RenderError> {
+        let (system, messages) = collate(&values.messages);
+        let fields = self.fields();
+
+        if !values.prompt.is_empty() && !values.suffix.is_empty() {
+            return self.execute(&json!({
+                "Prompt": values.prompt,
+                "Suffix": values.suffix,
+                "Response": "",
+            }));
+        }
+
+        if fields.contains("messages") {
+            return self.execute(&json!({
+                "System": system,
+                "Messages": messages,
+                "Tools": values.tools,
+                "Response": "",
+                "Think": false,
+                "ThinkLevel": "",
+                "IsThinkSet": false,
+            }));
+        }
+
+        // Ollama adds a `{{ .Response }}` to templates without one
+        let mut nodes = self.nodes.clone();
+        if !fields.contains("response") {
+            nodes.push(Node::Action {
+                pipeline: Pipeline {
+                    variables: Vec::new(),
+                    assign: false,
+                    commands: vec![Command {
+                        args: vec![Operand::Field(vec!["Response".to_string()])],
+                    }],
+                },
+                span: Span::new("", 0..0),
+            });
+        }
+
+        let mut output = String::new();
+        let mut turn = Turn::default();
+        for message in &messages {
+            match message.role.as_str() {
+                "system" => {
+                    if !turn.prompt.is_empty() || !turn.response.is_empty() {
+                        output.push_str(&execute(&nodes, &turn.take())?);
+                    }
+                    turn.system = message.content.clone();
+                }
+                "user" => {
+                    if !turn.response.is_empty() {
+                        output.push_str(&execute(&nodes, &turn.take())?);
+                    }
+                    turn.prompt = message.content.clone();
+                }
+                "assistant" => turn.response = message.content.clone(),
+                _ => {}
+            }
+        }
+
+        let mut cut = false;
+        let nodes = cut_after_response(&nodes, &mut cut);
+        output.push_str(&execute(&nodes, &turn.take())?);
+
+        Ok(output)
+    }
+
+    /// Execute the template with `data` as `.`, like Go's `text/template`.
+    pub fn execute(&self, data: &Value) -> Result<String, RenderError> {
+        execute(&self.nodes, data)
+    }
+
+    /// The lowercase names of every field the template uses.
+    fn fields(&self) -> BTreeSet<String> {
+        let mut fields = BTreeSet::new();
+        node_fields(&self.nodes, &mut fields);
+        fields
+    }
+}
+
+fn node_fields(nodes: &[Node], fields: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            Node::Action { pipeline, .. }
+            | Node::Template {
+                pipeline: Some(pipeline),
+                ..
+            } => pipeline_fields(pipeline, fields),
+            Node::If(branch) | Node::Range(branch) | Node::With(branch) => {
+                pipeline_fields(&branch.pipeline, fields);
+                node_fields(&branch.body, fields);
+                node_fields(branch.otherwise.as_deref().unwrap_or_default(), fields);
+            }
+            Node::Define { body, .. } => node_fields(body, fields),
+            Node::Block { pipeline, body, .. } => {
+                pipeline_fields(pipeline, fields);
+                node_fields(body, fields);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn pipeline_fields(pipeline: &Pipeline, fields: &mut BTreeSet<String>) {
+    for operand in pipeline.commands.iter().flat_map(|command| &command.args) {
+        match operand {
+            Operand::Field(names) | Operand::Variable { fields: names, .. } => {
+                fields.extend(names.iter().map(|name| name.to_lowercase()));
+            }
+            Operand::Pipeline {
+                pipeline,
+                fields: names,
+            } => {
+                fields.extend(names.iter().map(|name| name.to_lowercase()));
+                pipeline_fields(pipeline, fields);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// One turn of an older template.
+#[derive(Default)]
+struct Turn {
+    system: String,
+    prompt: String,
+    response: String,
+}
+
+impl Turn {
+    fn take(&mut self) -> Value {
+        let turn = std::mem::take(self);
+        json!({
+            "System": turn.system,
+            "Prompt": turn.prompt,
+            "Response": turn.response,
+        })
+    }
+}
+
+/// Merge consecutive messages of the same role, except tool results,
+/// and collect the system messages.
+fn collate(messages: &[ChatMessage]) -> (String, Vec<ChatMessage>) {
+    let mut system = Vec::new();
+    let mut collated: Vec<ChatMessage> = Vec::new();
+
+    for message in messages {
+        if message.role == "system" {
+            system.push(message.content.as_str());
+        }
+        match collated.last_mut() {
+            Some(last) if last.role == message.role && message.role != "tool" => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&message.content);
+            }
+            _ => collated.push(message.clone()),
+        }
+    }
+
+    (system.join("\n\n"), collated)
+}
+
+/// Drop everything after the first `.Response`,
+/// like Ollama does for the last turn of older templates.
+///
+/// Conditions of `if`, `range` and `with` are kept as they are.
+fn cut_after_response(nodes: &[Node], cut: &mut bool) -> Vec<Node> {
+    let mut kept = Vec::new();
+
+    for node in nodes {
+        if *cut {
+            break;
+        }
+        let node = match node {
+            Node::Action { pipeline, span } => match cut_pipeline(pipeline, cut) {
+                Some(pipeline) => Node::Action {
+                    pipeline,
+                    span: span.clone(),
+                },
+                None => continue,
+            },
+            Node::If(branch) => Node::If(cut_branch(branch, cut)),
+            Node::Range(branch) => Node::Range(cut_branch(branch, cut)),
+            Node::With(branch) => Node::With(cut_branch(branch, cut)),
+            node => node.clone(),
+        };
+        kept.push(node);
+    }
+
+    kept
+}
+
+fn cut_branch(branch: &Branch, cut: &mut bool) -> Branch {
+    Branch {
+        pipeline: branch.pipeline.clone(),
+        body: cut_after_response(&branch.body, cut),
+        otherwise: branch
+            .otherwise
+            .as_ref()
+            .map(|otherwise| cut_after_response(otherwise, cut)),
+        span: branch.span.clone(),
+    }
+}
+
+fn cut_pipeline(pipeline: &Pipeline, cut: &mut bool) -> Option<Pipeline> {
+    let mut commands = Vec::new();
+
+    for command in &pipeline.commands {
+        let mut args = Vec::new();
+        for arg in &command.args {
+            if *cut {
+                break;
+            }
+            match arg {
+                Operand::Field(names) if names.iter().any(|name| name == "Response") => {
+                    *cut = true;
+                    args.push(arg.clone());
+                }
+                Operand::Pipeline {
+                    pipeline: inner,
+                    fields,
+                } => {
+                    if let Some(inner) = cut_pipeline(inner, cut) {
+                        args.push(Operand::Pipeline {
+                            pipeline: Box::new(inner),
+                            fields: fields.clone(),
+                        });
+                    }
+                }
+                arg => args.push(arg.clone()),
+            }
+        }
+        if args.is_empty() {
+            return None;
+        }
+        commands.push(Command { args });
+    }
+
+    Some(Pipeline {
+        commands,
+        ..pipeline.clone()
+    })
+}
+
+fn execute(nodes: &[Node], data: &Value) -> Result<String, RenderError> {
+    let mut templates = HashMap::new();
+    collect_templates(nodes, &mut templates);
+
+    let mut executor = Executor {
+        templates,
+        variables: vec![("$".to_string(), data.clone())],
+        output: String::new(),
+        depth: 0,
+    };
+    executor.list(nodes, data)?;
+
+    Ok(executor.output)
+}
+
+/// The `define`d and `block` templates, later definitions win.
+fn collect_templates<'a>(nodes: &'a [Node], templates: &mut HashMap<&'a str, &'a [Node]>) {
+    for node in nodes {
+        match node {
+            Node::Define { name, body, .. } | Node::Block { name, body, .. } => {
+                templates.insert(name.as_str(), body.as_slice());
+                collect_templates(body, templates);
+            }
+            Node::If(branch) | Node::Range(branch) | Node::With(branch) => {
+                collect_templates(&branch.body, templates);
+                collect_templates(branch.otherwise.as_deref().unwrap_or_default(), templates);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How a list of nodes finished.
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+struct Executor<'a> {
+    templates: HashMap<&'a str, &'a [Node]>,
+    /// Variables in scope, innermost last.
+    variables: Vec<(String, Value)>,
+    output: String,
+    depth: usize,
+}
+
+impl Executor<'_> {
+    fn list(&mut self, nodes: &[Node], dot: &Value) -> Result<Flow, RenderError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(RenderError::Execute(
+                "exceeded maximum template depth".to_string(),
+            ));
+        }
+
+        self.depth += 1;
+        let flow = self.nodes(nodes, dot);
+        self.depth -= 1;
+        flow
+    }
+
+    fn nodes(&mut self, nodes: &[Node], dot: &Value) -> Result<Flow, RenderError> {
+        for node in nodes {
+            let flow = match node {
+                Node::Text(text) => {
+                    self.output.push_str(text);
+                    Flow::Normal
+                }
+                Node::Comment(_) | Node::Define { .. } => Flow::Normal,
+                Node::Action { pipeline, .. } => {
+                    let value = self.pipeline(pipeline, dot)?;
+                    if pipeline.variables.is_empty() {
+                        self.output.push_str(&format_value(&value));
+                    }
+                    Flow::Normal
+                }
+                Node::If(branch) => self.branch(branch, dot, false)?,
+                Node::With(branch) => self.branch(branch, dot, true)?,
+                Node::Range(branch) => self.range(branch, dot)?,
+                Node::Block { name, pipeline, .. } => {
+                    let value = self.pipeline(pipeline, dot)?;
+                    self.template(name, value)?;
+                    Flow::Normal
+                }
+                Node::Template { name, pipeline, .. } => {
+                    let value = match pipeline {
+                        Some(pipeline) => self.pipeline(pipeline, dot)?,
+                        None => Value::Null,
+                    };
+                    self.template(name, value)?;
+                    Flow::Normal
+                }
+                Node::Break(_) => Flow::Break,
+                Node::Continue(_) => Flow::Continue,
+            };
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// An `if`, or a `with` which also sets `.`.
+    fn branch(&mut self, branch: &Branch, dot: &Value, with: bool) -> Result<Flow, RenderError> {
+        let mark = self.variables.len();
+        let value = self.pipeline(&branch.pipeline, dot)?;
+
+        let flow = if is_true(&value) {
+            self.list(&branch.body, if with { &value } else { dot })?
+        } else if let Some(otherwise) = &branch.otherwise {
+            self.list(otherwise, dot)?
+        } else {
+            Flow::Normal
+        };
+
+        self.variables.truncate(mark);
+        Ok(flow)
+    }
+
+    fn range(&mut self, branch: &Branch, dot: &Value) -> Result<Flow, RenderError> {
+        let mark = self.variables.len();
+        let value = self.commands(&branch.pipeline, dot)?;
+
+        let items: Vec<(Value, Value)> = match value {
+            Value::Array(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| (index.into(), item))
+                .collect(),
+            Value::Object(map) => map
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+            Value::Number(number) if number.is_u64() || number.is_i64() => {
+                let count = number.as_u64().unwrap_or_default();
+                (0..count)
+                    .map(|index| (index.into(), index.into()))
+                    .collect()
+            }
+            Value::Null => Vec::new(),
+            value => {
+                return Err(RenderError::Execute(format!(
+                    "range can't iterate over {}",
+                    format_value(&value)
+                )))
+            }
+        };
+
+        let empty = items.is_empty();
+        for (key, item) in items {
+            let values = match branch.pipeline.variables.len() {
+                1 => vec![item.clone()],
+                _ => vec![key, item.clone()],
+            };
+            self.declare(&branch.pipeline, values)?;
+
+            let flow = self.list(&branch.body, &item)?;
+            self.variables.truncate(mark);
+            if matches!(flow, Flow::Break) {
+                break;
+            }
+        }
+
+        let flow = match &branch.otherwise {
+            Some(otherwise) if empty => self.list(otherwise, dot)?,
+            _ => Flow::Normal,
+        };
+
+        self.variables.truncate(mark);
+        Ok(flow)
+    }
+
+    fn template(&mut self, name: &str, dot: Value) -> Result<(), RenderError> {
+        let body = self
+            .templates
+            .get(name)
+            .copied()
+            .ok_or_else(|| RenderError::Execute(format!("no such template {name:?}")))?;
+        if self.depth >= MAX_DEPTH {
+            return Err(RenderError::Execute(format!(
+                "exceeded maximum template depth calling {name:?}"
+            )));
+        }
+
+        // a template only sees its own `$`
+        let variables =
+            std::mem::replace(&mut self.variables, vec![("$".to_string(), dot.clone())]);
+        let result = self.list(body, &dot);
+        self.variables = variables;
+
+        result.map(|_| ())
+    }
+
+    /// Evaluate the pipeline and declare or assign its variables.
+    fn pipeline(&mut self, pipeline: &Pipeline, dot: &Value) -> Result<Value, RenderError> {
+        let value = self.commands(pipeline, dot)?;
+        let values = vec![value.clone(); pipeline.variables.len()];
+        self.declare(pipeline, values)?;
+
+        Ok(value)
+    }
+
+    fn declare(&mut self, pipeline: &Pipeline, values: Vec<Value>) -> Result<(), RenderError> {
+        for (name, value) in pipeline.variables.iter().zip(values) {
+            if !pipeline.assign {
+                self.variables.push((name.clone(), value));
+                continue;
+            }
+
+            let variable = self
+                .variables
+                .iter_mut()
+                .rev()
+                .find(|(defined, _)| defined == name)
+                .ok_or_else(|| RenderError::Execute(format!("undefined variable {name}")))?;
+            variable.1 = value;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the commands, passing each result to the next.
+    fn commands(&mut self, pipeline: &Pipeline, dot: &Value) -> Result<Value, RenderError> {
+        let mut piped = None;
+
+        for command in &pipeline.commands {
+            piped = Some(self.command(command, dot, piped)?);
+        }
+
+        Ok(piped.unwrap_or_default())
+    }
+
+    fn command(
+        &mut self,
+        command: &Command,
+        dot: &Value,
+        piped: Option<Value>,
+    ) -> Result<Value, RenderError> {
+        match command.args.split_first() {
+            Some((Operand::Function(name), args)) => self.call(name, args, dot, piped),
+            Some((operand, [])) if piped.is_none() => self.operand(operand, dot),
+            _ => Err(RenderError::Execute(
+                "can't give argument to non-function".to_string(),
+            )),
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand, dot: &Value) -> Result<Value, RenderError> {
+        Ok(match operand {
+            Operand::Dot => dot.clone(),
+            Operand::Field(fields) => field(dot, fields)?,
+            Operand::Variable { name, fields } => {
+                let (_, value) = self
+                    .variables
+                    .iter()
+                    .rev()
+                    .find(|(defined, _)| defined == name)
+                    .ok_or_else(|| RenderError::Execute(format!("undefined variable {name}")))?;
+                field(value, fields)?
+            }
+            Operand::Function(name) => self.call(name, &[], dot, None)?,
+            Operand::Pipeline { pipeline, fields } => {
+                let value = self.pipeline(pipeline, dot)?;
+                field(&value, fields)?
+            }
+            Operand::String(text) => Value::String(text.clone()),
+            Operand::Number(number) => parse_number(number)?,
+            Operand::Char(c) => u32::from(*c).into(),
+            Operand::Bool(value) => Value::Bool(*value),
+            Operand::Nil => Value::Null,
+        })
+    }
+
+    fn call(
+        &mut self,
+        function: &str,
+        args: &[Operand],
+        dot: &Value,
+        piped: Option<Value>,
+    ) -> Result<Value, RenderError> {
+        // `and` and `or` stop evaluating at the first value that decides them
+        if let "and" | "or" = function {
+            let stop = function == "or";
+            let mut value = Value::Null;
+            for arg in args {
+                value = self.operand(arg, dot)?;
+                if is_true(&value) == stop {
+                    return Ok(value);
+                }
+            }
+            return match piped {
+                Some(piped) => Ok(piped),
+                None if args.is_empty() => Err(call_error(function, "wrong number of args")),
+                None => Ok(value),
+            };
+        }
+
+        let mut values = Vec::with_capacity(args.len() + 1);
+        for arg in args {
+            values.push(self.operand(arg, dot)?);
+        }
+        values.extend(piped);
+
+        builtin(function, &values).map_err(|message| call_error(function, message))
+    }
+}
+
+fn call_error(function: &str, message: impl Into<String>) -> RenderError {
+    RenderError::Call {
+        function: function.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Look up `.A.B` on a value.
+///
+/// Go templates see Ollama's structs, which are serialized in `snake_case`,
+/// so `.ToolCalls` finds `tool_calls`.
+/// Missing fields are `null`, like the zero values of Go structs.
+fn field(value: &Value, fields: &[String]) -> Result<Value, RenderError> {
+    let mut value = value;
+
+    for name in fields {
+        value = match value {
+            Value::Object(map) => match map.get(name).or_else(|| map.get(&snake_case(name))) {
+                Some(value) => value,
+                None => &Value::Null,
+            },
+            Value::Null => &Value::Null,
+            value => {
+                return Err(RenderError::Execute(format!(
+                    "can't evaluate field {name} in {}",
+                    kind(value)
+                )))
+            }
+        };
+    }
+
+    Ok(value.clone())
+}
+
+pub(crate) fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    let mut previous_lower = false;
+
+    for c in name.chars() {
+        if c.is_uppercase() && previous_lower {
+            snake.push('_');
+        }
+        previous_lower = c.is_lowercase() || c.is_ascii_digit();
+        snake.extend(c.to_lowercase());
+    }
+
+    snake
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "nil",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "slice",
+        Value::Object(_) => "map",
+    }
+}
+
+fn parse_number(number: &str) -> Result<Value, RenderError> {
+    let invalid = || RenderError::Execute(format!("invalid number {number}"));
+    let digits = number.replace('_', "");
+    let (negative, unsigned) = match digits.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, digits.trim_start_matches('+')),
+    };
+
+    let radix = [
+        ("0x", 16),
+        ("0X", 16),
+        ("0o", 8),
+        ("0O", 8),
+        ("0b", 2),
+        ("0B", 2),
+    ]
+    .into_iter()
+    .find_map(|(prefix, radix)| unsigned.strip_prefix(prefix).map(|rest| (rest, radix)));
+    if let Some((rest, radix)) = radix {
+        let value = i64::from_str_radix(rest, radix).map_err(|_| invalid())?;
+        return Ok((if negative { -value } else { value }).into());
+    }
+
+    if let Ok(value) = digits.parse::<i64>() {
+        return Ok(value.into());
+    }
+    let value: f64 = digits.parse().map_err(|_| invalid())?;
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(invalid)
+}
+
+/// Go's idea of truth: `false`, `0`, `nil` and empty values are false.
+fn is_true(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Print a value like Go's `%v` does for Ollama's types,
+/// which print lists and objects as JSON.
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => float.to_string(),
+            _ => number.to_string(),
+        },
+        Value::Array(_) | Value::Object(_) => go_json(value),
+    }
+}
+
+/// JSON like Go's `json.Marshal`, which escapes HTML characters.
+fn go_json(value: &Value) -> String {
+    let mut escaped = String::new();
+
+    for c in value.to_string().chars() {
+        match c {
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                escaped.push_str(&format!("\\u{:04x}", u32::from(c)));
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Call a function on evaluated arguments.
+fn builtin(function: &str, args: &[Value]) -> Result<Value, String> {
+    let arity = |count: usize| {
+        if args.len() == count {
+            Ok(())
+        } else {
+            Err(format!(
+                "wrong number of args: want {count} got {}",
+                args.len()
+            ))
+        }
+    };
+
+    Ok(match function {
+        "not" => {
+            arity(1)?;
+            Value::Bool(!is_true(&args[0]))
+        }
+        "eq" => {
+            let (first, rest) = args
+                .split_first()
+                .ok_or("missing argument for comparison")?;
+            if rest.is_empty() {
+                return Err("missing argument for comparison".to_string());
+            }
+            let mut equal = false;
+            for other in rest {
+                equal |= compare(first, other)? == std::cmp::Ordering::Equal;
+            }
+            Value::Bool(equal)
+        }
+        "ne" | "lt" | "le" | "gt" | "ge" => {
+            arity(2)?;
+            let ordering = compare(&args[0], &args[1])?;
+            let ordered = matches!((&args[0], &args[1]), (Value::Number(_), Value::Number(_)))
+                || matches!((&args[0], &args[1]), (Value::String(_), Value::String(_)));
+            if function != "ne" && !ordered {
+                return Err(format!("invalid type for comparison: {}", kind(&args[0])));
+            }
+            Value::Bool(match function {
+                "ne" => ordering.is_ne(),
+                "lt" => ordering.is_lt(),
+                "le" => ordering.is_le(),
+                "gt" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })
+        }
+        "len" => {
+            arity(1)?;
+            match &args[0] {
+                Value::String(text) => text.len().into(),
+                Value::Array(items) => items.len().into(),
+                Value::Object(map) => map.len().into(),
+                value => return Err(format!("len of {}", kind(value))),
+            }
+        }
+        "index" => {
+            let (item, keys) = args.split_first().ok_or("wrong number of args")?;
+            let mut item = item.clone();
+            for key in keys {
+                item = match (&item, key) {
+                    (Value::Array(items), key) => {
+                        let index = as_index(key)?;
+                        items
+                            .get(index)
+                            .cloned()
+                            .ok_or_else(|| format!("index out of range: {index}"))?
+                    }
+                    (Value::Object(map), Value::String(key)) => {
+                        map.get(key).cloned().unwrap_or_default()
+                    }
+                    (Value::Null, _) => return Err("index of untyped nil".to_string()),
+                    (item, _) => return Err(format!("can't index item of type {}", kind(item))),
+                };
+            }
+            item
+        }
+        "slice" => {
+            let (item, indexes) = args.split_first().ok_or("wrong number of args")?;
+            let indexes = indexes
+                .iter()
+                .map(as_index)
+                .collect::<Result<Vec<usize>, String>>()?;
+            let len = match item {
+                Value::String(text) => text.len(),
+                Value::Array(items) => items.len(),
+                item => return Err(format!("can't slice item of type {}", kind(item))),
+            };
+            let (start, end) = match indexes.as_slice() {
+                [] => (0, len),
+                [start] => (*start, len),
+                [start, end] => (*start, *end),
+                _ => return Err("too many slice indexes".to_string()),
+            };
+            if start > end || end > len {
+                return Err(format!("index out of range: {start}:{end}"));
+            }
+            match item {
+                Value::String(text) => text
+                    .get(start..end)
+                    .ok_or("slice is not at a character boundary")?
+                    .into(),
+                Value::Array(items) => items[start..end].into(),
+                _ => Value::Null,
+            }
+        }
+        "print" => {
+            let mut text = String::new();
+            for (index, arg) in args.iter().enumerate() {
+                let strings = arg.is_string() || index > 0 && args[index - 1].is_string();
+                if index > 0 && !strings {
+                    text.push(' ');
+                }
+                text.push_str(&format_value(arg));
+            }
+            text.into()
+        }
+        "println" => {
+            let words: Vec<String> = args.iter().map(format_value).collect();
+            format!("{}\n", words.join(" ")).into()
+        }
+        "printf" => {
+            let (format, args) = args.split_first().ok_or("wrong number of args")?;
+            printf(&format_value(format), args).into()
+        }
+        "html" => {
+            let text = sprint(args);
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '&' => escaped.push_str("&amp;"),
+                    '\'' => escaped.push_str("&#39;"),
+                    '"' => escaped.push_str("&#34;"),
+                    '\0' => escaped.push('\u{FFFD}'),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.into()
+        }
+        "js" => {
+            let text = sprint(args);
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\'' => escaped.push_str("\\'"),
+                    '"' => escaped.push_str("\\\""),
+                    '<' | '>' | '&' | '=' => escaped.push_str(&format!("\\u{:04X}", u32::from(c))),
+                    c if c.is_control() => escaped.push_str(&format!("\\u{:04X}", u32::from(c))),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.into()
+        }
+        "urlquery" => {
+            let text = sprint(args);
+            let mut escaped = String::with_capacity(text.len());
+            for byte in text.bytes() {
+                match byte {
+                    b' ' => escaped.push('+'),
+                    byte if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) => {
+                        escaped.push(char::from(byte));
+                    }
+                    byte => escaped.push_str(&format!("%{byte:02X}")),
+                }
+            }
+            escaped.into()
+        }
+        "json" => {
+            arity(1)?;
+            go_json(&args[0]).into()
+        }
+        "currentDate" => {
+            arity(0)?;
+            date(SystemTime::now()).into()
+        }
+        "yesterdayDate" => {
+            arity(0)?;
+            let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+            date(yesterday).into()
+        }
+        "toTypeScriptType" => {
+            arity(1)?;
+            typescript_type(&args[0]).into()
+        }
+        "call" => return Err("templates have no functions to call".to_string()),
+        function => return Err(format!("function {function:?} not defined")),
+    })
+}
+
+/// Compare basic values of the same kind, numbers by value.
+fn compare(left: &Value, right: &Value) -> Result<std::cmp::Ordering, String> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => match (left.as_i64(), right.as_i64()) {
+            (Some(left), Some(right)) => Ok(left.cmp(&right)),
+            _ => {
+                let (left, right) = (
+                    left.as_f64().unwrap_or(f64::NAN),
+                    right.as_f64().unwrap_or(f64::NAN),
+                );
+                left.partial_cmp(&right)
+                    .ok_or_else(|| "can't compare NaN".to_string())
+            }
+        },
+        (Value::String(left), Value::String(right)) => Ok(left.cmp(right)),
+        (Value::Bool(left), Value::Bool(right)) => Ok(left.cmp(right)),
+        (Value::Null, Value::Null) => Ok(std::cmp::Ordering::Equal),
+        (Value::Array(_) | Value::Object(_), _) | (_, Value::Array(_) | Value::Object(_)) => {
+            Err("non-comparable type".to_string())
+        }
+        _ => Err("incompatible types for comparison".to_string()),
+    }
+}
+
+fn as_index(value: &Value) -> Result<usize, String> {
+    value
+        .as_u64()
+        .and_then(|index| usize::try_from(index).ok())
+        .ok_or_else(|| format!("cannot index with {}", format_value(value)))
+}
+
+/// Like Go's `fmt.Sprint`.
+fn sprint(args: &[Value]) -> String {
+    match builtin("print", args) {
+        Ok(Value::String(text)) => text,
+        _ => String::new(),
+    }
+}
+
+/// Like Go's `fmt.Sprintf`, for the verbs templates use.
+fn printf(format: &str, args: &[Value]) -> String {
+    let mut text = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        let Some(verb) = chars.next() else {
+            text.push_str("%!(NOVERB)");
+            break;
+        };
+        if verb == '%' {
+            text.push('%');
+            continue;
+        }
+        let Some(arg) = args.next() else {
+            text.push_str(&format!("%!{verb}(MISSING)"));
+            continue;
+        };
+        match verb {
+            'v' | 's' | 'd' | 't' | 'g' => text.push_str(&format_value(arg)),
+            'q' => text.push_str(&Value::String(format_value(arg)).to_string()),
+            'f' => match arg.as_f64() {
+                Some(float) => text.push_str(&format!("{float:.6}")),
+                None => text.push_str(&format!("%!f({})", format_value(arg))),
+            },
+            verb => text.push_str(&format!("%!{verb}({})", format_value(arg))),
+        }
+    }
+
+    let extra: Vec<String> = args.map(format_value).collect();
+    if !extra.is_empty() {
+        text.push_str(&format!("%!(EXTRA {})", extra.join(", ")));
+    }
+
+    text
+}
+
+/// The UTC date of `time`, like `2024-11-02`.
+fn date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = i64::try_from(seconds / 86_400).unwrap_or_default();
+
+    // days to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The TypeScript type of a tool parameter, like Ollama's `ToTypeScriptType`.
+fn typescript_type(property: &Value) -> String {
+    let types: Vec<&str> = match property.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if types.is_empty() {
+        return "any".to_string();
+    }
+
+    types
+        .into_iter()
+        .map(|name| match name {
+            "string" => "string",
+            "number" | "integer" => "number",
+            "boolean" => "boolean",
+            "array" => "any[]",
+            "object" => "Record<string, any>",
+            "null" => "null",
+            _ => "any",
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+
+    use crate::{
+        message::{Message, ToolCall, ToolCallFunction},
+        modelfile::test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR},
+    };
+
+    use super::*;
+
+    fn message(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+        }
+    }
+
+    fn execute(template: &str, data: Value) -> String {
+        template
+            .parse::<TemplateAst>()
+            .expect("template should parse")
+            .execute(&data)
+            .expect("template should execute")
+    }
+
+    #[test]
+    fn fixture_previews() {
+        let chat = [
+            message("user", "Why is the sky blue?"),
+            message("assistant", "Rayleigh scattering."),
+            message("user", "Explain it like I'm five."),
+        ];
+
+        let mut fixtures = load_modelfiles(TEST_GOOD_DATA_DIR);
+        fixtures.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut previews = String::new();
+        for TestData { path, contents } in fixtures {
+            let modelfile: Modelfile = contents.parse().expect("fixture should parse");
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .expect("fixture should have a file name");
+            let preview = modelfile.preview(&chat).expect("fixture should render");
+            previews.push_str(&format!("===== {name} =====\n{preview}\n"));
+        }
+
+        assert_snapshot!(previews);
+    }
+
+    #[test]
+    fn tool_calls_are_previewed() {
+        let contents = std::fs::read_to_string(
+            std::path::Path::new(TEST_GOOD_DATA_DIR).join("llama3.2.latest.Modelfile"),
+        )
+        .expect("should read llama3.2 fixture");
+        let modelfile: Modelfile = contents.parse().expect("fixture should parse");
+
+        let chat: Vec<ChatMessage> = [
+            Message::User("What's the weather in Paris?".into()),
+            Message::ToolCalls {
+                content: "".into(),
+                tool_calls: vec![ToolCall {
+                    function: ToolCallFunction {
+                        name: "get_weather".to_string(),
+                        arguments: [("city".to_string(), json!("Paris"))].into(),
+                        extra: Default::default(),
+                    },
+                    extra: Default::default(),
+                }],
+            },
+            Message::Tool("22 degrees and sunny".into()),
+        ]
+        .iter()
+        .map(ChatMessage::from)
+        .collect();
+
+        let preview = modelfile.preview(&chat).expect("tool calls should render");
+        assert_snapshot!(preview, @r#"
+        <|start_header_id|>system<|end_header_id|>
+
+        Cutting Knowledge Date: December 2023
+
+        <|eot_id|><|start_header_id|>user<|end_header_id|>
+
+        What's the weather in Paris?<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+
+        {"name": "get_weather", "parameters": {"city":"Paris"}}<|eot_id|><|start_header_id|>ipython<|end_header_id|>
+
+        22 degrees and sunny<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+        "#);
+    }
+
+    #[test]
+    fn go_semantics() {
+        let data = json!({
+            "Messages": [message("user", "hi"), message("assistant", "hello")],
+            "Tools": [{"type": "function", "function": {"name": "weather", "parameters": {"type": "object"}}}],
+            "count": 3,
+        });
+
+        assert_snapshot!(
+            execute(
+                "{{- range $i, $m := .Messages }}{{ $i }}:{{ .Role }}={{ $m.Content }}\n{{ end -}}",
+                data.clone(),
+            ),
+            @r"
+        0:user=hi
+        1:assistant=hello
+        "
+        );
+        assert_snapshot!(
+            execute(
+                "{{ range .missing }}x{{ else }}empty{{ end }} {{ range .count }}{{ if eq . 1 }}{{ continue }}{{ end }}{{ . }}{{ end }}",
+                data.clone(),
+            ),
+            @"empty 02"
+        );
+        assert_snapshot!(
+            execute(
+                "{{ $x := 1 }}{{ if true }}{{ $x = 2 }}{{ end }}{{ $x }} {{ len .Messages | printf \"%d messages\" }}",
+                data.clone(),
+            ),
+            @"2 2 messages"
+        );
+        assert_snapshot!(
+            execute(
+                "{{ with index .Tools 0 }}{{ .Function.Name }}: {{ json .Function.Parameters }} {{ toTypeScriptType .Function.Parameters }}{{ end }}",
+                data.clone(),
+            ),
+            @r#"weather: {"type":"object"} Record<string, any>"#
+        );
+        assert_snapshot!(
+            execute(
+                "{{ define \"turn\" }}<{{ .Role }}>{{ end }}{{ range .Messages }}{{ template \"turn\" . }}{{ end }} {{ and .count .missing }}|{{ or .missing \"default\" }}",
+                data,
+            ),
+            @"<user><assistant> |default"
+        );
+    }
+
+    #[test]
+    fn recursive_templates_stop_at_the_depth_limit() {
+        let ast: TemplateAst =
+            "{{ define \"a\" }}{{ template \"a\" . }}{{ end }}{{ template \"a\" . }}"
+                .parse()
+                .expect("template should parse");
+
+        assert_snapshot!(
+            ast.execute(&json!({})).expect_err("recursion should be stopped"),
+            @r#"exceeded maximum template depth calling "a""#
+        );
+
+        // every block is another level
+        let nested: TemplateAst = "{{ define \"a\" }}{{ if . }}{{ with . }}{{ range $k, $v := . }}{{ if eq $k \"x\" }}{{ template \"a\" $ }}{{ end }}{{ end }}{{ end }}{{ end }}{{ end }}{{ template \"a\" . }}"
+            .parse()
+            .expect("template should parse");
+        assert_snapshot!(
+            nested.execute(&json!({"x": 1})).expect_err("recursion should be stopped"),
+            @"exceeded maximum template depth"
+        );
+    }
+
+    #[test]
+    fn older_templates_repeat_every_turn() {
+        let ast: TemplateAst = "{{ if .System }}[SYS]{{ .System }}[/SYS]{{ end }}[INST]{{ .Prompt }}[/INST]{{ .Response }}</s>"
+            .parse()
+            .expect("template should parse");
+        let values = TemplateValues {
+            messages: vec![
+                message("system", "Be brief."),
+                message("user", "Hi"),
+                message("assistant", "Hello"),
+                message("user", "Bye"),
+            ],
+            ..TemplateValues::default()
+        };
+
+        assert_snapshot!(
+            ast.render(&values).expect("template should render"),
+            @"[SYS]Be brief.[/SYS][INST]Hi[/INST]Hello</s>[INST]Bye[/INST]"
+        );
+    }
+
+    #[test]
+    fn dates_are_civil() {
+        assert_eq!(date(SystemTime::UNIX_EPOCH), "1970-01-01");
+        assert_eq!(
+            date(SystemTime::UNIX_EPOCH + Duration::from_secs(1_730_548_800)),
+            "2024-11-02"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! The Ollama version a [`super::Modelfile`] `REQUIRES`.
+
+use std::str::FromStr;
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use super::{error::ModelfileError, Modelfile};
+
+/// Represented by the `REQUIRES` field in the [`crate::Modelfile`].
+///
+/// Ollama writes a bare version, like `REQUIRES 0.5.0`,
+/// which is the minimum server version.
+/// Full requirements, like `>=0.5, <0.7`, are accepted too.
+///
+/// Keeps the text exactly as written,
+/// so [`Display`](std::fmt::Display) round-trips losslessly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Requires {
+    text: String,
+    requirement: VersionReq,
+}
+
+impl Requires {
+    /// The `REQUIRES` value exactly as written.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The requirement, with a bare version read as `>=`.
+    pub fn requirement(&self) -> &VersionReq {
+        &self.requirement
+    }
+
+    /// `true` if a server at `version` can run the model.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.requirement.matches(version)
+    }
+}
+
+impl FromStr for Requires {
+    type Err = semver::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let version = text.strip_prefix('v').unwrap_or(text);
+        let requirement = if version.starts_with(|c: char| c.is_ascii_digit()) {
+            format!(">={version}").parse()?
+        } else {
+            text.parse()?
+        };
+
+        Ok(Requires {
+            text: text.to_string(),
+            requirement,
+        })
+    }
+}
+
+impl TryFrom<String> for Requires {
+    type Error = semver::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Requires> for String {
+    fn from(value: Requires) -> Self {
+        value.text
+    }
+}
+
+impl std::fmt::Display for Requires {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Modelfile {
+    /// Check that an Ollama server at `version`
+    /// meets the `REQUIRES` of the Modelfile, if it has one.
+    pub fn check_version(&self, version: &Version) -> Result<(), ModelfileError> {
+        match &self.requires {
+            Some(requires) if !requires.matches(version) => Err(ModelfileError::Incompatible {
+                requires: requires.clone(),
+                version: version.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    use super::*;
+
+    #[test]
+    fn requirements_are_checked() {
+        let checks: Vec<(&str, &str, Result<bool, String>)> = [
+            ("0.5.0", "0.5.0"),
+            ("0.5.0", "0.4.7"),
+            ("0.5.0", "0.12.3"),
+            ("v0.3", "0.3.14"),
+            (">=0.5, <0.7", "0.7.0"),
+            ("0.5.0-rc1", "0.5.0"),
+            ("latest", "0.5.0"),
+        ]
+        .into_iter()
+        .map(|(requires, version)| {
+            let server: Version = version.parse().expect("should parse version");
+            let matches = requires
+                .parse::<Requires>()
+                .map(|requires| requires.matches(&server))
+                .map_err(|error| error.to_string());
+            (requires, version, matches)
+        })
+        .collect();
+
+        assert_debug_snapshot!(checks, @r#"
+        [
+            (
+                "0.5.0",
+                "0.5.0",
+                Ok(
+                    true,
+                ),
+            ),
+            (
+                "0.5.0",
+                "0.4.7",
+                Ok(
+                    false,
+                ),
+            ),
+            (
+                "0.5.0",
+                "0.12.3",
+                Ok(
+                    true,
+                ),
+            ),
+            (
+                "v0.3",
+                "0.3.14",
+                Ok(
+                    true,
+                ),
+            ),
+            (
+                ">=0.5, <0.7",
+                "0.7.0",
+                Ok(
+                    false,
+                ),
+            ),
+            (
+                "0.5.0-rc1",
+                "0.5.0",
+                Ok(
+                    true,
+                ),
+            ),
+            (
+                "latest",
+                "0.5.0",
+                Err(
+                    "unexpected character 'l' while parsing major version number",
+                ),
+            ),
+        ]
+        "#);
+    }
+
+    #[test]
+    fn incompatible_servers_are_errors() {
+        let modelfile: Modelfile = "FROM llama3.2\nREQUIRES 0.5.0\n"
+            .parse()
+            .expect("should parse REQUIRES");
+
+        let rendered: Modelfile = modelfile
+            .render()
+            .parse()
+            .expect("should parse rendered REQUIRES");
+        assert_eq!(rendered, modelfile);
+        let toml = toml::to_string(&modelfile).expect("should serialize REQUIRES");
+        assert_eq!(
+            toml::from_str::<Modelfile>(&toml).expect("should deserialize REQUIRES"),
+            modelfile
+        );
+
+        modelfile
+            .check_version(&Version::new(0, 5, 1))
+            .expect("newer server should be compatible");
+        let error = modelfile
+            .check_version(&Version::new(0, 4, 7))
+            .expect_err("older server should be incompatible");
+
+        assert_snapshot!(error, @"Modelfile requires Ollama 0.5.0, but the server is version 0.4.7");
+    }
+}
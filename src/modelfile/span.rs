@@ -0,0 +1,169 @@
+//! Locations of parsed items in the source of a [`super::Modelfile`].
+//!
+//! Spans are recorded as byte ranges into the original input
+//! alongside human friendly line and column numbers,
+//! so tooling like linters and editors can point at the offending line.
+
+use std::ops::Range;
+
+use derive_more::derive::{AsRef, Deref};
+use serde::{Deserialize, Serialize};
+
+/// A line and column in the source text.
+///
+/// Both values start at 1, like the positions reported by editors and compilers.
+/// Columns are counted in characters, not bytes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A region of the source text.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte range of the region in the source text.
+    pub range: Range<usize>,
+    /// The position of the first character in the region.
+    pub start: Position,
+    /// The position just past the last character in the region.
+    pub end: Position,
+}
+
+impl Span {
+    /// Create a [`Span`] for the given byte range of `source`.
+    pub fn new(source: &str, range: Range<usize>) -> Self {
+        LineIndex::new(source).span(range)
+    }
+
+    /// The text this span covers in `source`.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.range.clone()]
+    }
+}
+
+/// A value paired with the [`Span`] it was parsed from.
+#[derive(AsRef, Debug, Deref, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Spanned<T> {
+    #[as_ref]
+    #[deref]
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Self { value, span }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+/// Precomputed line starts of a source text,
+/// used to turn byte offsets into [`Position`]s.
+#[derive(Debug, Clone)]
+pub(crate) struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The [`Position`] of the given byte offset.
+    ///
+    /// Offsets past the end of the source are clamped to the end.
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.source.len());
+        let line = self
+            .line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1);
+        let line_start = self.line_starts[line];
+        let column = self
+            .source
+            .get(line_start..offset)
+            .map(|prefix| prefix.chars().count())
+            .unwrap_or(offset - line_start);
+
+        Position {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+
+    pub(crate) fn span(&self, range: Range<usize>) -> Span {
+        Span {
+            start: self.position(range.start),
+            end: self.position(range.end),
+            range,
+        }
+    }
+
+    /// The full text of the line containing the given byte offset,
+    /// without the trailing line ending.
+    pub(crate) fn line_text(&self, offset: usize) -> &'a str {
+        let line = self.position(offset).line - 1;
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.source.len());
+
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_are_one_based() {
+        let source = "FROM llama3.2\nPARAMETER num_ctx 4096\n";
+        let index = LineIndex::new(source);
+
+        assert_eq!(index.position(0), Position { line: 1, column: 1 });
+        assert_eq!(index.position(5), Position { line: 1, column: 6 });
+        assert_eq!(index.position(14), Position { line: 2, column: 1 });
+        assert_eq!(
+            index.position(source.len()),
+            Position { line: 3, column: 1 }
+        );
+        assert_eq!(index.line_text(20), "PARAMETER num_ctx 4096");
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let source = "SYSTEM “quoted” text";
+        let index = LineIndex::new(source);
+
+        let offset = source.find("text").expect("should find text");
+
+        assert_eq!(
+            index.position(offset),
+            Position {
+                line: 1,
+                column: 17
+            }
+        );
+    }
+}
@@ -0,0 +1,1378 @@
+//! A parser for the Go [`text/template`] syntax of `TEMPLATE` bodies.
+//!
+//! [`Template::parse`] builds a [`TemplateAst`] and reports the mistakes
+//! Ollama would only find at `ollama create` time,
+//! like an unbalanced `{{ end }}`, an undefined `$variable`
+//! or a field Ollama doesn't pass to templates.
+//!
+//! [`text/template`]: https://pkg.go.dev/text/template
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+use super::{
+    error::write_snippet,
+    instruction::Template,
+    span::{LineIndex, Span},
+};
+
+/// How deep blocks like `{{ if }}`, `(pipelines)`
+/// and `{{ template }}` calls can nest.
+///
+/// Go allows 100000 calls since its stacks grow,
+/// this is low enough for the native stack of a thread.
+pub(crate) const MAX_DEPTH: usize = 200;
+
+/// The functions Go templates have built in,
+/// and the ones Ollama adds.
+const FUNCTIONS: &[&str] = &[
+    "and",
+    "call",
+    "eq",
+    "ge",
+    "gt",
+    "html",
+    "index",
+    "js",
+    "le",
+    "len",
+    "lt",
+    "ne",
+    "not",
+    "or",
+    "print",
+    "printf",
+    "println",
+    "slice",
+    "urlquery",
+    // Ollama
+    "currentDate",
+    "json",
+    "toTypeScriptType",
+    "yesterdayDate",
+];
+
+/// Every field Ollama passes to templates,
+/// on the template values, messages, tool calls and tools.
+///
+/// Only capitalized fields read from the template values are checked,
+/// lowercase names are keys of maps like tool call arguments.
+pub(crate) const FIELDS: &[&str] = &[
+    // template values
+    "System",
+    "Prompt",
+    "Suffix",
+    "Response",
+    "Messages",
+    "Tools",
+    "Think",
+    "ThinkLevel",
+    "IsThinkSet",
+    // messages
+    "Role",
+    "Content",
+    "Thinking",
+    "Images",
+    "ToolCalls",
+    "ToolName",
+    "ToolCallID",
+    // tool calls
+    "ID",
+    "Function",
+    "Index",
+    "Name",
+    "Arguments",
+    // tools
+    "Type",
+    "Items",
+    "Description",
+    "Parameters",
+    "Required",
+    "Properties",
+    "Enum",
+    "Defs",
+];
+
+/// A parsed template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateAst {
+    pub nodes: Vec<Node>,
+}
+
+/// A part of a template.
+///
+/// Text next to `{{-` and `-}}` trim markers
+/// has already had its whitespace trimmed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Text outside of actions, written as is.
+    Text(String),
+    /// `{{/* a comment */}}`
+    Comment(String),
+    /// `{{ pipeline }}`, writes the value of the pipeline.
+    Action { pipeline: Pipeline, span: Span },
+    /// `{{ if pipeline }} … {{ else }} … {{ end }}`
+    If(Branch),
+    /// `{{ range pipeline }} … {{ else }} … {{ end }}`
+    Range(Branch),
+    /// `{{ with pipeline }} … {{ else }} … {{ end }}`
+    With(Branch),
+    /// `{{ define "name" }} … {{ end }}`
+    Define {
+        name: String,
+        body: Vec<Node>,
+        span: Span,
+    },
+    /// `{{ block "name" pipeline }} … {{ end }}`
+    Block {
+        name: String,
+        pipeline: Pipeline,
+        body: Vec<Node>,
+        span: Span,
+    },
+    /// `{{ template "name" pipeline }}`
+    Template {
+        name: String,
+        pipeline: Option<Pipeline>,
+        span: Span,
+    },
+    /// `{{ break }}`
+    Break(Span),
+    /// `{{ continue }}`
+    Continue(Span),
+}
+
+/// The parts of an `if`, `range` or `with`.
+///
+/// `{{ else if … }}` is an `otherwise` with a single nested [`Node::If`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub pipeline: Pipeline,
+    pub body: Vec<Node>,
+    pub otherwise: Option<Vec<Node>>,
+    /// The opening action.
+    pub span: Span,
+}
+
+/// Commands separated by `|`,
+/// optionally declaring or assigning variables, like `$i, $_ := .Messages`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    /// The variables, with their `$`.
+    pub variables: Vec<String>,
+    /// `true` for `=`, `false` for `:=`.
+    pub assign: bool,
+    pub commands: Vec<Command>,
+}
+
+/// A function call or a single value, like `eq .Role "user"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub args: Vec<Operand>,
+}
+
+/// A value in a [`Command`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    /// `.`
+    Dot,
+    /// `.Messages` or `.Function.Name`.
+    Field(Vec<String>),
+    /// `$`, `$last` or `$.Tools`, the name with its `$` and any fields.
+    Variable {
+        name: String,
+        fields: Vec<String>,
+    },
+    /// The name of a function, like `eq`.
+    Function(String),
+    /// `(pipeline)`, with any fields after it like `(index .Messages 0).Role`.
+    Pipeline {
+        pipeline: Box<Pipeline>,
+        fields: Vec<String>,
+    },
+    String(String),
+    /// A number as written, like `1`, `-2.5` or `0x1F`.
+    Number(String),
+    Char(char),
+    Bool(bool),
+    Nil,
+}
+
+/// A located description of why a template could not be parsed.
+///
+/// Displayed like a [`super::error::ParseDiagnostic`],
+/// with positions relative to the start of the template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    pub span: Span,
+    /// The full template line containing the start of [`Self::span`].
+    pub line: String,
+}
+
+/// What is wrong with a template.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TemplateErrorKind {
+    #[error("unclosed action, expected `}}}}`")]
+    UnclosedAction,
+    #[error("unclosed comment, expected `*/}}}}`")]
+    UnclosedComment,
+    #[error("unterminated {0} literal")]
+    Unterminated(&'static str),
+    #[error("unexpected `{0}` in action")]
+    Unexpected(String),
+    #[error("missing value in action")]
+    MissingValue,
+    #[error("`{{{{ end }}}}` without an open `if`, `range`, `with`, `define` or `block`")]
+    UnexpectedEnd,
+    #[error("`{{{{ else }}}}` outside of an `if`, `range` or `with`")]
+    UnexpectedElse,
+    #[error("`{{{{ {0} }}}}` is never closed with `{{{{ end }}}}`")]
+    Unclosed(&'static str),
+    #[error("`{{{{ {0} }}}}` outside of a `range`")]
+    OutsideRange(&'static str),
+    #[error("undefined variable {0}")]
+    UndefinedVariable(String),
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("unknown field .{0}")]
+    UnknownField(String),
+    #[error("exceeded maximum template depth of {}", MAX_DEPTH)]
+    TooDeep,
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_snippet(f, &self.kind.to_string(), &self.span, &self.line)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Template {
+    /// Parse the template into a [`TemplateAst`].
+    pub fn parse(&self) -> Result<TemplateAst, TemplateError> {
+        AsRef::<str>::as_ref(&**self).parse()
+    }
+}
+
+impl std::str::FromStr for TemplateAst {
+    type Err = TemplateError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let index = LineIndex::new(source);
+        let error = |(kind, range): (TemplateErrorKind, Range<usize>)| TemplateError {
+            kind,
+            line: index.line_text(range.start).to_string(),
+            span: index.span(range),
+        };
+
+        let items = scan(source).map_err(error)?;
+        let mut parser = Parser {
+            source,
+            items: items.into_iter(),
+            index: &index,
+            scopes: vec![vec!["$".to_string()]],
+            range_depth: 0,
+            rebound: 0,
+            defines: 0,
+            depth: 1,
+        };
+
+        let (nodes, terminator) = parser.list().map_err(error)?;
+        match terminator {
+            Some(Terminator::End(range)) => Err(error((TemplateErrorKind::UnexpectedEnd, range))),
+            Some(Terminator::Else(_, range)) => {
+                Err(error((TemplateErrorKind::UnexpectedElse, range)))
+            }
+            None => Ok(TemplateAst { nodes }),
+        }
+    }
+}
+
+type Failure = (TemplateErrorKind, Range<usize>);
+
+/// A token inside an action.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Field(String),
+    Variable(String),
+    Ident(String),
+    String(String),
+    Number(String),
+    Char(char),
+    LeftParen,
+    RightParen,
+    Pipe,
+    Comma,
+    Declare,
+    Assign,
+}
+
+#[derive(Debug, Clone)]
+struct Lexeme {
+    token: Token,
+    range: Range<usize>,
+}
+
+/// Text, comments and actions, before they are nested.
+#[derive(Debug)]
+enum Item {
+    Text(String),
+    Comment(String),
+    Action {
+        lexemes: Vec<Lexeme>,
+        range: Range<usize>,
+    },
+}
+
+/// Split the source into [`Item`]s, applying trim markers.
+fn scan(source: &str) -> Result<Vec<Item>, Failure> {
+    let mut items = Vec::new();
+    let mut rest = 0;
+    let mut trim_next = false;
+
+    while let Some(found) = source[rest..].find("{{") {
+        let start = rest + found;
+        let mut text = &source[rest..start];
+        if trim_next {
+            text = text.trim_start_matches(is_space);
+        }
+
+        let mut inner = start + 2;
+        let trim_left =
+            source[inner..].starts_with('-') && source[inner + 1..].starts_with(is_space);
+        if trim_left {
+            text = text.trim_end_matches(is_space);
+            inner += 1;
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text.to_string()));
+        }
+
+        let content_start = inner + (source[inner..].len() - source[inner..].trim_start().len());
+        let (item, content_end, trim_right) = if source[content_start..].starts_with("/*") {
+            let close = source[content_start..]
+                .find("*/")
+                .map(|found| content_start + found)
+                .ok_or((TemplateErrorKind::UnclosedComment, start..source.len()))?;
+            let after = &source[close + 2..];
+            let trim_right = after.starts_with(" -}}");
+            if !trim_right && !after.starts_with("}}") {
+                return Err((TemplateErrorKind::UnclosedComment, start..close + 2));
+            }
+            let comment = Item::Comment(source[content_start + 2..close].to_string());
+            (comment, close + 2 + usize::from(trim_right) * 2, trim_right)
+        } else {
+            let end = action_end(source, inner)
+                .ok_or((TemplateErrorKind::UnclosedAction, start..source.len()))?;
+            let trim_right = source[inner..end].ends_with(" -")
+                || source[inner..end].ends_with("\t-")
+                || source[inner..end].ends_with("\n-");
+            let content_end = if trim_right { end - 1 } else { end };
+            let lexemes = lex(source, inner..content_end)?;
+            (
+                Item::Action {
+                    lexemes,
+                    range: start..end + 2,
+                },
+                end,
+                trim_right,
+            )
+        };
+
+        items.push(item);
+        rest = content_end + 2;
+        trim_next = trim_right;
+    }
+
+    let mut text = &source[rest..];
+    if trim_next {
+        text = text.trim_start_matches(is_space);
+    }
+    if !text.is_empty() {
+        items.push(Item::Text(text.to_string()));
+    }
+
+    Ok(items)
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// The offset of the `}}` closing the action starting at `start`,
+/// skipping over quoted text.
+fn action_end(source: &str, start: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut offset = start;
+
+    while offset < bytes.len() {
+        match bytes[offset] {
+            b'}' if bytes.get(offset + 1) == Some(&b'}') => return Some(offset),
+            quote @ (b'"' | b'\'') => {
+                offset += 1;
+                while offset < bytes.len() && bytes[offset] != quote {
+                    if bytes[offset] == b'\\' {
+                        offset += 1;
+                    }
+                    offset += 1;
+                }
+            }
+            b'`' => {
+                offset += 1 + source[offset + 1..].find('`')?;
+            }
+            _ => {}
+        }
+        offset += 1;
+    }
+
+    None
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Split the inside of an action into tokens.
+fn lex(source: &str, range: Range<usize>) -> Result<Vec<Lexeme>, Failure> {
+    let mut lexemes = Vec::new();
+    let mut offset = range.start;
+
+    while offset < range.end {
+        let rest = &source[offset..range.end];
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        if is_space(c) {
+            offset += 1;
+            continue;
+        }
+
+        let take_ident = |from: usize| {
+            rest[from..]
+                .find(|c: char| !is_ident(c))
+                .map_or(rest.len(), |end| from + end)
+        };
+
+        let (token, len) = match c {
+            '(' => (Token::LeftParen, 1),
+            ')' => (Token::RightParen, 1),
+            '|' => (Token::Pipe, 1),
+            ',' => (Token::Comma, 1),
+            '=' => (Token::Assign, 1),
+            ':' if rest.starts_with(":=") => (Token::Declare, 2),
+            '.' if rest[1..].starts_with(|c: char| c.is_ascii_digit()) => number(rest),
+            '.' if rest[1..].starts_with(is_ident) => {
+                let end = take_ident(1);
+                (Token::Field(rest[1..end].to_string()), end)
+            }
+            '.' => (Token::Dot, 1),
+            '$' => {
+                let end = take_ident(1);
+                (Token::Variable(rest[..end].to_string()), end)
+            }
+            '"' => {
+                let (text, len) = quoted(rest, offset)?;
+                (Token::String(text), len)
+            }
+            '`' => {
+                let end = rest[1..].find('`').ok_or((
+                    TemplateErrorKind::Unterminated("raw string"),
+                    offset..range.end,
+                ))?;
+                (Token::String(rest[1..end + 1].to_string()), end + 2)
+            }
+            '\'' => {
+                let (text, len) = quoted(rest, offset)?;
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => (Token::Char(c), len),
+                    _ => {
+                        return Err((
+                            TemplateErrorKind::Unexpected(rest[..len].to_string()),
+                            offset..offset + len,
+                        ))
+                    }
+                }
+            }
+            '+' | '-' if rest[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+                number(rest)
+            }
+            c if c.is_ascii_digit() => number(rest),
+            c if is_ident(c) => {
+                let end = take_ident(0);
+                (Token::Ident(rest[..end].to_string()), end)
+            }
+            c => {
+                return Err((
+                    TemplateErrorKind::Unexpected(c.to_string()),
+                    offset..offset + c.len_utf8(),
+                ))
+            }
+        };
+
+        lexemes.push(Lexeme {
+            token,
+            range: offset..offset + len,
+        });
+        offset += len;
+    }
+
+    Ok(lexemes)
+}
+
+/// A number literal at the start of `rest`.
+fn number(rest: &str) -> (Token, usize) {
+    let mut len = 0;
+    let mut previous = ' ';
+    for (index, c) in rest.char_indices() {
+        let sign =
+            matches!(c, '+' | '-') && (index == 0 || matches!(previous, 'e' | 'E' | 'p' | 'P'));
+        if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || sign) {
+            break;
+        }
+        len = index + c.len_utf8();
+        previous = c;
+    }
+
+    (Token::Number(rest[..len].to_string()), len)
+}
+
+/// A `"` or `'` quoted literal at the start of `rest`,
+/// with Go escapes replaced, and its length in the source.
+fn quoted(rest: &str, offset: usize) -> Result<(String, usize), Failure> {
+    let quote = rest.chars().next().unwrap_or('"');
+    let kind = if quote == '"' { "string" } else { "character" };
+    let unterminated = || {
+        (
+            TemplateErrorKind::Unterminated(kind),
+            offset..offset + rest.len(),
+        )
+    };
+
+    let mut text = String::new();
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((text, index + 1)),
+            '\\' => {
+                let (_, escaped) = chars.next().ok_or_else(unterminated)?;
+                let hex = |chars: &mut dyn Iterator<Item = (usize, char)>, digits| {
+                    let code: String = chars.take(digits).map(|(_, c)| c).collect();
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(unterminated)
+                };
+                text.push(match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    'a' => '\u{07}',
+                    'b' => '\u{08}',
+                    'f' => '\u{0C}',
+                    'v' => '\u{0B}',
+                    'x' => hex(&mut chars, 2)?,
+                    'u' => hex(&mut chars, 4)?,
+                    'U' => hex(&mut chars, 8)?,
+                    other => other,
+                });
+            }
+            '\n' => return Err(unterminated()),
+            c => text.push(c),
+        }
+    }
+
+    Err(unterminated())
+}
+
+/// What ended a list of nodes.
+enum Terminator {
+    End(Range<usize>),
+    /// `{{ else }}`, with any tokens after `else` like `if .System`.
+    Else(Vec<Lexeme>, Range<usize>),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    items: std::vec::IntoIter<Item>,
+    index: &'a LineIndex<'a>,
+    /// Variables in scope, innermost last.
+    scopes: Vec<Vec<String>>,
+    range_depth: usize,
+    /// `range`, `with`, `define` and `block` bodies the parser is in,
+    /// where `.` is no longer the template values.
+    rebound: usize,
+    /// `define` and `block` bodies the parser is in,
+    /// where `$` is no longer the template values.
+    defines: usize,
+    /// Bodies and parentheses the parser is in,
+    /// counting the template itself like the renderer does.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Nodes up to the next `{{ end }}`, `{{ else }}` or the end of the template.
+    fn list(&mut self) -> Result<(Vec<Node>, Option<Terminator>), Failure> {
+        let mut nodes = Vec::new();
+
+        while let Some(item) = self.items.next() {
+            let (lexemes, range) = match item {
+                Item::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue;
+                }
+                Item::Comment(comment) => {
+                    nodes.push(Node::Comment(comment));
+                    continue;
+                }
+                Item::Action { lexemes, range } => (lexemes, range),
+            };
+
+            let keyword = match lexemes.first() {
+                Some(Lexeme {
+                    token: Token::Ident(keyword),
+                    ..
+                }) => keyword.as_str(),
+                _ => "",
+            };
+            let rest = lexemes.get(1..).unwrap_or_default().to_vec();
+            let span = self.index.span(range.clone());
+
+            nodes.push(match keyword {
+                "end" => {
+                    self.nothing(&rest)?;
+                    return Ok((nodes, Some(Terminator::End(range))));
+                }
+                "else" => return Ok((nodes, Some(Terminator::Else(rest, range)))),
+                "if" => Node::If(self.branch("if", &rest, range)?),
+                "range" => Node::Range(self.branch("range", &rest, range)?),
+                "with" => Node::With(self.branch("with", &rest, range)?),
+                "define" => {
+                    let (name, pipeline) = self.name(&rest, &range)?;
+                    self.nothing(pipeline)?;
+                    let body = self.definition("define", range)?;
+                    Node::Define { name, body, span }
+                }
+                "block" => {
+                    let (name, pipeline) = self.name(&rest, &range)?;
+                    let pipeline = self.pipeline(pipeline, &range)?;
+                    let body = self.definition("block", range)?;
+                    Node::Block {
+                        name,
+                        pipeline,
+                        body,
+                        span,
+                    }
+                }
+                "template" => {
+                    let (name, pipeline) = self.name(&rest, &range)?;
+                    let pipeline = if pipeline.is_empty() {
+                        None
+                    } else {
+                        Some(self.pipeline(pipeline, &range)?)
+                    };
+                    Node::Template {
+                        name,
+                        pipeline,
+                        span,
+                    }
+                }
+                "break" | "continue" => {
+                    self.nothing(&rest)?;
+                    let keyword = if keyword == "break" {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    if self.range_depth == 0 {
+                        return Err((TemplateErrorKind::OutsideRange(keyword), range));
+                    }
+                    if keyword == "break" {
+                        Node::Break(span)
+                    } else {
+                        Node::Continue(span)
+                    }
+                }
+                _ => {
+                    let pipeline = self.pipeline(&lexemes, &range)?;
+                    self.declare(&pipeline);
+                    Node::Action { pipeline, span }
+                }
+            });
+        }
+
+        Ok((nodes, None))
+    }
+
+    /// An `if`, `range` or `with`, up to and including its `{{ end }}`.
+    fn branch(
+        &mut self,
+        keyword: &'static str,
+        lexemes: &[Lexeme],
+        range: Range<usize>,
+    ) -> Result<Branch, Failure> {
+        self.scopes.push(Vec::new());
+        let pipeline = self.pipeline(lexemes, &range)?;
+        self.declare(&pipeline);
+
+        self.nest(&range)?;
+        let is_range = keyword == "range";
+        let rebinds = keyword != "if";
+        self.range_depth += usize::from(is_range);
+        self.rebound += usize::from(rebinds);
+        let (body, terminator) = self.list()?;
+        self.range_depth -= usize::from(is_range);
+        self.rebound -= usize::from(rebinds);
+
+        let otherwise = match terminator {
+            None => return Err((TemplateErrorKind::Unclosed(keyword), range)),
+            Some(Terminator::End(_)) => None,
+            Some(Terminator::Else(rest, _)) if rest.is_empty() => {
+                Some(self.body(keyword, range.clone())?)
+            }
+            Some(Terminator::Else(rest, else_range)) => {
+                let chained = match &rest[0].token {
+                    Token::Ident(chained) if chained == "if" => "if",
+                    Token::Ident(chained) if chained == "with" => "with",
+                    _ => return Err(self.unexpected(&rest[0])),
+                };
+                // the chained branch shares this branch's `{{ end }}`
+                let branch = self.branch(chained, &rest[1..], else_range)?;
+                Some(vec![if chained == "if" {
+                    Node::If(branch)
+                } else {
+                    Node::With(branch)
+                }])
+            }
+        };
+
+        self.depth -= 1;
+        self.scopes.pop();
+        Ok(Branch {
+            pipeline,
+            body,
+            otherwise,
+            span: self.index.span(range),
+        })
+    }
+
+    /// Nodes up to the `{{ end }}` of the `keyword` opened at `range`.
+    fn body(&mut self, keyword: &'static str, range: Range<usize>) -> Result<Vec<Node>, Failure> {
+        match self.list()? {
+            (body, Some(Terminator::End(_))) => Ok(body),
+            (_, Some(Terminator::Else(_, range))) => {
+                Err((TemplateErrorKind::UnexpectedElse, range))
+            }
+            (_, None) => Err((TemplateErrorKind::Unclosed(keyword), range)),
+        }
+    }
+
+    /// The body of a `define` or `block`, with its own `$`.
+    fn definition(
+        &mut self,
+        keyword: &'static str,
+        range: Range<usize>,
+    ) -> Result<Vec<Node>, Failure> {
+        self.nest(&range)?;
+        self.scopes.push(vec!["$".to_string()]);
+        self.rebound += 1;
+        self.defines += 1;
+        let body = self.body(keyword, range);
+        self.scopes.pop();
+        self.rebound -= 1;
+        self.defines -= 1;
+        self.depth -= 1;
+        body
+    }
+
+    /// Go a level deeper into a body or parentheses opened at `range`.
+    fn nest(&mut self, range: &Range<usize>) -> Result<(), Failure> {
+        if self.depth >= MAX_DEPTH {
+            return Err((TemplateErrorKind::TooDeep, range.clone()));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// The quoted name of a `define`, `block` or `template`, and the tokens after it.
+    fn name<'l>(
+        &self,
+        lexemes: &'l [Lexeme],
+        range: &Range<usize>,
+    ) -> Result<(String, &'l [Lexeme]), Failure> {
+        match lexemes.split_first() {
+            Some((
+                Lexeme {
+                    token: Token::String(name),
+                    ..
+                },
+                rest,
+            )) => Ok((name.clone(), rest)),
+            Some((lexeme, _)) => Err(self.unexpected(lexeme)),
+            None => Err((TemplateErrorKind::MissingValue, range.clone())),
+        }
+    }
+
+    fn unexpected(&self, lexeme: &Lexeme) -> Failure {
+        let text = &self.source[lexeme.range.clone()];
+        (
+            TemplateErrorKind::Unexpected(text.to_string()),
+            lexeme.range.clone(),
+        )
+    }
+
+    fn nothing(&self, lexemes: &[Lexeme]) -> Result<(), Failure> {
+        match lexemes.first() {
+            Some(lexeme) => Err(self.unexpected(lexeme)),
+            None => Ok(()),
+        }
+    }
+
+    fn declare(&mut self, pipeline: &Pipeline) {
+        if pipeline.assign {
+            return;
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.extend(pipeline.variables.iter().cloned());
+        }
+    }
+
+    fn is_defined(&self, variable: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.iter().any(|defined| defined == variable))
+    }
+
+    fn pipeline(&mut self, lexemes: &[Lexeme], range: &Range<usize>) -> Result<Pipeline, Failure> {
+        let (variables, assign, lexemes) = declarations(lexemes);
+        for variable in &variables {
+            if assign && !self.is_defined(variable) {
+                return Err((
+                    TemplateErrorKind::UndefinedVariable(variable.clone()),
+                    range.clone(),
+                ));
+            }
+        }
+
+        let mut commands = Vec::new();
+        for command in split_top_level(lexemes, |token| *token == Token::Pipe) {
+            if command.is_empty() {
+                return Err((TemplateErrorKind::MissingValue, range.clone()));
+            }
+            commands.push(self.command(command)?);
+        }
+        if commands.is_empty() {
+            return Err((TemplateErrorKind::MissingValue, range.clone()));
+        }
+
+        Ok(Pipeline {
+            variables,
+            assign,
+            commands,
+        })
+    }
+
+    fn command(&mut self, lexemes: &[Lexeme]) -> Result<Command, Failure> {
+        let mut args = Vec::new();
+        let mut rest = lexemes;
+
+        while let Some((lexeme, after)) = rest.split_first() {
+            rest = after;
+            let operand = match &lexeme.token {
+                Token::Dot => Operand::Dot,
+                Token::Field(field) => {
+                    if self.rebound == 0 {
+                        self.check_field(field, &lexeme.range)?;
+                    }
+                    let (mut fields, after) = self.fields(lexeme.range.end, rest, false)?;
+                    rest = after;
+                    fields.insert(0, field.clone());
+                    Operand::Field(fields)
+                }
+                Token::Variable(name) => {
+                    if !self.is_defined(name) {
+                        return Err((
+                            TemplateErrorKind::UndefinedVariable(name.clone()),
+                            lexeme.range.clone(),
+                        ));
+                    }
+                    let root = name == "$" && self.defines == 0;
+                    let (fields, after) = self.fields(lexeme.range.end, rest, root)?;
+                    rest = after;
+                    Operand::Variable {
+                        name: name.clone(),
+                        fields,
+                    }
+                }
+                Token::Ident(name) => match name.as_str() {
+                    "true" => Operand::Bool(true),
+                    "false" => Operand::Bool(false),
+                    "nil" => Operand::Nil,
+                    name if FUNCTIONS.contains(&name) => Operand::Function(name.to_string()),
+                    name => {
+                        return Err((
+                            TemplateErrorKind::UnknownFunction(name.to_string()),
+                            lexeme.range.clone(),
+                        ))
+                    }
+                },
+                Token::String(text) => Operand::String(text.clone()),
+                Token::Number(number) => {
+                    if !is_number(number) {
+                        return Err(self.unexpected(lexeme));
+                    }
+                    Operand::Number(number.clone())
+                }
+                Token::Char(c) => Operand::Char(*c),
+                Token::LeftParen => {
+                    let close = matching_paren(rest).ok_or_else(|| self.unexpected(lexeme))?;
+                    let inner = &rest[..close];
+                    let end = rest[close].range.end;
+                    let range = lexeme.range.start..end;
+                    self.nest(&range)?;
+                    let pipeline = self.pipeline(inner, &range)?;
+                    self.depth -= 1;
+                    let (fields, after) = self.fields(end, &rest[close + 1..], false)?;
+                    rest = after;
+                    Operand::Pipeline {
+                        pipeline: Box::new(pipeline),
+                        fields,
+                    }
+                }
+                Token::RightParen | Token::Pipe | Token::Comma | Token::Declare | Token::Assign => {
+                    return Err(self.unexpected(lexeme))
+                }
+            };
+            args.push(operand);
+        }
+
+        Ok(Command { args })
+    }
+
+    /// The `.Field`s directly after the offset `end`, with no space between,
+    /// checking the first if they are read from the template values.
+    fn fields<'l>(
+        &self,
+        mut end: usize,
+        mut lexemes: &'l [Lexeme],
+        root: bool,
+    ) -> Result<(Vec<String>, &'l [Lexeme]), Failure> {
+        let mut fields = Vec::new();
+
+        while let Some((
+            Lexeme {
+                token: Token::Field(field),
+                range,
+            },
+            rest,
+        )) = lexemes.split_first()
+        {
+            if range.start != end {
+                break;
+            }
+            if root && fields.is_empty() {
+                self.check_field(field, range)?;
+            }
+            fields.push(field.clone());
+            end = range.end;
+            lexemes = rest;
+        }
+
+        Ok((fields, lexemes))
+    }
+
+    fn check_field(&self, field: &str, range: &Range<usize>) -> Result<(), Failure> {
+        let exported = field.starts_with(|c: char| c.is_uppercase());
+        if exported && !FIELDS.contains(&field) {
+            return Err((
+                TemplateErrorKind::UnknownField(field.to_string()),
+                range.clone(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Split off `$a, $b :=` or `$a =` from the start of a pipeline.
+fn declarations(lexemes: &[Lexeme]) -> (Vec<String>, bool, &[Lexeme]) {
+    let mut variables = Vec::new();
+
+    for (index, lexeme) in lexemes.iter().enumerate() {
+        match (&lexeme.token, index % 2) {
+            (Token::Variable(name), 0) => variables.push(name.clone()),
+            (Token::Comma, 1) => {}
+            (Token::Declare | Token::Assign, 1) => {
+                let assign = lexeme.token == Token::Assign;
+                return (variables, assign, &lexemes[index + 1..]);
+            }
+            _ => break,
+        }
+    }
+
+    (Vec::new(), false, lexemes)
+}
+
+/// Split on tokens outside of parentheses.
+fn split_top_level(lexemes: &[Lexeme], is_separator: impl Fn(&Token) -> bool) -> Vec<&[Lexeme]> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (index, lexeme) in lexemes.iter().enumerate() {
+        match &lexeme.token {
+            Token::LeftParen => depth += 1,
+            Token::RightParen => depth = depth.saturating_sub(1),
+            token if depth == 0 && is_separator(token) => {
+                parts.push(&lexemes[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < lexemes.len() || !parts.is_empty() {
+        parts.push(&lexemes[start..]);
+    }
+
+    parts
+}
+
+/// The index of the `)` closing an already opened `(`.
+fn matching_paren(lexemes: &[Lexeme]) -> Option<usize> {
+    let mut depth = 0usize;
+
+    for (index, lexeme) in lexemes.iter().enumerate() {
+        match lexeme.token {
+            Token::LeftParen => depth += 1,
+            Token::RightParen if depth == 0 => return Some(index),
+            Token::RightParen => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn is_number(number: &str) -> bool {
+    let unsigned = number.trim_start_matches(['+', '-']).replace('_', "");
+    let hex = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"));
+
+    match hex {
+        Some(hex) => u64::from_str_radix(hex, 16).is_ok(),
+        None => unsigned.parse::<f64>().is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    use crate::modelfile::{
+        test_data::{load_modelfiles, TestData, TEST_GOOD_DATA_DIR},
+        Modelfile,
+    };
+
+    use super::*;
+
+    fn error(template: &str) -> String {
+        template
+            .parse::<TemplateAst>()
+            .expect_err("template should not parse")
+            .to_string()
+    }
+
+    #[test]
+    fn fixture_templates_parse() {
+        let mut templates = 0;
+
+        for TestData { path, contents } in load_modelfiles(TEST_GOOD_DATA_DIR) {
+            let modelfile: Modelfile = contents.parse().expect("fixture should parse");
+            let Some(template) = &modelfile.template else {
+                continue;
+            };
+            templates += 1;
+
+            if let Err(error) = template.parse() {
+                panic!("template in {} should parse:\n{error}", path.display());
+            }
+        }
+
+        assert!(templates > 0);
+    }
+
+    #[test]
+    fn ast_snapshot() {
+        let ast: TemplateAst = "{{- range $i, $_ := .Messages }}\n\
+            {{- if eq .Role \"user\" }}[INST] {{ .Content }}{{ else }}{{ .Content | print }}{{ end -}}\n\
+            {{ end }}"
+            .parse()
+            .expect("template should parse");
+
+        assert_debug_snapshot!(ast, @r#"
+        TemplateAst {
+            nodes: [
+                Range(
+                    Branch {
+                        pipeline: Pipeline {
+                            variables: [
+                                "$i",
+                                "$_",
+                            ],
+                            assign: false,
+                            commands: [
+                                Command {
+                                    args: [
+                                        Field(
+                                            [
+                                                "Messages",
+                                            ],
+                                        ),
+                                    ],
+                                },
+                            ],
+                        },
+                        body: [
+                            If(
+                                Branch {
+                                    pipeline: Pipeline {
+                                        variables: [],
+                                        assign: false,
+                                        commands: [
+                                            Command {
+                                                args: [
+                                                    Function(
+                                                        "eq",
+                                                    ),
+                                                    Field(
+                                                        [
+                                                            "Role",
+                                                        ],
+                                                    ),
+                                                    String(
+                                                        "user",
+                                                    ),
+                                                ],
+                                            },
+                                        ],
+                                    },
+                                    body: [
+                                        Text(
+                                            "[INST] ",
+                                        ),
+                                        Action {
+                                            pipeline: Pipeline {
+                                                variables: [],
+                                                assign: false,
+                                                commands: [
+                                                    Command {
+                                                        args: [
+                                                            Field(
+                                                                [
+                                                                    "Content",
+                                                                ],
+                                                            ),
+                                                        ],
+                                                    },
+                                                ],
+                                            },
+                                            span: Span {
+                                                range: 65..79,
+                                                start: Position {
+                                                    line: 2,
+                                                    column: 33,
+                                                },
+                                                end: Position {
+                                                    line: 2,
+                                                    column: 47,
+                                                },
+                                            },
+                                        },
+                                    ],
+                                    otherwise: Some(
+                                        [
+                                            Action {
+                                                pipeline: Pipeline {
+                                                    variables: [],
+                                                    assign: false,
+                                                    commands: [
+                                                        Command {
+                                                            args: [
+                                                                Field(
+                                                                    [
+                                                                        "Content",
+                                                                    ],
+                                                                ),
+                                                            ],
+                                                        },
+                                                        Command {
+                                                            args: [
+                                                                Function(
+                                                                    "print",
+                                                                ),
+                                                            ],
+                                                        },
+                                                    ],
+                                                },
+                                                span: Span {
+                                                    range: 89..111,
+                                                    start: Position {
+                                                        line: 2,
+                                                        column: 57,
+                                                    },
+                                                    end: Position {
+                                                        line: 2,
+                                                        column: 79,
+                                                    },
+                                                },
+                                            },
+                                        ],
+                                    ),
+                                    span: Span {
+                                        range: 33..58,
+                                        start: Position {
+                                            line: 2,
+                                            column: 1,
+                                        },
+                                        end: Position {
+                                            line: 2,
+                                            column: 26,
+                                        },
+                                    },
+                                },
+                            ),
+                        ],
+                        otherwise: None,
+                        span: Span {
+                            range: 0..32,
+                            start: Position {
+                                line: 1,
+                                column: 1,
+                            },
+                            end: Position {
+                                line: 1,
+                                column: 33,
+                            },
+                        },
+                    },
+                ),
+            ],
+        }
+        "#);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_snapshot!(error("{{ .Prompt }}\n{{ end }}"), @r"
+        error: `{{ end }}` without an open `if`, `range`, `with`, `define` or `block`
+         --> 2:1
+          |
+        2 | {{ end }}
+          | ^
+        ");
+        assert_snapshot!(error("{{ if .System }}\n{{ .System }}"), @r"
+        error: `{{ if }}` is never closed with `{{ end }}`
+         --> 1:1
+          |
+        1 | {{ if .System }}
+          | ^
+        ");
+        assert_snapshot!(error("{{ range .Messages }}{{ .Content }}{{ end }}{{ .Prompts }}"), @r"
+        error: unknown field .Prompts
+         --> 1:48
+          |
+        1 | {{ range .Messages }}{{ .Content }}{{ end }}{{ .Prompts }}
+          |                                                ^
+        ");
+        assert_snapshot!(error("{{ range .Messages }}{{ $last }}{{ end }}"), @r"
+        error: undefined variable $last
+         --> 1:25
+          |
+        1 | {{ range .Messages }}{{ $last }}{{ end }}
+          |                         ^
+        ");
+        assert_snapshot!(error("[INST] {{ .Prompt [/INST]"), @r"
+        error: unclosed action, expected `}}`
+         --> 1:8
+          |
+        1 | [INST] {{ .Prompt [/INST]
+          |        ^
+        ");
+    }
+
+    #[test]
+    fn nesting_stops_at_the_depth_limit() {
+        let nested = |depth: usize| "{{ if 1 }}\n".repeat(depth) + &"{{ end }}".repeat(depth);
+        let ast: TemplateAst = nested(MAX_DEPTH - 1)
+            .parse()
+            .expect("template within the limit should parse");
+        assert_eq!(
+            ast.execute(&serde_json::json!({}))
+                .expect("template should render"),
+            "\n".repeat(MAX_DEPTH - 1)
+        );
+
+        assert_snapshot!(error(&nested(MAX_DEPTH)), @r"
+        error: exceeded maximum template depth of 200
+           --> 200:1
+            |
+        200 | {{ if 1 }}
+            | ^
+        ");
+        assert!(nested(20_000).parse::<TemplateAst>().is_err());
+
+        let parentheses = format!("{{{{ {}1{} }}}}", "(".repeat(20_000), ")".repeat(20_000));
+        assert_snapshot!(
+            parentheses.parse::<TemplateAst>().expect_err("parentheses should be too deep").kind,
+            @"exceeded maximum template depth of 200"
+        );
+    }
+
+    #[test]
+    fn only_fields_of_the_template_values_are_checked() {
+        for template in [
+            "{{ range $m := .Messages }}{{ $m.Custom }}{{ end }}",
+            "{{ range .Messages }}{{ .Custom.Name }}{{ end }}",
+            "{{ with .Tools }}{{ .Custom }}{{ else }}{{ .System }}{{ end }}",
+            "{{ define \"x\" }}{{ .Custom }}{{ $.Custom }}{{ end }}",
+            "{{ block \"x\" .Messages }}{{ .Custom }}{{ end }}",
+            "{{ (index .Messages 0).Custom }}",
+            "{{ .Messages.Custom }}",
+        ] {
+            assert!(template.parse::<TemplateAst>().is_ok(), "{template}");
+        }
+
+        for template in [
+            "{{ .Custom }}",
+            "{{ range .Messages }}{{ $.Custom }}{{ end }}",
+            "{{ with .Custom }}{{ end }}",
+            "{{ with .Tools }}{{ else }}{{ .Custom }}{{ end }}",
+            "{{ block \"x\" .Custom }}{{ end }}",
+        ] {
+            assert!(template.parse::<TemplateAst>().is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn variables_are_scoped() {
+        assert!("{{ $x := 1 }}{{ $x = 2 }}{{ $x }}"
+            .parse::<TemplateAst>()
+            .is_ok());
+        assert!("{{ if true }}{{ $x := 1 }}{{ end }}{{ $x }}"
+            .parse::<TemplateAst>()
+            .is_err());
+        assert!(
+            "{{ range $i, $m := .Messages }}{{ $.System }}{{ end }}{{ $m }}"
+                .parse::<TemplateAst>()
+                .is_err()
+        );
+        assert!("{{ break }}".parse::<TemplateAst>().is_err());
+    }
+}
@@ -59,6 +59,24 @@ pub fn load_modelfiles(test_dir: impl AsRef<Path>) -> Vec<TestData> {
         .collect()
 }
 
+/// Load the `/api/show` responses in `test_dir`.
+pub fn load_show_responses(test_dir: impl AsRef<Path>) -> Vec<TestData> {
+    test_dir
+        .as_ref()
+        .read_dir()
+        .expect("could not open test data dir")
+        .map(|file| file.expect("error reading dir entry").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .map(|path| TestData {
+            contents: std::fs::read_to_string(&path).expect("could not read file contents"),
+            path,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
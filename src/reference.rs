@@ -0,0 +1,226 @@
+//! References to models in an [Ollama] registry,
+//! like `llama3.2`, `library/llama3.2:latest`
+//! or `registry.example.com/team/model:tag`.
+//!
+//! [Ollama]: https://ollama.com/
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The registry used when a reference doesn't name one.
+pub const DEFAULT_REGISTRY: &str = "registry.ollama.ai";
+/// The namespace used when a reference doesn't name one.
+pub const DEFAULT_NAMESPACE: &str = "library";
+/// The tag used when a reference doesn't name one.
+pub const DEFAULT_TAG: &str = "latest";
+
+/// A fully qualified reference to a model in a registry.
+///
+/// Parts left out of the parsed text are filled in with
+/// [`DEFAULT_REGISTRY`], [`DEFAULT_NAMESPACE`] and [`DEFAULT_TAG`],
+/// so `llama3.2` and `registry.ollama.ai/library/llama3.2:latest`
+/// are the same reference.
+/// The reference is displayed fully qualified.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModelReference {
+    registry: String,
+    namespace: String,
+    name: String,
+    tag: String,
+}
+
+impl ModelReference {
+    /// The host of the registry, like `registry.ollama.ai`.
+    /// May include a port.
+    pub fn registry(&self) -> &str {
+        &self.registry
+    }
+
+    /// The namespace in the registry, like `library`.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The name of the model, like `llama3.2`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The tag of the model, like `latest` or `3b-instruct-q4_K_M`.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+}
+
+/// Why a string is not a valid [`ModelReference`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ReferenceError {
+    #[error("model reference is empty")]
+    Empty,
+    #[error("model reference has more than three `/` separated parts: {0}")]
+    TooManyParts(String),
+    #[error("invalid {part} in model reference {reference}")]
+    InvalidPart {
+        part: &'static str,
+        reference: String,
+    },
+}
+
+fn is_valid_part(part: &str) -> bool {
+    !part.is_empty()
+        && part
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+impl FromStr for ModelReference {
+    type Err = ReferenceError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.is_empty() {
+            return Err(ReferenceError::Empty);
+        }
+
+        let invalid = |part| ReferenceError::InvalidPart {
+            part,
+            reference: input.to_string(),
+        };
+
+        // the registry may have a port, so the tag is after a `:` in the last part
+        let (path, tag) = match input.rsplit_once(':') {
+            Some((path, tag)) if !tag.contains('/') => (path, tag),
+            _ => (input, DEFAULT_TAG),
+        };
+
+        let mut parts = path.rsplit('/');
+        let name = parts.next().unwrap_or_default();
+        let namespace = parts.next().unwrap_or(DEFAULT_NAMESPACE);
+        let registry = parts.next().unwrap_or(DEFAULT_REGISTRY);
+
+        if parts.next().is_some() {
+            return Err(ReferenceError::TooManyParts(input.to_string()));
+        }
+        if !is_valid_part(name) {
+            return Err(invalid("name"));
+        }
+        if !is_valid_part(tag) {
+            return Err(invalid("tag"));
+        }
+        if !is_valid_part(namespace) {
+            return Err(invalid("namespace"));
+        }
+        // `host:port`
+        if !registry.split(':').all(is_valid_part) || registry.matches(':').count() > 1 {
+            return Err(invalid("registry"));
+        }
+
+        Ok(ModelReference {
+            registry: registry.to_string(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for ModelReference {
+    type Error = ReferenceError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ModelReference> for String {
+    fn from(value: ModelReference) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for ModelReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ModelReference {
+            registry,
+            namespace,
+            name,
+            tag,
+        } = self;
+        write!(f, "{registry}/{namespace}/{name}:{tag}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+
+    #[test]
+    fn references_are_normalized() {
+        let references: Vec<Result<String, ReferenceError>> = [
+            "llama3.2",
+            "llama3.1:latest",
+            "library/llama3.2:3b",
+            "registry.ollama.ai/library/llama3.2:latest",
+            "registry.example.com/team/model:tag",
+            "localhost:5000/team/model",
+            "",
+            "a/b/c/d",
+            "llama3.2:",
+            "my model",
+        ]
+        .into_iter()
+        .map(|reference| {
+            reference
+                .parse()
+                .map(|reference: ModelReference| reference.to_string())
+        })
+        .collect();
+
+        assert_debug_snapshot!(references, @r#"
+        [
+            Ok(
+                "registry.ollama.ai/library/llama3.2:latest",
+            ),
+            Ok(
+                "registry.ollama.ai/library/llama3.1:latest",
+            ),
+            Ok(
+                "registry.ollama.ai/library/llama3.2:3b",
+            ),
+            Ok(
+                "registry.ollama.ai/library/llama3.2:latest",
+            ),
+            Ok(
+                "registry.example.com/team/model:tag",
+            ),
+            Ok(
+                "localhost:5000/team/model:latest",
+            ),
+            Err(
+                Empty,
+            ),
+            Err(
+                TooManyParts(
+                    "a/b/c/d",
+                ),
+            ),
+            Err(
+                InvalidPart {
+                    part: "tag",
+                    reference: "llama3.2:",
+                },
+            ),
+            Err(
+                InvalidPart {
+                    part: "name",
+                    reference: "my model",
+                },
+            ),
+        ]
+        "#);
+    }
+}
@@ -0,0 +1,454 @@
+//! Reading the models [Ollama] keeps on disk,
+//! without a running server.
+//!
+//! Ollama stores models under `$OLLAMA_MODELS`, `~/.ollama/models` by default:
+//!
+//! ```text
+//! manifests/<registry>/<namespace>/<name>/<tag>
+//! blobs/sha256-<hex>
+//! ```
+//!
+//! A manifest lists the layers of a model by digest,
+//! and each layer's content is in the blob file named after its digest.
+//!
+//! [Ollama]: https://ollama.com/
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+use thiserror::Error;
+
+use crate::{
+    api::create::{messages_from_chat, parameters_from_json},
+    digest::Digest,
+    message::ChatMessage,
+    modelfile::{
+        base_model::{BaseModel, ModelSource},
+        builder::ModelfileBuilder,
+        error::ModelfileError,
+        home_dir, Modelfile, TensorFile,
+    },
+    reference::{ModelReference, ReferenceError},
+};
+
+/// The environment variable Ollama reads the store location from.
+pub const MODELS_ENV: &str = "OLLAMA_MODELS";
+
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.ollama.image.";
+
+/// Errors reading the model store.
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("couldn't read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("couldn't parse {path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("invalid model name in the store: {0}")]
+    Reference(#[from] ReferenceError),
+    #[error("manifest has no model layer")]
+    NoModel,
+    #[error(transparent)]
+    Modelfile(#[from] ModelfileError),
+}
+
+/// An Ollama model store on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Store {
+    root: PathBuf,
+}
+
+/// A model manifest, listing the layers of a model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub media_type: String,
+    pub config: Layer,
+    pub layers: Vec<Layer>,
+}
+
+/// A single layer of a model, stored as a blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layer {
+    /// Like `application/vnd.ollama.image.model`.
+    pub media_type: String,
+    pub digest: Digest,
+    /// The size of the blob in bytes.
+    pub size: u64,
+}
+
+/// What an Ollama layer holds, from its media type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum LayerKind {
+    /// The model weights.
+    Model,
+    /// The weights of a multimodal projector.
+    Projector,
+    /// The weights of a LoRA adapter.
+    Adapter,
+    /// The `TEMPLATE` text.
+    Template,
+    /// The `SYSTEM` text.
+    System,
+    /// The `PARAMETER`s, as a JSON object.
+    Params,
+    /// The `MESSAGE`s, as a JSON array.
+    Messages,
+    /// The `LICENSE` text.
+    License,
+}
+
+impl Layer {
+    /// What the layer holds,
+    /// or `None` if it isn't an Ollama layer.
+    pub fn kind(&self) -> Option<LayerKind> {
+        self.media_type
+            .strip_prefix(MEDIA_TYPE_PREFIX)?
+            .parse()
+            .ok()
+    }
+}
+
+impl Manifest {
+    /// The layers of a kind, in order.
+    pub fn layers_of(&self, kind: LayerKind) -> impl Iterator<Item = &Layer> {
+        self.layers
+            .iter()
+            .filter(move |layer| layer.kind() == Some(kind))
+    }
+}
+
+impl Store {
+    /// The store at `root`, the directory containing `manifests` and `blobs`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Store { root: root.into() }
+    }
+
+    /// The store Ollama uses,
+    /// `$OLLAMA_MODELS` or `~/.ollama/models`.
+    ///
+    /// `None` if neither the variable nor a home directory is set.
+    pub fn from_env() -> Option<Self> {
+        if let Some(root) = std::env::var_os(MODELS_ENV) {
+            return Some(Store::new(root));
+        }
+
+        home_dir().map(|home| Store::new(home.join(".ollama").join("models")))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The file the blob with `digest` is stored in.
+    pub fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.root.join("blobs").join(digest.blob_file_name())
+    }
+
+    /// The file the manifest of `model` is stored in.
+    pub fn manifest_path(&self, model: &ModelReference) -> PathBuf {
+        self.root
+            .join("manifests")
+            .join(model.registry())
+            .join(model.namespace())
+            .join(model.name())
+            .join(model.tag())
+    }
+
+    /// The manifest of an installed model.
+    pub fn manifest(&self, model: &ModelReference) -> Result<Manifest, StoreError> {
+        read_json(&self.manifest_path(model))
+    }
+
+    /// Every installed model, sorted.
+    pub fn models(&self) -> Result<Vec<ModelReference>, StoreError> {
+        let mut models = Vec::new();
+
+        for registry in sub_directories(&self.root.join("manifests"))? {
+            for namespace in sub_directories(&registry)? {
+                for name in sub_directories(&namespace)? {
+                    for tag in entries(&name)? {
+                        let parts = [&registry, &namespace, &name, &tag]
+                            .map(|path| path.file_name().unwrap_or_default().to_string_lossy());
+                        let [registry, namespace, name, tag] = parts;
+                        models.push(format!("{registry}/{namespace}/{name}:{tag}").parse()?);
+                    }
+                }
+            }
+        }
+
+        models.sort();
+        Ok(models)
+    }
+
+    /// Rebuild the [`Modelfile`] of an installed model from its layers.
+    ///
+    /// Like `ollama show --modelfile`,
+    /// `FROM` is the path to the model blob,
+    /// followed by a `FROM` for each projector blob.
+    /// `ADAPTER`s are written as `@sha256:<hex>` blob references.
+    pub fn modelfile(&self, model: &ModelReference) -> Result<Modelfile, StoreError> {
+        let manifest = self.manifest(model)?;
+        let from = manifest
+            .layers_of(LayerKind::Model)
+            .next()
+            .ok_or(StoreError::NoModel)?;
+
+        let mut builder = ModelfileBuilder::default().base_model(self.blob_path(&from.digest));
+
+        for layer in &manifest.layers {
+            let path = self.blob_path(&layer.digest);
+            builder = match layer.kind() {
+                Some(LayerKind::Template) => builder.template(read_text(&path)?.into())?,
+                Some(LayerKind::System) => builder.system(read_text(&path)?)?,
+                Some(LayerKind::License) => builder.license(read_text(&path)?),
+                Some(LayerKind::Adapter) => builder.adapter(TensorFile::Blob(layer.digest.clone())),
+                Some(LayerKind::Params) => {
+                    let params: BTreeMap<String, serde_json::Value> = read_json(&path)?;
+                    parameters_from_json(&params)?
+                        .into_iter()
+                        .fold(builder, ModelfileBuilder::parameter)
+                }
+                Some(LayerKind::Messages) => {
+                    let messages: Vec<ChatMessage> = read_json(&path)?;
+                    messages_from_chat(messages)?
+                        .into_iter()
+                        .fold(builder, ModelfileBuilder::message)
+                }
+                Some(LayerKind::Projector) => builder.base_model(path),
+                Some(LayerKind::Model) | None => builder,
+            };
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// The installed model a `FROM` value refers to.
+    ///
+    /// A model name is returned if the model is installed.
+    /// A blob, like `FROM /usr/share/ollama/.ollama/models/blobs/sha256-…`,
+    /// is mapped back to the first model, by name, built from it.
+    pub fn model_name(&self, from: &BaseModel) -> Result<Option<ModelReference>, StoreError> {
+        let digest = match from.source() {
+            ModelSource::Reference(reference) => {
+                let installed = self.models()?.contains(reference);
+                return Ok(installed.then(|| reference.clone()));
+            }
+            ModelSource::Blob(digest) => digest,
+            ModelSource::Gguf(_) | ModelSource::Safetensor(_) | ModelSource::Directory(_) => {
+                return Ok(None)
+            }
+        };
+
+        for model in self.models()? {
+            let manifest = self.manifest(&model)?;
+            if manifest
+                .layers_of(LayerKind::Model)
+                .any(|layer| &layer.digest == digest)
+            {
+                return Ok(Some(model));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn read_text(path: &Path) -> Result<String, StoreError> {
+    std::fs::read_to_string(path).map_err(|source| StoreError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, StoreError> {
+    serde_json::from_str(&read_text(path)?).map_err(|source| StoreError::Json {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// The entries of a directory, or none if it doesn't exist.
+fn entries(path: &Path) -> Result<Vec<PathBuf>, StoreError> {
+    let io_error = |source| StoreError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()).map_err(io_error))
+            .collect(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(io_error(error)),
+    }
+}
+
+fn sub_directories(path: &Path) -> Result<Vec<PathBuf>, StoreError> {
+    Ok(entries(path)?
+        .into_iter()
+        .filter(|entry| entry.is_dir())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Write a blob to the store and return its layer.
+    fn blob(store: &Store, kind: &str, contents: &str) -> serde_json::Value {
+        let digest = Digest::from_reader(contents.as_bytes()).expect("should hash contents");
+        let path = store.blob_path(&digest);
+        std::fs::create_dir_all(path.parent().expect("blob should be in a directory"))
+            .expect("should create blobs directory");
+        std::fs::write(path, contents).expect("should write blob");
+
+        json!({
+            "mediaType": format!("{MEDIA_TYPE_PREFIX}{kind}"),
+            "digest": digest,
+            "size": contents.len(),
+        })
+    }
+
+    fn install(store: &Store, model: &str, layers: Vec<serde_json::Value>) {
+        let model: ModelReference = model.parse().expect("should parse model name");
+        let config = blob(store, "config", "{}");
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": config,
+            "layers": layers,
+        });
+
+        let path = store.manifest_path(&model);
+        std::fs::create_dir_all(path.parent().expect("manifest should be in a directory"))
+            .expect("should create manifests directory");
+        std::fs::write(path, manifest.to_string()).expect("should write manifest");
+    }
+
+    #[test]
+    fn modelfiles_are_rebuilt_from_the_store() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let store = Store::new(dir.path());
+
+        let model = blob(&store, "model", "weights");
+        let layers = vec![
+            model.clone(),
+            blob(&store, "projector", "vision"),
+            blob(&store, "template", "{{ .Prompt }}"),
+            blob(&store, "system", "You are a pirate."),
+            blob(
+                &store,
+                "params",
+                r#"{"stop": ["<|eot_id|>", "<|eom_id|>"], "temperature": 0.7}"#,
+            ),
+            blob(
+                &store,
+                "messages",
+                r#"[{"role": "user", "content": "Ahoy?"}]"#,
+            ),
+            blob(&store, "license", "MIT"),
+        ];
+        install(&store, "pirate", layers);
+        install(&store, "registry.example.com/team/base:v1", vec![model]);
+
+        let pirate: ModelReference = "pirate".parse().expect("should parse model name");
+        let modelfile = store.modelfile(&pirate).expect("should rebuild Modelfile");
+        let rendered = modelfile
+            .render()
+            .replace(&dir.path().display().to_string(), "$OLLAMA_MODELS");
+
+        assert_snapshot!(rendered, @r#"
+        # This file was generated by the Ollama-CLI client
+        FROM $OLLAMA_MODELS/blobs/sha256-9a129038d9a00aed0cf6a7ea059ca50a813449061ab87848cf1a13eafdf33b2c
+        FROM $OLLAMA_MODELS/blobs/sha256-5944ae849448011ca08c3785f1de1a54c8a96d6c23f787f9d962b624edd4151d
+
+        SYSTEM """You are a pirate."""
+
+        TEMPLATE """{{ .Prompt }}"""
+
+        PARAMETER stop <|eot_id|>
+        PARAMETER stop <|eom_id|>
+        PARAMETER temperature 0.7
+
+        MESSAGE user Ahoy?
+
+        LICENSE """MIT"""
+        "#);
+
+        let models = store.models().expect("should list models");
+        assert_eq!(
+            models.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "registry.example.com/team/base:v1",
+                "registry.ollama.ai/library/pirate:latest"
+            ]
+        );
+
+        let name = store
+            .model_name(&modelfile.from)
+            .expect("should search the store");
+        assert_eq!(name.as_ref(), models.first());
+
+        for (from, installed) in [("pirate", true), ("pirate:v2", false), ("llama3", false)] {
+            let from: BaseModel = from.parse().expect("should parse FROM");
+            let name = store.model_name(&from).expect("should search the store");
+            assert_eq!(name.is_some(), installed, "{from}");
+        }
+    }
+
+    #[test]
+    fn relative_roots_are_paths() {
+        let dir = tempfile::Builder::new()
+            .prefix("models")
+            .tempdir_in(".")
+            .expect("should create temp dir");
+        let root = dir.path().file_name().expect("temp dir should have a name");
+        let store = Store::new(root);
+
+        let model = blob(&store, "model", "weights");
+        install(&store, "relative", vec![model.clone()]);
+
+        let relative: ModelReference = "relative".parse().expect("should parse model name");
+        let modelfile = store
+            .modelfile(&relative)
+            .expect("should rebuild Modelfile");
+        let digest: Digest =
+            serde_json::from_value(model["digest"].clone()).expect("layer should have a digest");
+
+        assert_eq!(modelfile.from.source(), &ModelSource::Blob(digest));
+        assert_eq!(
+            store
+                .model_name(&modelfile.from)
+                .expect("should search the store"),
+            Some(relative)
+        );
+    }
+
+    #[test]
+    fn missing_models_are_errors() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let store = Store::new(dir.path());
+        let model: ModelReference = "missing".parse().expect("should parse model name");
+
+        assert!(store.models().expect("should list no models").is_empty());
+        assert!(matches!(
+            store.modelfile(&model),
+            Err(StoreError::Io { .. })
+        ));
+    }
+}